@@ -30,6 +30,16 @@ fn build_lib() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+fn git_sha() -> String {
+    Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".into())
+}
+
 // fn build_components() -> Result<(), Box<dyn Error>> {
 //     Command::new("sh")
 //         .arg("-c")
@@ -46,6 +56,9 @@ fn main() {
         exit(1);
     }
 
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rustc-env=CRABOT_GIT_SHA={}", git_sha());
+
     println!("cargo:rerun-if-changed=assets/tailwind.css");
     println!("cargo:rerun-if-changed=templates/*.html");
     println!("cargo:rerun-if-changed=templates/pages/*.html");
@@ -4,6 +4,9 @@
 
 import { SSE } from 'sse.js'
 
+// Reconnection attempts to a dropped stream, with exponential backoff.
+var MAX_RETRIES = 8
+
 var VERBS = ['get', 'post', 'put', 'delete', 'patch']
 var VERB_SELECTOR = VERBS.map(function (verb) {
   return '[hx-sse-' + verb + '], [data-hx-sse-' + verb + ']'
@@ -37,11 +40,13 @@ htmx.defineExtension('trigger-sse', {
         var internalData = api.getInternalData(evt.target)
         // Try to remove remove an EventSource when elements are removed
         if (internalData.sseEventSource) {
+          internalData.sseEnded = true
           internalData.sseEventSource.close()
         }
         return
 
       case 'htmx:afterProcessNode':
+        followEventSource(evt.target)
         return
 
       case 'htmx:trigger':
@@ -69,9 +74,19 @@ function createEventSource(url, options = {}) {
 }
 
 function triggerSSE(elt) {
-  // Set internalData and source
+  listen(elt, api.getInternalData(elt).sseEventSource)
+}
+
+/**
+ * listen swaps the events of a source into the page and starts it. When the
+ * connection drops before the `end` event, the stream is resumed from the URL
+ * announced by the server in its `generation` event.
+ *
+ * @param {HTMLElement} elt
+ * @param {SSE} source
+ */
+function listen(elt, source) {
   var internalData = api.getInternalData(elt)
-  var source = internalData.sseEventSource
 
   var sseEventNames = api
     .getAttributeValue(elt, 'hx-sse-events')
@@ -80,6 +95,7 @@ function triggerSSE(elt) {
 
   sseEventNames.forEach((eventName) => {
     source.addEventListener(eventName, (event) => {
+      trackEvent(internalData, event)
       var settleInfo = api.makeSettleInfo(elt)
 
       // api.oobSwap('true', eventElt, settleInfo)
@@ -106,18 +122,97 @@ function triggerSSE(elt) {
     })
   })
 
+  source.addEventListener('generation', (event) => {
+    trackEvent(internalData, event)
+    internalData.sseResumeURL = event.data
+  })
+  source.addEventListener('end', (event) => {
+    trackEvent(internalData, event)
+    // Followed streams carry one answer after the other.
+    if (!internalData.sseFollowing) {
+      internalData.sseEnded = true
+    }
+  })
+
+  source.addEventListener('readystatechange', (event) => {
+    if (event.readyState !== SSE.CLOSED || internalData.sseEnded) {
+      return
+    }
+    if (!internalData.sseResumeURL || internalData.sseRetryCount >= MAX_RETRIES) {
+      api.triggerErrorEvent(elt, 'htmx:sseError', { source: source })
+      return
+    }
+
+    var retryCount = internalData.sseRetryCount++
+    window.setTimeout(function () {
+      resume(elt)
+    }, Math.min(30000, 2 ** retryCount * 500))
+  })
+
   source.stream()
 }
 
+/**
+ * trackEvent remembers the last event received, to resume after it.
+ */
+function trackEvent(internalData, event) {
+  if (event.id != null && event.id !== '') {
+    internalData.sseLastEventId = event.id
+  }
+  internalData.sseRetryCount = 0
+}
+
+/**
+ * resume reconnects to a generation, the server replays the events following
+ * `Last-Event-ID` and then streams the new ones.
+ *
+ * @param {HTMLElement} elt
+ */
+function resume(elt) {
+  var internalData = api.getInternalData(elt)
+  var headers = { 'HX-Request': 'true' }
+  if (internalData.sseLastEventId != null) {
+    headers['Last-Event-ID'] = internalData.sseLastEventId
+  }
+
+  var source = htmx.createEventSource(internalData.sseResumeURL, {
+    headers,
+    method: 'GET',
+    start: false,
+  })
+  internalData.sseEventSource = source
+  listen(elt, source)
+}
+
+/**
+ * followEventSource connects elements with `hx-sse-get` as soon as they are
+ * processed, and keeps them connected until they are removed.
+ *
+ * @param {HTMLElement} elt
+ */
+function followEventSource(elt) {
+  var url = api.getAttributeValue(elt, 'hx-sse-get')
+  var internalData = api.getInternalData(elt)
+  if (url == null || internalData.sseEventSource) {
+    return
+  }
+
+  internalData.sseResumeURL = url
+  internalData.sseLastEventId = null
+  internalData.sseRetryCount = 0
+  internalData.sseEnded = false
+  internalData.sseFollowing = true
+  resume(elt)
+}
+
 /**
  * ensureEventSourceOnElement creates a new EventSource connection on the provided element.
  * If a usable EventSource already exists, then it is returned.  If not, then a new EventSource
  * is created and stored in the element's internalData.
  * @param {HTMLElement} elt
- * @param {number} retryCount
  * @returns {EventSource | null}
  */
-function ensureEventSourceOnElement(elt, retryCount) {
+function ensureEventSourceOnElement(elt) {
   if (elt == null) {
     return null
   }
@@ -130,43 +225,50 @@ function ensureEventSourceOnElement(elt, retryCount) {
       return
     }
 
-    ensureEventSource(child, sseURL, retryCount)
+    ensureEventSource(child, sseURL)
   })
 }
 
-function ensureEventSource(elt, url, retryCount) {
+function ensureEventSource(elt, url) {
   const result = api.getInputValues(elt)
   const formData = makeFormData(result.values)
-  const payload = new URLSearchParams(formData).toString()
+
+  // Files can only be sent as multipart, let the browser set the boundary header.
+  const hasFiles = Array.from(formData.values()).some((v) => v instanceof File)
+  const payload = hasFiles ? formData : new URLSearchParams(formData).toString()
+  // Lets the server answer errors as events the page can display.
+  const headers = hasFiles
+    ? { 'HX-Request': 'true' }
+    : {
+        'Content-Type': 'application/x-www-form-urlencoded',
+        'HX-Request': 'true',
+      }
 
   var source = htmx.createEventSource(url, {
-    headers: { 'Content-Type': 'application/x-www-form-urlencoded' },
+    headers,
     payload,
     method: 'POST',
     start: false,
   })
 
+  // Reconnections resume the stream, posting the prompt again would start a new answer.
   source.onerror = function (err) {
-    // Log an error event
     api.triggerErrorEvent(elt, 'htmx:sseError', {
       error: err,
       source: source,
     })
-
-    if (source.readyState === EventSource.CLOSED) {
-      retryCount = retryCount || 0
-      var timeout = Math.random() * (2 ^ retryCount) * 500
-      window.setTimeout(function () {
-        ensureEventSourceOnElement(elt, Math.min(7, retryCount + 1))
-      }, timeout)
-    }
   }
 
   source.onopen = function (_evt) {
     api.triggerEvent(elt, 'htmx:sseOpen', { source: source })
   }
 
-  api.getInternalData(elt).sseEventSource = source
+  var internalData = api.getInternalData(elt)
+  internalData.sseEventSource = source
+  internalData.sseResumeURL = null
+  internalData.sseLastEventId = null
+  internalData.sseRetryCount = 0
+  internalData.sseEnded = false
 }
 
 /**
@@ -302,7 +404,7 @@ function makeFormData(values) {
     if (values.hasOwnProperty(name)) {
       var value = values[name]
       if (Array.isArray(value)) {
-        forEach(value, function (v) {
+        value.forEach(function (v) {
           formData.append(name, v)
         })
       } else {
@@ -0,0 +1,123 @@
+use std::path::Path;
+
+use anyhow::Context;
+use axum::{extract::MatchedPath, http::Request, middleware, Router};
+use tower_http::services::ServeDir;
+use tower_http::trace::TraceLayer;
+use tracing::info_span;
+
+use crate::attachments::AttachmentStore;
+use crate::auth::{keys::ApiKeyStore, middleware::require_user, session::SessionStore, UserStore};
+use crate::cache::ResponseCache;
+use crate::compare::ComparisonStore;
+use crate::config::Config;
+use crate::knowledge::KnowledgeBase;
+use crate::metrics;
+use crate::router::{
+    admin::admin_router, attachments::attachments_router, auth::auth_router,
+    compare::compare_router, documents::documents_router, export::export_router,
+    health::health_router, index::index_router, metrics::metrics_router, rooms::rooms_router,
+    search::search_router, tokenize::tokenize_router, ws::ws_router,
+};
+use crate::shutdown::Shutdown;
+use crate::state::AppState;
+use crate::store::Store;
+use crate::summaries::Summarizer;
+use crate::tokenize::Tokenizers;
+use crate::utils::tokens::TokenCounter;
+
+/// Opens every store of the data directory.
+pub fn open_state(
+    data_dir: impl AsRef<Path>,
+    shutdown: Shutdown,
+    config: Config,
+) -> anyhow::Result<AppState> {
+    let data_dir = data_dir.as_ref();
+    let store = Store::open(data_dir).context("Failed to open conversation store")?;
+    let knowledge = KnowledgeBase::open(data_dir).context("Failed to open knowledge base")?;
+    let attachments = AttachmentStore::open(data_dir).context("Failed to open attachments")?;
+    let users = UserStore::open(data_dir).context("Failed to open user store")?;
+    let sessions = SessionStore::open(data_dir).context("Failed to open session store")?;
+    let api_keys = ApiKeyStore::open(data_dir).context("Failed to open API key store")?;
+    let comparisons = ComparisonStore::open(data_dir).context("Failed to open comparison store")?;
+    let cache =
+        ResponseCache::open(data_dir, config.cache).context("Failed to open response cache")?;
+    let tokenizers = Tokenizers::open(data_dir).context("Failed to open tokenizers")?;
+
+    let summarizer = Summarizer::new(store.clone(), config.summaries, config.models.clone());
+
+    Ok(AppState {
+        shutdown,
+        cache,
+        summarizer,
+        context: config.context,
+        models: config.models,
+        fetch_base_url: config.fetch_base_url,
+        tokens: TokenCounter::from_env(),
+        tokenizers,
+        ..AppState::new(
+            store,
+            knowledge,
+            attachments,
+            users,
+            sessions,
+            api_keys,
+            comparisons,
+        )
+    })
+}
+
+/// Every route of the app. Static assets are served from the working directory.
+pub fn create_app(state: AppState) -> Router {
+    let trace_layer = TraceLayer::new_for_http().make_span_with(|request: &Request<_>| {
+        // Log the matched route's path (with placeholders not filled in).
+        // Use request.uri() or OriginalUri if you want the real path.
+        let matched_path = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map(MatchedPath::as_str);
+
+        // Handlers fill in the conversation and model they work on.
+        info_span!(
+            "http_request",
+            method = ?request.method(),
+            matched_path,
+            conversation_id = tracing::field::Empty,
+            model = tracing::field::Empty,
+        )
+    });
+
+    let assets_path = std::env::current_dir().unwrap();
+
+    // Everything but the login form and static assets requires a session.
+    let protected = Router::new()
+        .merge(index_router())
+        .merge(documents_router())
+        .merge(export_router())
+        .merge(admin_router())
+        .merge(rooms_router())
+        .merge(search_router())
+        .merge(tokenize_router())
+        .merge(compare_router())
+        .merge(ws_router())
+        .merge(attachments_router())
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_user));
+
+    // build our application with a route
+    Router::new()
+        .merge(protected)
+        .merge(auth_router())
+        .merge(metrics_router())
+        .merge(health_router())
+        .nest_service(
+            "/assets",
+            ServeDir::new(format!("{}/assets", assets_path.to_str().unwrap())),
+        )
+        .fallback_service(ServeDir::new(format!(
+            "{}/public",
+            assets_path.to_str().unwrap()
+        )))
+        .layer(middleware::from_fn(metrics::track_requests))
+        .layer(trace_layer)
+        .with_state(state)
+}
@@ -0,0 +1,113 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::knowledge::extract_text;
+
+/// Longest extracted text forwarded to the model for a single file.
+const MAX_TEXT_CHARS: usize = 32 * 1024;
+
+/// A file uploaded along with a prompt.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub name: String,
+    pub content_type: String,
+    pub size: usize,
+    /// Extracted text content, for text-like files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+impl Attachment {
+    pub fn url(&self) -> String {
+        format!("/attachments/{}/{}", self.id, self.name)
+    }
+
+    pub fn is_image(&self) -> bool {
+        self.content_type.starts_with("image/")
+    }
+}
+
+/// Uploaded files, stored as `<dir>/<id>/<name>` and served under `/attachments` to whoever can
+/// read their conversation.
+#[derive(Clone)]
+pub struct AttachmentStore {
+    dir: PathBuf,
+}
+
+impl AttachmentStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().join("attachments");
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn save(&self, name: &str, content_type: &str, data: &[u8]) -> Result<Attachment> {
+        let attachment = Attachment {
+            id: Uuid::new_v4(),
+            name: sanitize(name),
+            content_type: content_type.to_string(),
+            size: data.len(),
+            text: None,
+        };
+
+        let path = self.path(&attachment);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, data).with_context(|| format!("Failed to write {:?}", path))?;
+
+        // Anything decoding as UTF-8 counts as text, whatever its extension (source code, CSV...).
+        let text = if attachment.is_image() {
+            None
+        } else {
+            extract_text(&attachment.name, data)
+                .ok()
+                .or_else(|| String::from_utf8(data.to_vec()).ok())
+                .map(|t| t.chars().take(MAX_TEXT_CHARS).collect())
+        };
+
+        Ok(Attachment { text, ..attachment })
+    }
+
+    /// Inlines an image as a `data:` URL, the form accepted by `image_url` content parts.
+    pub fn data_url(&self, attachment: &Attachment) -> Result<String> {
+        let data = fs::read(self.path(attachment))?;
+        Ok(format!(
+            "data:{};base64,{}",
+            attachment.content_type,
+            STANDARD.encode(data)
+        ))
+    }
+
+    pub fn path(&self, attachment: &Attachment) -> PathBuf {
+        self.dir
+            .join(attachment.id.to_string())
+            .join(&attachment.name)
+    }
+}
+
+/// Keeps file names safe to use both on disk and in URLs.
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+
+    match name.trim_start_matches('.') {
+        "" => "file".into(),
+        n => n.to_string(),
+    }
+}
@@ -0,0 +1,309 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, NaiveDate, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::models::ChatModel;
+
+/// Prepended to every key so they are easy to recognize, e.g. by secret scanners.
+const KEY_PREFIX: &str = "crb_";
+/// Length of the key start kept in clear to tell keys apart in listings.
+const DISPLAYED_CHARS: usize = 10;
+
+/// Tokens spent by a key on a given day.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Usage {
+    pub day: NaiveDate,
+    pub tokens: u64,
+}
+
+/// A key granting programmatic access on behalf of a user.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    /// User the requests are made as.
+    pub owner: Uuid,
+    /// First characters of the key, for display.
+    pub start: String,
+    /// SHA-256 of the key. Keys are random and long, a slow hash would only slow down requests.
+    hash: String,
+    /// Models the key may use, any of them when empty.
+    #[serde(default)]
+    pub models: Vec<ChatModel>,
+    /// Tokens, prompt and response included, the key may spend per UTC day.
+    pub daily_tokens: Option<u64>,
+    pub requests_per_minute: Option<u32>,
+    #[serde(default)]
+    pub usage: Usage,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+
+    /// Tokens spent today.
+    pub fn tokens_today(&self) -> u64 {
+        match self.usage.day == Utc::now().date_naive() {
+            true => self.usage.tokens,
+            false => 0,
+        }
+    }
+}
+
+/// Settings of a key to create.
+pub struct NewApiKey {
+    pub name: String,
+    pub owner: Uuid,
+    pub models: Vec<ChatModel>,
+    pub daily_tokens: Option<u64>,
+    pub requests_per_minute: Option<u32>,
+}
+
+#[derive(Debug)]
+pub enum QuotaError {
+    ModelNotAllowed(ChatModel),
+    RateLimited,
+    BudgetExceeded,
+}
+
+impl fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ModelNotAllowed(model) => write!(f, "This key cannot use the {} model", model),
+            Self::RateLimited => write!(f, "Too many requests for this key, retry in a minute"),
+            Self::BudgetExceeded => write!(f, "Daily token budget of this key exhausted"),
+        }
+    }
+}
+
+impl IntoResponse for QuotaError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Self::ModelNotAllowed(_) => StatusCode::FORBIDDEN,
+            Self::RateLimited | Self::BudgetExceeded => StatusCode::TOO_MANY_REQUESTS,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// Tokens set aside from a key's budget while a request runs, until its usage is recorded.
+#[derive(Debug)]
+pub struct Reservation {
+    key: Uuid,
+    tokens: u64,
+}
+
+/// Issued API keys, persisted as a single JSON file along with their daily usage.
+#[derive(Clone)]
+pub struct ApiKeyStore {
+    path: PathBuf,
+    keys: Arc<RwLock<Vec<ApiKey>>>,
+    /// Recent request times per key, for rate limiting.
+    requests: Arc<Mutex<HashMap<Uuid, VecDeque<Instant>>>>,
+    /// Tokens reserved per key by requests still running.
+    reserved: Arc<Mutex<HashMap<Uuid, u64>>>,
+}
+
+fn hash(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+impl ApiKeyStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let path = dir.as_ref().join("api_keys.json");
+        let keys = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("Failed to parse {:?}", path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+
+        Ok(Self {
+            path,
+            keys: Arc::new(RwLock::new(keys)),
+            requests: Arc::new(Mutex::new(HashMap::new())),
+            reserved: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Every key, revoked ones included, most recent first.
+    pub fn list(&self) -> Vec<ApiKey> {
+        let mut keys = self.keys.read().unwrap().clone();
+        keys.sort_by_key(|k| std::cmp::Reverse(k.created_at));
+        keys
+    }
+
+    /// Issues a key. The returned secret is not stored and cannot be shown again.
+    pub fn create(&self, new_key: NewApiKey) -> Result<(ApiKey, String)> {
+        if new_key.name.trim().is_empty() {
+            bail!("Key name cannot be empty");
+        }
+
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let secret = format!("{}{}", KEY_PREFIX, URL_SAFE_NO_PAD.encode(bytes));
+
+        let key = ApiKey {
+            id: Uuid::new_v4(),
+            name: new_key.name.trim().to_string(),
+            owner: new_key.owner,
+            start: secret.chars().take(DISPLAYED_CHARS).collect(),
+            hash: hash(&secret),
+            models: new_key.models,
+            daily_tokens: new_key.daily_tokens,
+            requests_per_minute: new_key.requests_per_minute,
+            usage: Usage::default(),
+            created_at: Utc::now(),
+            revoked_at: None,
+        };
+
+        self.keys.write().unwrap().push(key.clone());
+        self.write()?;
+        Ok((key, secret))
+    }
+
+    pub fn revoke(&self, id: Uuid) -> Result<()> {
+        for key in self.keys.write().unwrap().iter_mut() {
+            if key.id == id && key.revoked_at.is_none() {
+                key.revoked_at = Some(Utc::now());
+            }
+        }
+        self.write()
+    }
+
+    /// Revokes every key of a user, e.g. when the account is deleted.
+    pub fn revoke_user(&self, owner: Uuid) -> Result<()> {
+        for key in self.keys.write().unwrap().iter_mut() {
+            if key.owner == owner && key.revoked_at.is_none() {
+                key.revoked_at = Some(Utc::now());
+            }
+        }
+        self.write()
+    }
+
+    /// Returns the active key matching a secret.
+    pub fn authenticate(&self, secret: &str) -> Option<ApiKey> {
+        let hash = hash(secret);
+        self.keys
+            .read()
+            .unwrap()
+            .iter()
+            .find(|k| k.hash == hash && !k.is_revoked())
+            .cloned()
+    }
+
+    /// Checks a key may use each of `models` and counts the request against its rate limit.
+    /// Done before anything is spent on the request.
+    pub fn admit(&self, key: &ApiKey, models: &[ChatModel]) -> Result<(), QuotaError> {
+        if let Some(model) = models
+            .iter()
+            .find(|m| !key.models.is_empty() && !key.models.contains(m))
+        {
+            return Err(QuotaError::ModelNotAllowed(*model));
+        }
+
+        if let Some(limit) = key.requests_per_minute {
+            let mut requests = self.requests.lock().unwrap();
+            let times = requests.entry(key.id).or_default();
+            let now = Instant::now();
+            while times
+                .front()
+                .is_some_and(|t| now.duration_since(*t) > Duration::from_secs(60))
+            {
+                times.pop_front();
+            }
+            if times.len() >= limit as usize {
+                return Err(QuotaError::RateLimited);
+            }
+            times.push_back(now);
+        }
+        Ok(())
+    }
+
+    /// Reserves the tokens of a prompt of about `tokens` tokens run on each of `models` until
+    /// [`Self::record_usage`]. Concurrent requests can't spend more than the budget together.
+    pub fn reserve(
+        &self,
+        key: &ApiKey,
+        models: &[ChatModel],
+        tokens: u64,
+    ) -> Result<Reservation, QuotaError> {
+        let tokens = tokens * models.len() as u64;
+
+        // Held until the tokens are reserved. The current usage is read here, the key may come
+        // from an earlier request.
+        let keys = self.keys.write().unwrap();
+        let mut reserved = self.reserved.lock().unwrap();
+        let spent = keys
+            .iter()
+            .find(|k| k.id == key.id)
+            .map(ApiKey::tokens_today)
+            .unwrap_or_default()
+            + reserved.get(&key.id).copied().unwrap_or_default();
+        if key
+            .daily_tokens
+            .is_some_and(|budget| spent + tokens > budget)
+        {
+            return Err(QuotaError::BudgetExceeded);
+        }
+
+        *reserved.entry(key.id).or_default() += tokens;
+        Ok(Reservation {
+            key: key.id,
+            tokens,
+        })
+    }
+
+    /// Adds the tokens a request actually spent to the usage of the day, in place of what it
+    /// reserved.
+    pub fn record_usage(&self, reservation: Reservation, tokens: u64) -> Result<()> {
+        let today = Utc::now().date_naive();
+        {
+            let mut keys = self.keys.write().unwrap();
+            let mut reserved = self.reserved.lock().unwrap();
+            if let Some(left) = reserved.get_mut(&reservation.key) {
+                *left = left.saturating_sub(reservation.tokens);
+                if *left == 0 {
+                    reserved.remove(&reservation.key);
+                }
+            }
+            for key in keys.iter_mut() {
+                if key.id == reservation.key {
+                    if key.usage.day != today {
+                        key.usage = Usage {
+                            day: today,
+                            tokens: 0,
+                        };
+                    }
+                    key.usage.tokens += tokens;
+                }
+            }
+        }
+        self.write()
+    }
+
+    fn write(&self) -> Result<()> {
+        let data = serde_json::to_vec_pretty(&*self.keys.read().unwrap())?;
+        fs::write(&self.path, data).with_context(|| format!("Failed to write {:?}", self.path))
+    }
+}
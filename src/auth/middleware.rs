@@ -0,0 +1,97 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{request::Parts, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::{
+    extract::CookieJar,
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+
+use super::{session::SESSION_COOKIE, User};
+use crate::state::AppState;
+
+/// Rejects requests without a valid session or API key, and makes the authenticated [`User`]
+/// available to handlers through [`CurrentUser`].
+///
+/// Requests made with an `Authorization: Bearer` API key act as the key's owner, and carry the
+/// [`ApiKey`](super::keys::ApiKey) in their extensions so handlers can enforce its quotas.
+pub async fn require_user(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let user = match bearer {
+        Some(TypedHeader(Authorization(bearer))) => {
+            match state.api_keys.authenticate(bearer.token()) {
+                Some(key) => {
+                    let user = state.users.get(key.owner);
+                    req.extensions_mut().insert(key);
+                    user
+                }
+                None => return (StatusCode::UNAUTHORIZED, "Invalid API key").into_response(),
+            }
+        }
+        None => jar
+            .get(SESSION_COOKIE)
+            .and_then(|cookie| state.sessions.user(cookie.value()))
+            .and_then(|id| state.users.get(id)),
+    };
+
+    match user {
+        Some(user) => {
+            req.extensions_mut().insert(user);
+            next.run(req).await
+        }
+        None => unauthorized(&req),
+    }
+}
+
+/// Browsers navigating to a page are sent to the login form, anything else gets a 401.
+fn unauthorized(req: &Request) -> Response {
+    if req.headers().contains_key("HX-Request") {
+        return (StatusCode::UNAUTHORIZED, [("HX-Redirect", "/login")]).into_response();
+    }
+    if req.method() == Method::GET {
+        return Redirect::to("/login").into_response();
+    }
+    StatusCode::UNAUTHORIZED.into_response()
+}
+
+/// The user logged in for this request, for routes behind [`require_user`].
+pub struct CurrentUser(pub User);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CurrentUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<User>()
+            .cloned()
+            .map(CurrentUser)
+            .ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// Same as [`CurrentUser`], restricted to administrators.
+pub struct AdminUser(pub User);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AdminUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let CurrentUser(user) = CurrentUser::from_request_parts(parts, state).await?;
+        if !user.is_admin() {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        Ok(AdminUser(user))
+    }
+}
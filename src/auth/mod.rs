@@ -0,0 +1,169 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub mod keys;
+pub mod middleware;
+pub mod session;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    #[default]
+    User,
+    Admin,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct User {
+    pub id: Uuid,
+    pub username: String,
+    /// Argon2 hash in PHC string format.
+    password_hash: String,
+    #[serde(default)]
+    pub role: UserRole,
+    pub created_at: DateTime<Utc>,
+}
+
+impl User {
+    pub fn is_admin(&self) -> bool {
+        self.role == UserRole::Admin
+    }
+}
+
+/// Accounts, persisted as a single JSON file.
+///
+/// Hashing and verifying passwords is deliberately slow, call those methods from
+/// `spawn_blocking`.
+#[derive(Clone)]
+pub struct UserStore {
+    path: PathBuf,
+    users: Arc<RwLock<Vec<User>>>,
+}
+
+/// Checks the credentials of a new user and hashes the password.
+fn new_user(username: &str, password: &str, role: UserRole) -> Result<User> {
+    let username = username.trim();
+    if username.is_empty() {
+        bail!("Username cannot be empty");
+    }
+    if password.len() < 8 {
+        bail!("Password must be at least 8 characters long");
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow!("Failed to hash password: {}", e))?
+        .to_string();
+
+    Ok(User {
+        id: Uuid::new_v4(),
+        username: username.to_string(),
+        password_hash,
+        role,
+        created_at: Utc::now(),
+    })
+}
+
+impl UserStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let path = dir.as_ref().join("users.json");
+        let users = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("Failed to parse {:?}", path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+
+        Ok(Self {
+            path,
+            users: Arc::new(RwLock::new(users)),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.read().unwrap().is_empty()
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<User> {
+        self.users
+            .read()
+            .unwrap()
+            .iter()
+            .find(|u| u.id == id)
+            .cloned()
+    }
+
+    pub fn find(&self, username: &str) -> Option<User> {
+        self.users
+            .read()
+            .unwrap()
+            .iter()
+            .find(|u| u.username == username.trim())
+            .cloned()
+    }
+
+    pub fn list(&self) -> Vec<User> {
+        self.users.read().unwrap().clone()
+    }
+
+    pub fn create(&self, username: &str, password: &str, role: UserRole) -> Result<User> {
+        self.add(new_user(username, password, role)?, false)
+    }
+
+    /// Creates the first user, as an administrator. Fails once any user exists, so concurrent
+    /// setups can't create several administrators.
+    pub fn create_first(&self, username: &str, password: &str) -> Result<User> {
+        self.add(new_user(username, password, UserRole::Admin)?, true)
+    }
+
+    fn add(&self, user: User, first: bool) -> Result<User> {
+        {
+            let mut users = self.users.write().unwrap();
+            if first && !users.is_empty() {
+                bail!("Setup is already done, log in instead");
+            }
+            if users.iter().any(|u| u.username == user.username) {
+                bail!("Username `{}` is already taken", user.username);
+            }
+            users.push(user.clone());
+        }
+
+        self.write()?;
+        Ok(user)
+    }
+
+    /// Returns the user matching these credentials.
+    pub fn authenticate(&self, username: &str, password: &str) -> Option<User> {
+        let user = self.find(username)?;
+
+        let hash = PasswordHash::new(&user.password_hash).ok()?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .ok()
+            .map(|_| user)
+    }
+
+    pub fn remove(&self, id: Uuid) -> Result<()> {
+        self.users.write().unwrap().retain(|u| u.id != id);
+        self.write()
+    }
+
+    fn write(&self) -> Result<()> {
+        let data = serde_json::to_vec_pretty(&*self.users.read().unwrap())?;
+        fs::write(&self.path, data).with_context(|| format!("Failed to write {:?}", self.path))
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Name of the cookie holding the session token.
+pub const SESSION_COOKIE: &str = "crabot_session";
+/// How long a login lasts.
+pub const SESSION_DAYS: i64 = 30;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Session {
+    pub user: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Login sessions keyed by their random token, persisted so restarts don't log everyone out.
+#[derive(Clone)]
+pub struct SessionStore {
+    path: PathBuf,
+    sessions: Arc<RwLock<HashMap<String, Session>>>,
+}
+
+impl SessionStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let path = dir.as_ref().join("sessions.json");
+        let mut sessions: HashMap<String, Session> = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("Failed to parse {:?}", path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        sessions.retain(|_, s| s.expires_at > Utc::now());
+
+        Ok(Self {
+            path,
+            sessions: Arc::new(RwLock::new(sessions)),
+        })
+    }
+
+    /// Starts a session and returns its token.
+    pub fn create(&self, user: Uuid) -> Result<String> {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+
+        self.sessions.write().unwrap().insert(
+            token.clone(),
+            Session {
+                user,
+                expires_at: Utc::now() + Duration::days(SESSION_DAYS),
+            },
+        );
+        self.write()?;
+        Ok(token)
+    }
+
+    /// Returns the user logged in with this token, unless the session expired.
+    pub fn user(&self, token: &str) -> Option<Uuid> {
+        self.sessions
+            .read()
+            .unwrap()
+            .get(token)
+            .filter(|s| s.expires_at > Utc::now())
+            .map(|s| s.user)
+    }
+
+    pub fn remove(&self, token: &str) -> Result<()> {
+        self.sessions.write().unwrap().remove(token);
+        self.write()
+    }
+
+    /// Ends every session of a user, e.g. when the account is deleted.
+    pub fn remove_user(&self, user: Uuid) -> Result<()> {
+        self.sessions.write().unwrap().retain(|_, s| s.user != user);
+        self.write()
+    }
+
+    fn write(&self) -> Result<()> {
+        let data = serde_json::to_vec_pretty(&*self.sessions.read().unwrap())?;
+        fs::write(&self.path, data).with_context(|| format!("Failed to write {:?}", self.path))
+    }
+}
@@ -0,0 +1,318 @@
+//! Runs a dataset of prompts against one or more models and checks their answers.
+//!
+//! The dataset is a JSONL file (or a JSON array) of cases:
+//!
+//! ```json
+//! {"id": "capital", "prompt": "What is the capital of France?", "assert": [{"type": "contains", "value": "Paris"}], "mock": "Paris."}
+//! ```
+//!
+//! Assertions are `contains`, `regex`, `json_schema` and `exact`. With `--mock`, remote models
+//! are answered by a local mock provider with each case's `mock` answer (or its prompt), for
+//! deterministic CI runs. Answers recorded with `CRABOT_RECORD_DIR` are replayed from
+//! `CRABOT_REPLAY_DIR`.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Instant,
+};
+
+use anyhow::{bail, Context, Result};
+use askama::Template;
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_stream::StreamExt;
+
+use crabot::chat;
+use crabot::models::{mock::MockProvider, upstream::Upstream, ChatMessage, ChatModel, ModelConfig};
+use crabot::telemetry;
+use crabot::utils::{schema, tokens};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Dataset of cases, as JSONL or a JSON array.
+    dataset: PathBuf,
+
+    /// Model to evaluate, repeat to compare several.
+    #[arg(long = "model", default_value = "lorem")]
+    models: Vec<ChatModel>,
+
+    /// Answer remote models with a local mock provider instead of calling their APIs.
+    #[arg(long)]
+    mock: bool,
+
+    /// Directory the JSON and HTML reports are written to.
+    #[arg(long, default_value = "eval-report")]
+    out: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct Case {
+    id: String,
+    prompt: String,
+    #[serde(default)]
+    system: Option<String>,
+    /// JSON Schema the answer is requested to follow.
+    #[serde(default)]
+    schema: Option<Value>,
+    #[serde(default, rename = "assert")]
+    assertions: Vec<Assertion>,
+    /// Answer of the mock provider.
+    #[serde(default)]
+    mock: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+enum Assertion {
+    Contains(String),
+    Regex(String),
+    JsonSchema(Value),
+    Exact(String),
+}
+
+impl Assertion {
+    /// Why the output fails the assertion, if it does.
+    fn check(&self, output: &str) -> Option<String> {
+        match self {
+            Self::Contains(text) => {
+                (!output.contains(text.as_str())).then(|| format!("Does not contain `{}`", text))
+            }
+            Self::Regex(pattern) => match Regex::new(pattern) {
+                Ok(regex) => {
+                    (!regex.is_match(output)).then(|| format!("Does not match `{}`", pattern))
+                }
+                Err(e) => Some(format!("Invalid regex: {}", e)),
+            },
+            Self::JsonSchema(schema) => match schema::validate(schema, output) {
+                violations if violations.is_empty() => None,
+                violations => Some(violations.join("; ")),
+            },
+            Self::Exact(text) => {
+                (output.trim() != text.trim()).then(|| "Not an exact match".into())
+            }
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Contains(_) => "contains",
+            Self::Regex(_) => "regex",
+            Self::JsonSchema(_) => "json_schema",
+            Self::Exact(_) => "exact",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+    assertion: Assertion,
+    passed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct CaseResult {
+    case: String,
+    model: ChatModel,
+    prompt: String,
+    output: String,
+    passed: bool,
+    checks: Vec<Check>,
+    /// Milliseconds until the first chunk, unset when nothing was answered.
+    time_to_first_token_ms: Option<u64>,
+    duration_ms: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Summary {
+    model: ChatModel,
+    passed: usize,
+    failed: usize,
+    mean_duration_ms: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+#[derive(Debug, Serialize, Template)]
+#[template(path = "eval/report.html")]
+struct Report {
+    dataset: String,
+    generated_at: DateTime<Utc>,
+    mock: bool,
+    summaries: Vec<Summary>,
+    results: Vec<CaseResult>,
+}
+
+fn read_dataset(path: &Path) -> Result<Vec<Case>> {
+    let data = fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    if data.trim_start().starts_with('[') {
+        return serde_json::from_str(&data).context("Invalid JSON dataset");
+    }
+
+    data.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).with_context(|| format!("Invalid case on line {}", i + 1))
+        })
+        .collect()
+}
+
+async fn run_case(models: &ModelConfig, case: &Case, model: ChatModel) -> CaseResult {
+    let mut messages = vec![];
+    if let Some(system) = &case.system {
+        messages.push(ChatMessage::system(system.clone()));
+    }
+    messages.push(ChatMessage::user(case.prompt.clone()));
+    let prompt_tokens = tokens::estimate_messages(&messages);
+
+    let started_at = Instant::now();
+    let mut time_to_first_token_ms = None;
+    let mut output = String::new();
+    let mut chunks = chat::pipeline(models, model, None, case.schema.clone()).run(messages);
+    while let Some(chunk) = chunks.next().await {
+        time_to_first_token_ms.get_or_insert(started_at.elapsed().as_millis() as u64);
+        output.push_str(&chunk);
+    }
+    let duration_ms = started_at.elapsed().as_millis() as u64;
+
+    let checks: Vec<_> = case
+        .assertions
+        .iter()
+        .map(|assertion| {
+            let detail = assertion.check(&output);
+            Check {
+                assertion: assertion.clone(),
+                passed: detail.is_none(),
+                detail,
+            }
+        })
+        .collect();
+
+    CaseResult {
+        case: case.id.clone(),
+        model,
+        prompt: case.prompt.clone(),
+        passed: checks.iter().all(|c| c.passed),
+        checks,
+        completion_tokens: tokens::estimate(&output),
+        output,
+        time_to_first_token_ms,
+        duration_ms,
+        prompt_tokens,
+    }
+}
+
+fn summarize(model: ChatModel, results: &[CaseResult]) -> Summary {
+    let results: Vec<_> = results.iter().filter(|r| r.model == model).collect();
+    let passed = results.iter().filter(|r| r.passed).count();
+    let total_duration: u64 = results.iter().map(|r| r.duration_ms).sum();
+
+    Summary {
+        model,
+        passed,
+        failed: results.len() - passed,
+        mean_duration_ms: total_duration / results.len().max(1) as u64,
+        prompt_tokens: results.iter().map(|r| r.prompt_tokens).sum(),
+        completion_tokens: results.iter().map(|r| r.completion_tokens).sum(),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    let args = Args::parse();
+    let _telemetry = telemetry::init("crabot-eval");
+
+    let cases = read_dataset(&args.dataset)?;
+
+    let mut models = ModelConfig::from_env();
+    if args.mock {
+        let answers = cases
+            .iter()
+            .filter_map(|c| Some((c.prompt.clone(), c.mock.clone()?)))
+            .collect::<HashMap<_, _>>();
+        let provider = MockProvider::start(answers)
+            .await
+            .context("Failed to start the mock provider")?;
+        provider.install(&mut models);
+    }
+    let live = !args.mock && Upstream::from_env().is_live();
+    if let Some(model) = args.models.iter().find(|m| live && !m.has_credentials()) {
+        bail!(
+            "{} requires {} to be set, or --mock",
+            model,
+            model.credentials_var().unwrap_or_default()
+        );
+    }
+
+    let mut results = vec![];
+    for case in cases.iter() {
+        for model in args.models.iter() {
+            let result = run_case(&models, case, *model).await;
+            println!(
+                "{} {} [{}] {} ms",
+                if result.passed { "PASS" } else { "FAIL" },
+                result.case,
+                result.model,
+                result.duration_ms
+            );
+            for check in result.checks.iter().filter(|c| !c.passed) {
+                println!(
+                    "    {}: {}",
+                    check.assertion.kind(),
+                    check.detail.as_deref().unwrap_or_default()
+                );
+            }
+            results.push(result);
+        }
+    }
+
+    let report = Report {
+        dataset: args.dataset.display().to_string(),
+        generated_at: Utc::now(),
+        mock: args.mock,
+        summaries: args
+            .models
+            .iter()
+            .map(|model| summarize(*model, &results))
+            .collect(),
+        results,
+    };
+
+    fs::create_dir_all(&args.out).with_context(|| format!("Failed to create {:?}", args.out))?;
+    fs::write(
+        args.out.join("report.json"),
+        serde_json::to_vec_pretty(&report)?,
+    )?;
+    fs::write(args.out.join("report.html"), report.render()?)?;
+
+    for summary in report.summaries.iter() {
+        println!(
+            "{}: {} passed, {} failed, {} ms on average, {} + {} tokens",
+            summary.model,
+            summary.passed,
+            summary.failed,
+            summary.mean_duration_ms,
+            summary.prompt_tokens,
+            summary.completion_tokens
+        );
+    }
+    println!("Report written to {:?}", args.out);
+
+    let failed = report.summaries.iter().any(|s| s.failed > 0);
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
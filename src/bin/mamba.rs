@@ -7,6 +7,8 @@ use candle_transformers::generation::LogitsProcessor;
 use hf_hub::{api::sync::Api, Repo, RepoType};
 use tokenizers::Tokenizer;
 
+use crabot::telemetry;
+
 /// This follows the lines of:
 /// https://github.com/johnma2006/mamba-minimal/blob/master/model.py
 /// Simple, minimal implementation of Mamba in one file of PyTorch.
@@ -70,7 +72,9 @@ impl TextGeneration {
             None => anyhow::bail!("cannot find the </s> token"),
         };
         let start_gen = std::time::Instant::now();
-        for _ in 0..sample_len {
+        for index in 0..sample_len {
+            let _span =
+                tracing::info_span!("mamba_forward", index, tokens = tokens.len()).entered();
             let input = Tensor::new(tokens.as_slice(), &self.device)?.unsqueeze(0)?;
             let logits = self.model.forward(&input)?;
             let logits = logits.squeeze(0)?.squeeze(0)?.to_dtype(DType::F32)?;
@@ -211,13 +215,22 @@ fn main() -> R<()> {
     use tracing_subscriber::prelude::*;
 
     let args = Args::parse();
-    let _guard = if args.tracing {
-        let (chrome_layer, guard) = ChromeLayerBuilder::new().build();
-        tracing_subscriber::registry().with(chrome_layer).init();
-        Some(guard)
-    } else {
-        None
+    let (chrome_layer, _guard) = match args.tracing {
+        true => {
+            let (chrome_layer, guard) = ChromeLayerBuilder::new().build();
+            (Some(chrome_layer), Some(guard))
+        }
+        false => (None, None),
     };
+    // The batch exporter sends spans from a Tokio task, generation itself stays synchronous.
+    let runtime = tokio::runtime::Runtime::new()?;
+    let _rt = runtime.enter();
+    let otlp = telemetry::otlp_layer("crabot-mamba", None);
+    let _telemetry = telemetry::TelemetryGuard::new(otlp.is_some());
+    tracing_subscriber::registry()
+        .with(chrome_layer)
+        .with(otlp)
+        .init();
     println!(
         "avx: {}, neon: {}, simd128: {}, f16c: {}",
         candle_core::utils::with_avx(),
@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt as _};
+use uuid::Uuid;
+
+use crate::models::{ChatMessage, ChatModel};
+use crate::utils::env_or;
+
+/// Where cached answers are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheBackend {
+    Memory,
+    /// In memory and mirrored to one JSON file each, so they survive restarts.
+    Disk,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Unset when caching is off.
+    pub backend: Option<CacheBackend>,
+    /// How long answers are reused.
+    pub ttl: Duration,
+    /// Past this many answers, the oldest are evicted.
+    pub max_entries: usize,
+    /// Delay between replayed chunks, to simulate streaming. Cached answers are sent at once
+    /// when zero.
+    pub replay_delay: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            backend: None,
+            ttl: Duration::from_secs(60 * 60),
+            max_entries: 1000,
+            replay_delay: Duration::ZERO,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Caching is opted into with `CRABOT_CACHE=memory` or `CRABOT_CACHE=disk`.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let backend = match std::env::var("CRABOT_CACHE").as_deref().map(str::trim) {
+            Ok("memory") => Some(CacheBackend::Memory),
+            Ok("disk") => Some(CacheBackend::Disk),
+            Ok("" | "off") | Err(_) => None,
+            Ok(other) => {
+                tracing::warn!("CRABOT_CACHE: Unknown backend `{}`", other);
+                None
+            }
+        };
+
+        Self {
+            backend,
+            ttl: Duration::from_secs(env_or("CRABOT_CACHE_TTL_SECS", defaults.ttl.as_secs())),
+            max_entries: env_or("CRABOT_CACHE_MAX_ENTRIES", defaults.max_entries),
+            replay_delay: Duration::from_millis(env_or("CRABOT_CACHE_REPLAY_DELAY_MS", 0)),
+        }
+    }
+}
+
+/// Identifies a request: who made it, the model, its parameters and the whole message history,
+/// system prompts and retrieved documents included. Answers are never shared between users.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey(String);
+
+impl CacheKey {
+    pub fn new(
+        owner: Uuid,
+        model: ChatModel,
+        schema: Option<&Value>,
+        messages: &[ChatMessage],
+    ) -> Self {
+        let request = json!({
+            "owner": owner,
+            "model": model,
+            "schema": schema,
+            "messages": messages,
+        });
+        let digest = Sha256::digest(request.to_string().as_bytes());
+        Self(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+}
+
+/// An answer as it was streamed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CachedAnswer {
+    pub key: String,
+    pub model: ChatModel,
+    pub chunks: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Complete answers, reused for identical requests. Disabled unless configured.
+#[derive(Clone, Default)]
+pub struct ResponseCache {
+    inner: Option<Arc<Inner>>,
+}
+
+struct Inner {
+    config: CacheConfig,
+    /// Set for the disk backend.
+    dir: Option<PathBuf>,
+    answers: Mutex<HashMap<String, CachedAnswer>>,
+}
+
+impl ResponseCache {
+    pub fn open(dir: impl AsRef<Path>, config: CacheConfig) -> anyhow::Result<Self> {
+        let Some(backend) = config.backend else {
+            return Ok(Self::default());
+        };
+
+        let mut answers = HashMap::new();
+        let dir = match backend {
+            CacheBackend::Memory => None,
+            CacheBackend::Disk => {
+                let dir = dir.as_ref().join("cache");
+                fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+                for entry in fs::read_dir(&dir)? {
+                    let path = entry?.path();
+                    if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                        continue;
+                    }
+
+                    let answer = fs::read(&path)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|data| Ok(serde_json::from_slice::<CachedAnswer>(&data)?));
+                    match answer {
+                        Ok(answer) => {
+                            answers.insert(answer.key.clone(), answer);
+                        }
+                        Err(e) => tracing::error!("Cache: Could not load {:?}: {}", path, e),
+                    }
+                }
+                Some(dir)
+            }
+        };
+
+        let inner = Inner {
+            config,
+            dir,
+            answers: Mutex::new(HashMap::new()),
+        };
+        inner.evict(&mut answers);
+        *inner.answers.lock().unwrap() = answers;
+
+        Ok(Self {
+            inner: Some(Arc::new(inner)),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    /// The answer cached for this request, unless it expired.
+    pub fn get(&self, key: &CacheKey) -> Option<CachedAnswer> {
+        let inner = self.inner.as_ref()?;
+        let mut answers = inner.answers.lock().unwrap();
+        let answer = answers.get(&key.0)?;
+        if !inner.is_expired(answer) {
+            return Some(answer.clone());
+        }
+
+        answers.remove(&key.0);
+        inner.remove_file(&key.0);
+        None
+    }
+
+    /// Streams a cached answer in the chunks it was received as.
+    pub fn replay(&self, answer: CachedAnswer) -> ReceiverStream<String> {
+        let delay = self
+            .inner
+            .as_ref()
+            .map_or(Duration::ZERO, |inner| inner.config.replay_delay);
+        let (tx, rx) = mpsc::channel(10);
+        tokio::spawn(async move {
+            for (i, chunk) in answer.chunks.into_iter().enumerate() {
+                if i > 0 && !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                if tx.send(chunk).await.is_err() {
+                    return;
+                }
+            }
+        });
+        rx.into()
+    }
+
+    /// Passes an answer through, and caches it once `complete` is received. Answers abandoned,
+    /// failed or cut short are not cached.
+    pub fn record(
+        &self,
+        key: CacheKey,
+        model: ChatModel,
+        chunks: ReceiverStream<String>,
+        complete: oneshot::Receiver<()>,
+    ) -> ReceiverStream<String> {
+        let Some(inner) = self.inner.clone() else {
+            return chunks;
+        };
+
+        let (tx, rx) = mpsc::channel(10);
+        tokio::spawn(async move {
+            let mut chunks = chunks;
+            let mut answer = CachedAnswer {
+                key: key.0,
+                model,
+                chunks: vec![],
+                created_at: Utc::now(),
+            };
+            while let Some(chunk) = chunks.next().await {
+                answer.chunks.push(chunk.clone());
+                if tx.send(chunk).await.is_err() {
+                    return;
+                }
+            }
+
+            // Completion is only confirmed once the answer stream ended.
+            drop(tx);
+            if complete.await.is_err() || answer.chunks.iter().all(|c| c.trim().is_empty()) {
+                return;
+            }
+            if let Err(e) = inner.insert(answer) {
+                tracing::error!("Cache: Could not store answer: {}", e);
+            }
+        });
+        rx.into()
+    }
+}
+
+impl Inner {
+    fn is_expired(&self, answer: &CachedAnswer) -> bool {
+        let age = Utc::now() - answer.created_at;
+        age.to_std().unwrap_or_default() > self.config.ttl
+    }
+
+    fn insert(&self, answer: CachedAnswer) -> anyhow::Result<()> {
+        if let Some(dir) = &self.dir {
+            let path = dir.join(format!("{}.json", answer.key));
+            fs::write(&path, serde_json::to_vec(&answer)?)
+                .with_context(|| format!("Failed to write {:?}", path))?;
+        }
+
+        let mut answers = self.answers.lock().unwrap();
+        answers.insert(answer.key.clone(), answer);
+        self.evict(&mut answers);
+        Ok(())
+    }
+
+    /// Drops expired answers, then the oldest ones past the size limit.
+    fn evict(&self, answers: &mut HashMap<String, CachedAnswer>) {
+        let mut expired: Vec<String> = answers
+            .values()
+            .filter(|a| self.is_expired(a))
+            .map(|a| a.key.clone())
+            .collect();
+
+        let excess = (answers.len() - expired.len()).saturating_sub(self.config.max_entries);
+        if excess > 0 {
+            let mut live: Vec<_> = answers.values().filter(|a| !self.is_expired(a)).collect();
+            live.sort_by_key(|a| a.created_at);
+            expired.extend(live.iter().take(excess).map(|a| a.key.clone()));
+        }
+
+        for key in expired {
+            answers.remove(&key);
+            self.remove_file(&key);
+        }
+    }
+
+    fn remove_file(&self, key: &str) {
+        if let Some(dir) = &self.dir {
+            let path = dir.join(format!("{}.json", key));
+            if let Err(e) = fs::remove_file(&path) {
+                tracing::error!("Cache: Could not remove {:?}: {}", path, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,417 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures::stream::once;
+use futures::FutureExt as _;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::{mpsc::channel, oneshot, OwnedSemaphorePermit};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt as _;
+use uuid::Uuid;
+
+use crate::attachments::Attachment;
+use crate::auth::{
+    keys::{ApiKey, QuotaError, Reservation},
+    User,
+};
+use crate::cache::CacheKey;
+use crate::context::{self, RollingSummary};
+use crate::generations::{GenerationLog, Update};
+use crate::knowledge::context_prompt;
+use crate::limits::{ClientKey, LimitError};
+use crate::metrics::Generation;
+use crate::models::{
+    gpt::GPT3Pipeline, lorem::LoremPipeline, mistral::MistralPipeline, upstream::Upstream,
+    ChatMessage, ChatModel, ModelConfig, Pipeline,
+};
+use crate::state::AppState;
+use crate::store::Message;
+use crate::tools::{ToolInvocation, ToolRegistry, Toolbox};
+use crate::utils::{schema, tokens};
+
+/// Number of document excerpts handed to the model as context.
+const CONTEXT_CHUNKS: usize = 4;
+
+/// A prompt, as posted by the chat form or sent over a WebSocket.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PostMessage {
+    pub prompt: String,
+    #[serde(default)]
+    pub model: ChatModel,
+    #[serde(default = "Uuid::new_v4")]
+    pub conversation: Uuid,
+    #[serde(default)]
+    pub tools: bool,
+    /// Optional JSON Schema the answer must conform to.
+    #[serde(default)]
+    pub schema: String,
+    /// Page the prompt was posted from, which displays the answer itself rather than through
+    /// its conversation's live stream.
+    #[serde(default)]
+    pub viewer: Option<Uuid>,
+}
+
+/// A file attached to a prompt.
+pub struct Upload {
+    pub name: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Why a prompt was refused.
+#[derive(Debug)]
+pub enum ChatError {
+    /// The conversation belongs to someone else.
+    NotFound,
+    Limit(LimitError),
+    Quota(QuotaError),
+    /// The JSON Schema the answer must conform to doesn't parse.
+    InvalidSchema(String),
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "Conversation not found"),
+            Self::Limit(e) => e.fmt(f),
+            Self::Quota(e) => e.fmt(f),
+            Self::InvalidSchema(e) => write!(f, "Invalid schema: {}", e),
+        }
+    }
+}
+
+impl IntoResponse for ChatError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::NotFound => StatusCode::NOT_FOUND.into_response(),
+            Self::Limit(e) => e.into_response(),
+            Self::Quota(e) => e.into_response(),
+            Self::InvalidSchema(_) => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+        }
+    }
+}
+
+/// The pipeline answering with a model. Lorem doesn't call tools.
+pub fn pipeline(
+    config: &ModelConfig,
+    model: ChatModel,
+    tools: Option<Toolbox>,
+    schema: Option<Value>,
+) -> Box<dyn Pipeline> {
+    match model {
+        ChatModel::GPT3 => Box::new(GPT3Pipeline {
+            tools,
+            schema,
+            endpoint: config.openai.clone(),
+            upstream: Upstream::from_env(),
+        }),
+        ChatModel::Mistral => Box::new(MistralPipeline {
+            tools,
+            schema,
+            endpoint: config.mistral.clone(),
+            upstream: Upstream::from_env(),
+        }),
+        ChatModel::Lorem => Box::new(LoremPipeline {
+            schema,
+            options: config.lorem.clone(),
+        }),
+    }
+}
+
+/// The JSON Schema an answer must conform to, if one was given.
+pub fn parse_schema(schema: &str) -> Result<Option<Value>, ChatError> {
+    match schema.trim() {
+        "" => Ok(None),
+        s => serde_json::from_str(s)
+            .map(Some)
+            .map_err(|e| ChatError::InvalidSchema(e.to_string())),
+    }
+}
+
+/// Checks a user may start answers, and takes a provider slot for each model. Slots are held
+/// until the answers are complete.
+pub fn acquire_slots(
+    state: &AppState,
+    user: &User,
+    models: &[ChatModel],
+) -> Result<Vec<Option<OwnedSemaphorePermit>>, ChatError> {
+    (!state.shutdown.is_started())
+        .then_some(())
+        .ok_or(LimitError::ShuttingDown)
+        .and_then(|_| state.limits.prompts.check(ClientKey::User(user.id)))
+        .and_then(|_| {
+            models
+                .iter()
+                .map(|model| state.limits.providers.acquire(*model))
+                .collect()
+        })
+        .map_err(ChatError::Limit)
+}
+
+/// Checks the API key a prompt is made with, if any, may use each model and isn't over its
+/// rate, before anything is spent on the prompt.
+pub fn admit_key(
+    state: &AppState,
+    api_key: Option<&ApiKey>,
+    models: &[ChatModel],
+) -> Result<(), ChatError> {
+    api_key
+        .map_or(Ok(()), |key| state.api_keys.admit(key, models))
+        .map_err(ChatError::Quota)
+}
+
+/// Checks a prompt of `prompt_tokens` tokens sent to each model fits the budget of the API key
+/// it is made with, if any, and reserves them. The budget is checked once the whole prompt is
+/// known, before the models are called.
+pub fn reserve_tokens(
+    state: &AppState,
+    api_key: Option<&ApiKey>,
+    models: &[ChatModel],
+    prompt_tokens: u64,
+) -> Result<Option<Reservation>, ChatError> {
+    api_key
+        .map(|key| state.api_keys.reserve(key, models, prompt_tokens))
+        .transpose()
+        .map_err(ChatError::Quota)
+}
+
+/// Checks the prompt may run, then answers it in the background. The answer is stored in its
+/// conversation once complete, whether or not anyone still follows the returned log.
+pub async fn start(
+    state: &AppState,
+    user: &User,
+    api_key: Option<ApiKey>,
+    data: PostMessage,
+    uploads: Vec<Upload>,
+) -> Result<Arc<GenerationLog>, ChatError> {
+    if !state.store.can_access(data.conversation, user) {
+        return Err(ChatError::NotFound);
+    }
+
+    let span = tracing::Span::current();
+    span.record(
+        "conversation_id",
+        tracing::field::display(data.conversation),
+    );
+    span.record("model", tracing::field::display(data.model));
+
+    let schema = parse_schema(&data.schema)?;
+    admit_key(state, api_key.as_ref(), &[data.model])?;
+    let permit = acquire_slots(state, user, &[data.model])?;
+
+    let attachments = {
+        let store = state.attachments.clone();
+        tokio::task::spawn_blocking(move || {
+            uploads
+                .iter()
+                .filter_map(|upload| {
+                    store
+                        .save(&upload.name, &upload.content_type, &upload.data)
+                        .map_err(|e| tracing::error!("Could not store attachment: {}", e))
+                        .ok()
+                })
+                .collect::<Vec<Attachment>>()
+        })
+        .await
+        .expect("Failed to join attachment task")
+    };
+
+    let mut message = Message::new(data.prompt.clone(), data.model);
+    message.attachments = attachments;
+    let shared = state
+        .store
+        .get(data.conversation)
+        .is_some_and(|c| !c.members.is_empty());
+    if shared {
+        message.author = Some(user.username.clone());
+    }
+
+    let images = message
+        .attachments
+        .iter()
+        .filter(|a| a.is_image())
+        .filter_map(|a| {
+            state
+                .attachments
+                .data_url(a)
+                .map_err(|e| tracing::error!("Could not read attachment: {}", e))
+                .ok()
+        })
+        .collect();
+
+    let conversation = state.store.get(data.conversation);
+    let mut messages = conversation
+        .as_ref()
+        .map(|c| c.history())
+        .unwrap_or_default();
+    messages.push(ChatMessage::user_with_images(
+        message.prompt_with_attachments(),
+        images,
+    ));
+
+    let citations = {
+        let knowledge = state.knowledge.clone();
+        let prompt = data.prompt.clone();
+        let conversation = data.conversation;
+        tokio::task::spawn_blocking(move || knowledge.search(&prompt, conversation, CONTEXT_CHUNKS))
+            .await
+            .expect("Failed to join retrieval task")
+            .unwrap_or_else(|e| {
+                tracing::error!("Knowledge: Could not retrieve context: {}", e);
+                vec![]
+            })
+    };
+    if !citations.is_empty() {
+        messages.insert(0, ChatMessage::system(context_prompt(&citations)));
+    }
+
+    let summary = conversation.as_ref().and_then(RollingSummary::of);
+    let fitted = context::fit(
+        messages,
+        data.model,
+        &state.tokens,
+        &state.context,
+        summary.as_ref(),
+    );
+    let messages = fitted.messages;
+    message.context_trimmed = fitted.trimmed;
+    message.context_summarized = fitted.summarized;
+
+    let prompt_tokens = tokens::estimate_messages(&messages);
+    let reservation = reserve_tokens(state, api_key.as_ref(), &[data.model], prompt_tokens)?;
+
+    let (tool_tx, tool_rx) = channel::<ToolInvocation>(16);
+    let tools = data.tools.then(|| Toolbox {
+        registry: ToolRegistry::builtin(state.store.clone(), user.id, state.fetch_base_url.clone()),
+        invocations: tool_tx,
+    });
+
+    // Answers calling tools depend on what the tools return, they are not reused.
+    let cache_key = (state.cache.is_enabled() && tools.is_none())
+        .then(|| CacheKey::new(user.id, data.model, schema.as_ref(), &messages));
+    let cached = cache_key.as_ref().and_then(|key| state.cache.get(key));
+    let pipeline = pipeline(&state.models, data.model, tools, schema.clone());
+
+    let stream_guard = state.shutdown.track_stream();
+    let generation = Arc::new(Mutex::new(Generation::start(data.model)));
+    message.cached = cached.is_some();
+    // Answers are cached once the model ended them by itself, unless cancelled or interrupted.
+    let (complete_tx, mut complete) = oneshot::channel();
+    let (cacheable_tx, cacheable) = oneshot::channel();
+    let rx = match (cached, cache_key) {
+        (Some(answer), _) => state.cache.replay(answer),
+        (None, Some(key)) => {
+            let chunks = pipeline.stream(messages, complete_tx);
+            state.cache.record(key, data.model, chunks, cacheable)
+        }
+        (None, None) => pipeline.stream(messages, complete_tx),
+    };
+    message.schema = schema;
+    message.citations = citations;
+    let log = state.generations.create(message.id, user.id);
+
+    let start_update = once({
+        let message = Arc::new(message.clone());
+        async move { Update::Start(message) }
+    });
+
+    // Collects the answer as it streams so it can be stored once complete.
+    let message = Arc::new(Mutex::new(message));
+
+    let rx_stream = rx.map({
+        let message = message.clone();
+        let generation = generation.clone();
+        move |word| {
+            generation.lock().unwrap().chunk();
+            message.lock().unwrap().response.push_str(&word);
+            Update::Chunk(word)
+        }
+    });
+
+    let tool_stream = ReceiverStream::new(tool_rx).map({
+        let message = message.clone();
+        move |invocation| {
+            message.lock().unwrap().tools.push(invocation.clone());
+            Update::Tool(invocation)
+        }
+    });
+
+    // On shutdown, answers still streaming past the deadline are cut short and stored as is.
+    // Cancelled answers too.
+    let interrupted = state.shutdown.interrupted().map({
+        let message = message.clone();
+        move |_| message.lock().unwrap().interrupted = true
+    });
+    let cancelled = log.cancelled().map({
+        let message = message.clone();
+        move |_| message.lock().unwrap().cancelled = true
+    });
+    let stop = async move {
+        tokio::select! {
+            _ = interrupted => {},
+            _ = cancelled => {},
+        }
+    };
+    let answer_stream = futures::StreamExt::take_until(rx_stream.merge(tool_stream), stop);
+
+    // Checks the assembled answer once streaming is over.
+    let validation_update = once({
+        let message = message.clone();
+        async move {
+            let mut message = message.lock().unwrap();
+            let violations = schema::validate(message.schema.as_ref()?, &message.response);
+            message.violations = Some(violations.clone());
+            Some(Update::Validation(violations))
+        }
+    })
+    .filter_map(|update| update);
+
+    let store = state.store.clone();
+    let api_keys = state.api_keys.clone();
+    let summarizer = state.summarizer.clone();
+    let owner = user.id;
+    let end_update = once(async move {
+        drop(permit);
+        let message = message.lock().unwrap().clone();
+        if complete.try_recv().is_ok() && !message.cancelled && !message.interrupted {
+            let _ = cacheable_tx.send(());
+        }
+        generation
+            .lock()
+            .unwrap()
+            .finish(tokens::estimate(&message.response));
+        if let Some(reservation) = reservation {
+            let tokens = prompt_tokens + tokens::estimate(&message.response);
+            if let Err(e) = api_keys.record_usage(reservation, tokens) {
+                tracing::error!("Could not record API key usage: {}", e);
+            }
+        }
+        match store.push_message(data.conversation, owner, message.clone()) {
+            Ok(()) => summarizer.schedule(data.conversation),
+            Err(e) => tracing::error!("Could not store message: {}", e),
+        }
+        // Only counts as drained once stored.
+        drop(stream_guard);
+        Update::End(Arc::new(message))
+    });
+
+    let updates = start_update
+        .chain(answer_stream)
+        .chain(validation_update)
+        .chain(end_update);
+    state.generations.record(&log, updates);
+    // Only the poster's own pages are spared the answer, they follow it already.
+    let origin = data
+        .viewer
+        .filter(|viewer| state.rooms.is_viewer(data.conversation, *viewer, user.id));
+    state.rooms.forward(data.conversation, origin, &log);
+
+    Ok(log)
+}
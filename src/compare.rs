@@ -0,0 +1,304 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use futures::{FutureExt as _, StreamExt as _};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+
+use crate::auth::{keys::ApiKey, User};
+use crate::chat::{self, ChatError, PostMessage};
+use crate::context::{self, RollingSummary};
+use crate::metrics::Generation;
+use crate::models::{ChatMessage, ChatModel};
+use crate::state::AppState;
+use crate::utils::tokens;
+
+/// One model's answer to a compared prompt.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Column {
+    pub id: Uuid,
+    pub model: ChatModel,
+    pub response: String,
+    /// Milliseconds until the first chunk, unset when nothing was answered.
+    pub time_to_first_token_ms: Option<u64>,
+    /// Milliseconds until the answer was complete.
+    pub duration_ms: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Set when the server stopped before the answer was complete.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+}
+
+/// A prompt answered by several models side by side.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Comparison {
+    pub id: Uuid,
+    pub owner: Uuid,
+    pub prompt: String,
+    pub columns: Vec<Column>,
+    /// Column the user preferred, if they voted.
+    #[serde(default)]
+    pub preferred: Option<Uuid>,
+    /// Exchanges of the conversation left out to fit the smallest context window.
+    #[serde(default)]
+    pub context_trimmed: usize,
+    /// Set when the exchanges left out were replaced by the conversation summary.
+    #[serde(default)]
+    pub context_summarized: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Comparison {
+    pub fn is_preferred(&self, column: &Column) -> bool {
+        self.preferred == Some(column.id)
+    }
+}
+
+/// What happened in a comparison, in the order it happened.
+#[derive(Debug, Clone)]
+pub enum CompareUpdate {
+    Chunk {
+        column: Uuid,
+        text: String,
+    },
+    /// A column is complete.
+    Done(Column),
+    /// Every column is complete and the comparison was stored.
+    End(Comparison),
+}
+
+/// Comparisons kept in memory and mirrored to one JSON file each on disk, for later analysis.
+#[derive(Clone)]
+pub struct ComparisonStore {
+    dir: PathBuf,
+    comparisons: Arc<RwLock<HashMap<Uuid, Comparison>>>,
+}
+
+impl ComparisonStore {
+    pub fn open(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().join("comparisons");
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+
+        let mut comparisons = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let comparison = fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|data| Ok(serde_json::from_slice::<Comparison>(&data)?));
+
+            match comparison {
+                Ok(c) => {
+                    comparisons.insert(c.id, c);
+                }
+                Err(e) => tracing::error!("Comparisons: Could not load {:?}: {}", path, e),
+            }
+        }
+
+        Ok(Self {
+            dir,
+            comparisons: Arc::new(RwLock::new(comparisons)),
+        })
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<Comparison> {
+        self.comparisons.read().unwrap().get(&id).cloned()
+    }
+
+    /// Returns every comparison, oldest first.
+    pub fn list(&self) -> Vec<Comparison> {
+        let mut comparisons: Vec<_> = self.comparisons.read().unwrap().values().cloned().collect();
+        comparisons.sort_by_key(|c| c.created_at);
+        comparisons
+    }
+
+    pub fn insert(&self, comparison: Comparison) -> anyhow::Result<()> {
+        self.write(&comparison)?;
+        self.comparisons
+            .write()
+            .unwrap()
+            .insert(comparison.id, comparison);
+        Ok(())
+    }
+
+    /// Records the column a user preferred, replacing any previous vote.
+    pub fn set_preferred(&self, id: Uuid, column: Uuid) -> anyhow::Result<Comparison> {
+        let comparison = {
+            let mut comparisons = self.comparisons.write().unwrap();
+            let comparison = comparisons
+                .get_mut(&id)
+                .with_context(|| format!("No comparison {}", id))?;
+            if !comparison.columns.iter().any(|c| c.id == column) {
+                bail!("No column {} in comparison {}", column, id);
+            }
+            comparison.preferred = Some(column);
+            comparison.clone()
+        };
+
+        self.write(&comparison)?;
+        Ok(comparison)
+    }
+
+    fn write(&self, comparison: &Comparison) -> anyhow::Result<()> {
+        let path = self.dir.join(format!("{}.json", comparison.id));
+        fs::write(&path, serde_json::to_vec_pretty(comparison)?)
+            .with_context(|| format!("Failed to write {:?}", path))
+    }
+}
+
+/// Answers a prompt with each model concurrently, in the context of its conversation. The
+/// comparison is stored once every column is complete, whether or not anyone still follows it.
+///
+/// Answers are not added to the conversation.
+pub async fn start(
+    state: &AppState,
+    user: &User,
+    api_key: Option<ApiKey>,
+    data: PostMessage,
+    models: Vec<ChatModel>,
+) -> Result<(Comparison, ReceiverStream<CompareUpdate>), ChatError> {
+    if !state.store.can_access(data.conversation, user) {
+        return Err(ChatError::NotFound);
+    }
+
+    let schema = chat::parse_schema(&data.schema)?;
+    chat::admit_key(state, api_key.as_ref(), &models)?;
+
+    // One slot per model, held until its column is complete.
+    let permits = chat::acquire_slots(state, user, &models)?;
+
+    let conversation = state.store.get(data.conversation);
+    let mut messages = conversation
+        .as_ref()
+        .map(|c| c.history())
+        .unwrap_or_default();
+    messages.push(ChatMessage::user(data.prompt.clone()));
+    // Every model gets the same history, fitted to the smallest context window.
+    let summary = conversation.as_ref().and_then(RollingSummary::of);
+    let (mut context_trimmed, mut context_summarized) = (0, false);
+    if let Some(model) = models.iter().min_by_key(|m| state.context.window(**m)) {
+        let fitted = context::fit(
+            messages,
+            *model,
+            &state.tokens,
+            &state.context,
+            summary.as_ref(),
+        );
+        messages = fitted.messages;
+        (context_trimmed, context_summarized) = (fitted.trimmed, fitted.summarized);
+    }
+
+    let prompt_tokens = tokens::estimate_messages(&messages);
+    // Every model spends the prompt, all of them are reserved at once.
+    let reservation = chat::reserve_tokens(state, api_key.as_ref(), &models, prompt_tokens)?;
+
+    let comparison = Comparison {
+        id: Uuid::new_v4(),
+        owner: user.id,
+        prompt: data.prompt,
+        columns: models
+            .iter()
+            .map(|model| Column {
+                id: Uuid::new_v4(),
+                model: *model,
+                response: "".into(),
+                time_to_first_token_ms: None,
+                duration_ms: 0,
+                prompt_tokens,
+                completion_tokens: 0,
+                interrupted: false,
+            })
+            .collect(),
+        preferred: None,
+        context_trimmed,
+        context_summarized,
+        created_at: Utc::now(),
+    };
+
+    let (tx, rx) = mpsc::channel(64);
+    let columns = comparison
+        .columns
+        .iter()
+        .cloned()
+        .zip(permits)
+        .map(|(column, permit)| {
+            let generation = Generation::start(column.model);
+            let started_at = Instant::now();
+            let chunks = chat::pipeline(&state.models, column.model, None, schema.clone())
+                .run(messages.clone());
+            let interrupted = state.shutdown.interrupted().shared();
+            let stream_guard = state.shutdown.track_stream();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let mut column = column;
+                let mut generation = generation;
+                let mut chunks = std::pin::pin!(chunks.take_until(interrupted.clone()));
+
+                while let Some(text) = chunks.next().await {
+                    generation.chunk();
+                    column
+                        .time_to_first_token_ms
+                        .get_or_insert(started_at.elapsed().as_millis() as u64);
+                    column.response.push_str(&text);
+                    // The client may be gone, the column completes all the same.
+                    let _ = tx
+                        .send(CompareUpdate::Chunk {
+                            column: column.id,
+                            text,
+                        })
+                        .await;
+                }
+
+                drop(permit);
+                column.interrupted = interrupted.now_or_never().is_some();
+                column.duration_ms = started_at.elapsed().as_millis() as u64;
+                column.completion_tokens = tokens::estimate(&column.response);
+                generation.finish(column.completion_tokens);
+                let _ = tx.send(CompareUpdate::Done(column.clone())).await;
+                drop(stream_guard);
+                column
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let comparisons = state.comparisons.clone();
+    let api_keys = state.api_keys.clone();
+    let mut stored = comparison.clone();
+    tokio::spawn(async move {
+        stored.columns = futures::future::join_all(columns)
+            .await
+            .into_iter()
+            .map(|column| column.expect("Failed to join comparison column"))
+            .collect();
+
+        if let Some(reservation) = reservation {
+            let tokens = stored
+                .columns
+                .iter()
+                .map(|c| c.prompt_tokens + c.completion_tokens)
+                .sum();
+            if let Err(e) = api_keys.record_usage(reservation, tokens) {
+                tracing::error!("Could not record API key usage: {}", e);
+            }
+        }
+        if let Err(e) = comparisons.insert(stored.clone()) {
+            tracing::error!("Could not store comparison: {}", e);
+        }
+        let _ = tx.send(CompareUpdate::End(stored)).await;
+    });
+
+    Ok((comparison, ReceiverStream::new(rx)))
+}
@@ -0,0 +1,29 @@
+use crate::cache::CacheConfig;
+use crate::context::ContextConfig;
+use crate::models::ModelConfig;
+use crate::summaries::SummaryConfig;
+
+/// Settings of the app. The server reads them from the environment once, when it starts.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub cache: CacheConfig,
+    pub context: ContextConfig,
+    pub summaries: SummaryConfig,
+    pub models: ModelConfig,
+    /// Host the fetch tool sends every request to instead, e.g. a local stand-in.
+    pub fetch_base_url: Option<String>,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            cache: CacheConfig::from_env(),
+            context: ContextConfig::from_env(),
+            summaries: SummaryConfig::from_env(),
+            models: ModelConfig::from_env(),
+            fetch_base_url: std::env::var("FETCH_TOOL_BASE_URL")
+                .ok()
+                .filter(|v| !v.trim().is_empty()),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::{collections::HashMap, str::FromStr};
+
+use crate::models::{ChatMessage, ChatModel, Role};
+use crate::store::Conversation;
+use crate::utils::{env_or, tokens::TokenCounter};
+
+/// What to do with conversation history that doesn't fit a model's context window. System
+/// messages and the prompt are always sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContextStrategy {
+    /// The oldest exchanges are left out until the rest fits.
+    #[default]
+    DropOldest,
+    /// Only the last exchanges are sent, fewer if they still don't fit.
+    KeepLast(usize),
+    /// The oldest exchanges are replaced by the conversation's rolling summary, when it has one.
+    Summarize,
+}
+
+impl FromStr for ContextStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "drop-oldest" => Ok(Self::DropOldest),
+            None if s == "summarize" => Ok(Self::Summarize),
+            Some(("keep-last", n)) => n
+                .parse()
+                .map(Self::KeepLast)
+                .map_err(|_| anyhow::anyhow!("Invalid exchange count `{}` in `{}`", n, s)),
+            _ => Err(anyhow::anyhow!("Unknown context strategy `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ContextConfig {
+    pub strategy: ContextStrategy,
+    /// Part of the context window left for the answer.
+    pub answer_tokens: u64,
+    /// Context windows of the models, in place of their own.
+    pub windows: HashMap<ChatModel, u64>,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            strategy: ContextStrategy::default(),
+            answer_tokens: 1024,
+            windows: HashMap::new(),
+        }
+    }
+}
+
+impl ContextConfig {
+    /// Reads `CRABOT_CONTEXT_STRATEGY` (`drop-oldest`, `keep-last:<exchanges>` or `summarize`),
+    /// `CRABOT_ANSWER_TOKENS` and the `CRABOT_CONTEXT_WINDOW_<MODEL>` overrides.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let strategy = match std::env::var("CRABOT_CONTEXT_STRATEGY") {
+            Ok(s) if !s.trim().is_empty() => s.trim().parse().unwrap_or_else(|e| {
+                tracing::warn!("CRABOT_CONTEXT_STRATEGY: {}", e);
+                defaults.strategy
+            }),
+            _ => defaults.strategy,
+        };
+
+        Self {
+            strategy,
+            answer_tokens: env_or("CRABOT_ANSWER_TOKENS", defaults.answer_tokens),
+            windows: ChatModel::ALL
+                .into_iter()
+                .filter_map(|model| {
+                    let name =
+                        format!("CRABOT_CONTEXT_WINDOW_{}", model.to_string().to_uppercase());
+                    let window = std::env::var(name).ok()?.parse().ok()?;
+                    Some((model, window))
+                })
+                .collect(),
+        }
+    }
+
+    /// Tokens a model reads at once, prompt and answer together.
+    pub fn window(&self, model: ChatModel) -> u64 {
+        self.windows
+            .get(&model)
+            .copied()
+            .unwrap_or_else(|| model.context_window())
+    }
+}
+
+/// The rolling summary of a conversation, standing in for its first exchanges.
+#[derive(Debug, Clone)]
+pub struct RollingSummary {
+    pub text: String,
+    /// Exchanges the summary covers.
+    pub exchanges: usize,
+}
+
+impl RollingSummary {
+    pub fn of(conversation: &Conversation) -> Option<Self> {
+        let text = conversation.summary.clone()?;
+        (conversation.summarized > 0).then_some(Self {
+            text,
+            exchanges: conversation.summarized,
+        })
+    }
+}
+
+/// A prompt fitted to a context window.
+#[derive(Debug)]
+pub struct Fitted {
+    pub messages: Vec<ChatMessage>,
+    /// Exchanges of the history left out.
+    pub trimmed: usize,
+    /// Set when the exchanges left out were replaced by a summary.
+    pub summarized: bool,
+}
+
+/// Trims the history of a prompt so it fits the model's context window with room for the
+/// answer. Whole exchanges are left out, oldest first. When summarizing, the conversation's
+/// rolling summary replaces the exchanges it covers, and without one they are just left out.
+pub fn fit(
+    messages: Vec<ChatMessage>,
+    model: ChatModel,
+    counter: &TokenCounter,
+    config: &ContextConfig,
+    summary: Option<&RollingSummary>,
+) -> Fitted {
+    let system_count = messages
+        .iter()
+        .take_while(|m| m.role == Role::System)
+        .count();
+    if messages.len() <= system_count + 1 {
+        return Fitted {
+            messages,
+            trimmed: 0,
+            summarized: false,
+        };
+    }
+
+    let mut messages = messages;
+    let prompt = messages.pop().expect("The prompt is the last message");
+    let history = messages.split_off(system_count);
+    let system = messages;
+
+    // An exchange is a user message and whatever answered it.
+    let mut exchanges: Vec<Vec<ChatMessage>> = vec![];
+    for message in history {
+        match exchanges.last_mut() {
+            Some(exchange) if message.role != Role::User => exchange.push(message),
+            _ => exchanges.push(vec![message]),
+        }
+    }
+    let costs: Vec<u64> = exchanges
+        .iter()
+        .map(|e| counter.count_messages(e))
+        .collect();
+
+    let budget = config.window(model).saturating_sub(config.answer_tokens);
+    let fixed =
+        counter.count_messages(&system) + counter.count_messages(std::slice::from_ref(&prompt));
+    let trim = |mut trimmed: usize, fixed: u64| {
+        while trimmed < exchanges.len() && fixed + costs[trimmed..].iter().sum::<u64>() > budget {
+            trimmed += 1;
+        }
+        trimmed
+    };
+    let mut trimmed = trim(
+        match config.strategy {
+            ContextStrategy::KeepLast(n) => exchanges.len().saturating_sub(n),
+            _ => 0,
+        },
+        fixed,
+    );
+
+    let mut summary_message = None;
+    if let (ContextStrategy::Summarize, Some(summary), true) =
+        (config.strategy, summary, trimmed > 0)
+    {
+        let message = ChatMessage::system(format!("Summary of earlier messages: {}", summary.text));
+        let fixed = fixed + counter.count_messages(std::slice::from_ref(&message));
+        // Summaries too long to fit are left out too.
+        if fixed <= budget {
+            trimmed = trim(trimmed.max(summary.exchanges.min(exchanges.len())), fixed);
+            summary_message = Some(message);
+        }
+    }
+
+    let kept = exchanges.split_off(trimmed);
+    let summarized = summary_message.is_some();
+    let mut fitted = system;
+    fitted.extend(summary_message);
+    fitted.extend(kept.into_iter().flatten());
+    fitted.push(prompt);
+
+    if trimmed > 0 {
+        tracing::info!(
+            trimmed,
+            summarized,
+            "Context: History trimmed to fit {}",
+            model
+        );
+    }
+    Fitted {
+        messages: fitted,
+        trimmed,
+        summarized,
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::models::ChatModel;
+use crate::store::{Conversation, Message};
+
+/// Identifies files produced by [`to_json`].
+const FORMAT: &str = "crabot.conversation";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Md,
+    Jsonl,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Md => "md",
+            Self::Jsonl => "jsonl",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Md => "text/markdown; charset=utf-8",
+            Self::Jsonl => "application/jsonl",
+        }
+    }
+
+    pub fn export(&self, conversation: &Conversation) -> Result<String> {
+        match self {
+            Self::Json => to_json(conversation),
+            Self::Md => Ok(to_markdown(conversation)),
+            Self::Jsonl => to_jsonl(conversation),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Export {
+    format: String,
+    version: u32,
+    conversation: Conversation,
+}
+
+/// Lossless export, the only format [`import`] reads back as is.
+pub fn to_json(conversation: &Conversation) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&Export {
+        format: FORMAT.into(),
+        version: VERSION,
+        conversation: conversation.clone(),
+    })?)
+}
+
+/// Human readable transcript.
+pub fn to_markdown(conversation: &Conversation) -> String {
+    let title = conversation
+        .title
+        .clone()
+        .unwrap_or_else(|| format!("Conversation of {}", conversation.created_at.date_naive()));
+    let mut md = format!("# {}\n", title);
+
+    for message in conversation.messages.iter() {
+        let author = message.author.as_deref().unwrap_or("You");
+        md.push_str(&format!("\n## {}\n\n{}\n", author, message.prompt));
+        for attachment in message.attachments.iter() {
+            md.push_str(&format!("\n- Attachment: `{}`\n", attachment.name));
+        }
+        md.push_str(&format!(
+            "\n## Crabot ({})\n\n{}\n",
+            message.model, message.response
+        ));
+        if message.interrupted {
+            md.push_str("\n_Interrupted by a server restart._\n");
+        }
+    }
+
+    md
+}
+
+/// One line in the OpenAI fine-tuning chat format.
+pub fn to_jsonl(conversation: &Conversation) -> Result<String> {
+    let messages: Vec<_> = conversation
+        .messages
+        .iter()
+        .flat_map(|m| {
+            [
+                json!({ "role": "user", "content": m.prompt_with_attachments() }),
+                json!({ "role": "assistant", "content": m.response }),
+            ]
+        })
+        .collect();
+
+    Ok(format!(
+        "{}\n",
+        serde_json::to_string(&json!({ "messages": messages }))?
+    ))
+}
+
+/// Reads either a [`to_json`] export or a ChatGPT `conversations.json` export.
+pub fn import(data: &[u8]) -> Result<Vec<Conversation>> {
+    let value: serde_json::Value = serde_json::from_slice(data).context("Invalid JSON")?;
+
+    if value.is_array() {
+        let conversations: Vec<ChatGPTConversation> =
+            serde_json::from_value(value).context("Not a ChatGPT conversations.json export")?;
+        return Ok(conversations.into_iter().map(Conversation::from).collect());
+    }
+
+    let export: Export = serde_json::from_value(value).context("Not a crabot export")?;
+    if export.format != FORMAT {
+        bail!("Unknown format `{}`", export.format);
+    }
+    if export.version > VERSION {
+        bail!("Unsupported export version {}", export.version);
+    }
+
+    Ok(vec![export.conversation])
+}
+
+#[derive(Deserialize)]
+struct ChatGPTConversation {
+    title: Option<String>,
+    create_time: Option<f64>,
+    current_node: Option<String>,
+    mapping: HashMap<String, ChatGPTNode>,
+}
+
+#[derive(Deserialize)]
+struct ChatGPTNode {
+    message: Option<ChatGPTMessage>,
+    parent: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatGPTMessage {
+    author: ChatGPTAuthor,
+    content: ChatGPTContent,
+    create_time: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct ChatGPTAuthor {
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct ChatGPTContent {
+    #[serde(default)]
+    parts: Vec<serde_json::Value>,
+}
+
+fn timestamp(seconds: Option<f64>) -> DateTime<Utc> {
+    seconds
+        .and_then(|s| Utc.timestamp_millis_opt((s * 1000.) as i64).single())
+        .unwrap_or_else(Utc::now)
+}
+
+impl From<ChatGPTConversation> for Conversation {
+    fn from(c: ChatGPTConversation) -> Self {
+        // Messages form a tree (one branch per edit or regeneration), follow the branch that was
+        // displayed last, from its leaf back to the root.
+        let mut branch = vec![];
+        let mut node_id = c.current_node.clone();
+        while let Some(node) = node_id.as_ref().and_then(|id| c.mapping.get(id)) {
+            if let Some(message) = &node.message {
+                branch.push(message);
+            }
+            node_id = node.parent.clone();
+        }
+        branch.reverse();
+
+        let mut messages: Vec<Message> = vec![];
+        for message in branch {
+            let text = message
+                .content
+                .parts
+                .iter()
+                .filter_map(|p| p.as_str())
+                .collect::<Vec<_>>()
+                .join("\n");
+            if text.is_empty() {
+                continue;
+            }
+
+            match message.author.role.as_str() {
+                "user" => {
+                    let mut m = Message::new(text, ChatModel::GPT3);
+                    m.created_at = timestamp(message.create_time);
+                    messages.push(m);
+                }
+                "assistant" => match messages.last_mut() {
+                    Some(m) if m.response.is_empty() => m.response = text,
+                    Some(m) => m.response.push_str(&format!("\n\n{}", text)),
+                    None => (),
+                },
+                _ => (),
+            }
+        }
+
+        Conversation {
+            id: Uuid::new_v4(),
+            title: c.title,
+            owner: None,
+            members: vec![],
+            summary: None,
+            summarized: 0,
+            created_at: timestamp(c.create_time),
+            messages,
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{stream, Stream, StreamExt};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::store::Message;
+use crate::tools::ToolInvocation;
+
+/// How long a completed generation can still be replayed.
+const RETENTION: Duration = Duration::from_secs(5 * 60);
+
+/// What happened in a generation, rendered by each transport in its own way.
+#[derive(Debug, Clone)]
+pub enum Update {
+    /// The prompt was accepted, the answer is empty so far.
+    Start(Arc<Message>),
+    Chunk(String),
+    Tool(ToolInvocation),
+    /// Schema violations of the complete answer.
+    Validation(Vec<String>),
+    /// The answer, as stored.
+    End(Arc<Message>),
+}
+
+/// An update, numbered from 0 in the order it was emitted.
+#[derive(Debug, Clone)]
+pub struct GenerationEvent {
+    pub id: u64,
+    pub update: Update,
+}
+
+#[derive(Default)]
+struct Log {
+    events: Vec<GenerationEvent>,
+    done: bool,
+}
+
+/// Events of one generation, kept so clients can resume after losing their connection.
+pub struct GenerationLog {
+    /// Id of the message being answered.
+    pub id: Uuid,
+    pub owner: Uuid,
+    log: watch::Sender<Log>,
+    cancelled: watch::Sender<bool>,
+}
+
+impl GenerationLog {
+    fn push(&self, update: Update) {
+        self.log.send_modify(|log| {
+            let id = log.events.len() as u64;
+            log.events.push(GenerationEvent { id, update });
+        });
+    }
+
+    fn finish(&self) {
+        self.log.send_modify(|log| log.done = true);
+    }
+
+    /// Stops the answer, which is stored as it is.
+    pub fn cancel(&self) {
+        self.cancelled.send_replace(true);
+    }
+
+    /// Resolves once [`cancel`](Self::cancel) is called.
+    pub fn cancelled(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut cancelled = self.cancelled.subscribe();
+        async move {
+            let _ = cancelled.wait_for(|c| *c).await;
+        }
+    }
+
+    /// Events following `after` (all of them when `None`), then live ones until the generation
+    /// completes.
+    pub fn events(&self, after: Option<u64>) -> impl Stream<Item = GenerationEvent> {
+        let next = after.map_or(0, |id| id as usize + 1);
+        let rx = self.log.subscribe();
+
+        stream::unfold((next, rx), |(next, mut rx)| async move {
+            loop {
+                let (event, done) = {
+                    let log = rx.borrow_and_update();
+                    (log.events.get(next).cloned(), log.done)
+                };
+                if let Some(event) = event {
+                    return Some((event, (next + 1, rx)));
+                }
+                if done || rx.changed().await.is_err() {
+                    return None;
+                }
+            }
+        })
+    }
+}
+
+/// Generations in progress or recently completed, by message id.
+#[derive(Clone, Default)]
+pub struct Generations {
+    logs: Arc<Mutex<HashMap<Uuid, Arc<GenerationLog>>>>,
+}
+
+impl Generations {
+    pub fn get(&self, id: Uuid) -> Option<Arc<GenerationLog>> {
+        self.logs.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Registers the generation answering a message, before its updates are known.
+    pub fn create(&self, id: Uuid, owner: Uuid) -> Arc<GenerationLog> {
+        let log = Arc::new(GenerationLog {
+            id,
+            owner,
+            log: watch::channel(Log::default()).0,
+            cancelled: watch::channel(false).0,
+        });
+        self.logs.lock().unwrap().insert(id, log.clone());
+        log
+    }
+
+    /// Records `updates` from a task of its own, so the generation completes and gets stored
+    /// even when its client goes away.
+    pub fn record<S>(&self, log: &Arc<GenerationLog>, updates: S)
+    where
+        S: Stream<Item = Update> + Send + 'static,
+    {
+        let logs = self.logs.clone();
+        let log = log.clone();
+        tokio::spawn(async move {
+            let mut updates = std::pin::pin!(updates);
+            while let Some(update) = updates.next().await {
+                log.push(update);
+            }
+            log.finish();
+
+            tokio::time::sleep(RETENTION).await;
+            logs.lock().unwrap().remove(&log.id);
+        });
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::{Error as E, Result};
+use candle_core::{Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use hf_hub::{api::sync::Api, Repo, RepoType};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+use crate::metrics::metrics;
+
+const MODEL_ID: &str = "sentence-transformers/all-MiniLM-L6-v2";
+const REVISION: &str = "refs/pr/21";
+/// Tokens beyond this length are ignored when embedding a chunk.
+const MAX_TOKENS: usize = 256;
+
+/// Sentence embeddings computed locally with a small BERT model.
+pub struct Embedder {
+    model: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+}
+
+impl Embedder {
+    pub fn load() -> Result<Self> {
+        let start = std::time::Instant::now();
+        let api = Api::new()?;
+        let repo = api.repo(Repo::with_revision(
+            MODEL_ID.to_string(),
+            RepoType::Model,
+            REVISION.to_string(),
+        ));
+
+        let config: Config = serde_json::from_slice(&std::fs::read(repo.get("config.json")?)?)?;
+        let mut tokenizer = Tokenizer::from_file(repo.get("tokenizer.json")?).map_err(E::msg)?;
+        tokenizer.with_padding(Some(PaddingParams::default()));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_TOKENS,
+                ..Default::default()
+            }))
+            .map_err(E::msg)?;
+
+        let device = Device::Cpu;
+        let weights = repo.get("model.safetensors")?;
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DTYPE, &device)? };
+        let model = BertModel::load(vb, &config)?;
+        tracing::info!("Loaded embedding model in {:?}", start.elapsed());
+        metrics().model_loaded(MODEL_ID, start.elapsed());
+
+        Ok(Self {
+            model,
+            tokenizer,
+            device,
+        })
+    }
+
+    /// Returns one L2-normalized vector per text.
+    pub fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(E::msg)?;
+
+        let ids = encodings
+            .iter()
+            .map(|e| Tensor::new(e.get_ids(), &self.device))
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        let masks = encodings
+            .iter()
+            .map(|e| Tensor::new(e.get_attention_mask(), &self.device))
+            .collect::<candle_core::Result<Vec<_>>>()?;
+
+        let ids = Tensor::stack(&ids, 0)?;
+        let mask = Tensor::stack(&masks, 0)?;
+        let token_type_ids = ids.zeros_like()?;
+        let hidden = self.model.forward(&ids, &token_type_ids, Some(&mask))?;
+
+        // Mean pooling over non-padding tokens.
+        let mask = mask.to_dtype(DTYPE)?.unsqueeze(2)?;
+        let pooled = hidden
+            .broadcast_mul(&mask)?
+            .sum(1)?
+            .broadcast_div(&mask.sum(1)?)?;
+        let norm = pooled.sqr()?.sum_keepdim(1)?.sqrt()?;
+
+        Ok(pooled.broadcast_div(&norm)?.to_vec2::<f32>()?)
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use embedding::Embedder;
+
+pub mod embedding;
+
+/// Number of words per chunk, and how many of them are repeated in the next chunk.
+const CHUNK_WORDS: usize = 200;
+const CHUNK_OVERLAP: usize = 40;
+const EMBEDDING_BATCH_SIZE: usize = 16;
+/// Chunks scoring below this cosine similarity are never used as context.
+const MIN_SCORE: f32 = 0.25;
+
+/// Whether the embedding model is loaded at startup rather than on the first upload, set
+/// `CRABOT_PRELOAD_EMBEDDINGS=false` to skip it.
+pub fn preload_enabled() -> bool {
+    std::env::var("CRABOT_PRELOAD_EMBEDDINGS").map_or(true, |v| v != "false" && v != "0")
+}
+
+/// An uploaded file. Documents without a conversation are shared by all conversations.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Document {
+    pub id: Uuid,
+    pub name: String,
+    pub conversation: Option<Uuid>,
+    pub chunks: usize,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Chunk {
+    document: Uuid,
+    text: String,
+    embedding: Vec<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct Index {
+    documents: Vec<Document>,
+    chunks: Vec<Chunk>,
+}
+
+/// A chunk of a document retrieved as context for a prompt.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Citation {
+    pub document: Uuid,
+    pub name: String,
+    pub excerpt: String,
+    pub score: f32,
+}
+
+/// Uploaded documents, split in chunks and indexed by embedding.
+///
+/// The index is a flat list searched exhaustively, persisted as a single JSON file. Methods
+/// embedding text are blocking and should be called from `spawn_blocking`.
+#[derive(Clone)]
+pub struct KnowledgeBase {
+    path: PathBuf,
+    index: Arc<RwLock<Index>>,
+    embedder: Arc<Mutex<Option<Arc<Embedder>>>>,
+}
+
+impl KnowledgeBase {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let path = dir.as_ref().join("knowledge.json");
+        let index = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("Failed to parse {:?}", path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Index::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+
+        Ok(Self {
+            path,
+            index: Arc::new(RwLock::new(index)),
+            embedder: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Loads the embedding model on first use.
+    fn embedder(&self) -> Result<Arc<Embedder>> {
+        let mut embedder = self.embedder.lock().unwrap();
+        if let Some(e) = embedder.as_ref() {
+            return Ok(e.clone());
+        }
+
+        let e = Arc::new(Embedder::load()?);
+        *embedder = Some(e.clone());
+        Ok(e)
+    }
+
+    /// Loads the embedding model ahead of the first upload. Blocking.
+    pub fn preload(&self) -> Result<()> {
+        self.embedder().map(|_| ())
+    }
+
+    pub fn embedder_loaded(&self) -> bool {
+        self.embedder.lock().unwrap().is_some()
+    }
+
+    /// Returns one normalized embedding per text, loading the model if needed. Blocking.
+    pub fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let embedder = self.embedder()?;
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(EMBEDDING_BATCH_SIZE) {
+            embeddings.extend(embedder.embed(batch)?);
+        }
+        Ok(embeddings)
+    }
+
+    /// Documents visible from a conversation, including shared ones.
+    pub fn documents(&self, conversation: Uuid) -> Vec<Document> {
+        self.index
+            .read()
+            .unwrap()
+            .documents
+            .iter()
+            .filter(|d| d.conversation.is_none_or(|c| c == conversation))
+            .cloned()
+            .collect()
+    }
+
+    pub fn document(&self, id: Uuid) -> Option<Document> {
+        self.index
+            .read()
+            .unwrap()
+            .documents
+            .iter()
+            .find(|d| d.id == id)
+            .cloned()
+    }
+
+    pub fn add_document(
+        &self,
+        name: String,
+        text: &str,
+        conversation: Option<Uuid>,
+    ) -> Result<Document> {
+        let texts = chunk(text);
+        if texts.is_empty() {
+            bail!("`{}` does not contain any text", name);
+        }
+
+        let embeddings = self.embed(&texts)?;
+
+        let document = Document {
+            id: Uuid::new_v4(),
+            name,
+            conversation,
+            chunks: texts.len(),
+            created_at: Utc::now(),
+        };
+
+        {
+            let mut index = self.index.write().unwrap();
+            index.documents.push(document.clone());
+            index.chunks.extend(
+                texts
+                    .into_iter()
+                    .zip(embeddings)
+                    .map(|(text, embedding)| Chunk {
+                        document: document.id,
+                        text,
+                        embedding,
+                    }),
+            );
+        }
+
+        self.write()?;
+        Ok(document)
+    }
+
+    pub fn remove_document(&self, id: Uuid) -> Result<()> {
+        {
+            let mut index = self.index.write().unwrap();
+            index.documents.retain(|d| d.id != id);
+            index.chunks.retain(|c| c.document != id);
+        }
+
+        self.write()
+    }
+
+    /// Returns the `k` chunks visible from a conversation that are closest to the query.
+    pub fn search(&self, query: &str, conversation: Uuid, k: usize) -> Result<Vec<Citation>> {
+        let documents = self.documents(conversation);
+        if documents.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let query = self
+            .embedder()?
+            .embed(&[query.to_string()])?
+            .pop()
+            .unwrap_or_default();
+
+        let index = self.index.read().unwrap();
+        let mut scored: Vec<_> = index
+            .chunks
+            .iter()
+            .filter_map(|chunk| {
+                let document = documents.iter().find(|d| d.id == chunk.document)?;
+                // Embeddings are normalized, the dot product is the cosine similarity.
+                let score = chunk
+                    .embedding
+                    .iter()
+                    .zip(query.iter())
+                    .map(|(a, b)| a * b)
+                    .sum::<f32>();
+                (score >= MIN_SCORE).then_some((document, chunk, score))
+            })
+            .collect();
+
+        scored.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+        Ok(scored
+            .into_iter()
+            .take(k)
+            .map(|(document, chunk, score)| Citation {
+                document: document.id,
+                name: document.name.clone(),
+                excerpt: chunk.text.clone(),
+                score,
+            })
+            .collect())
+    }
+
+    fn write(&self) -> Result<()> {
+        let data = serde_json::to_vec(&*self.index.read().unwrap())?;
+        fs::write(&self.path, data).with_context(|| format!("Failed to write {:?}", self.path))
+    }
+}
+
+/// Extracts the text of an uploaded file.
+pub fn extract_text(name: &str, data: &[u8]) -> Result<String> {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    match extension.as_deref() {
+        Some("pdf") => pdf_extract::extract_text_from_mem(data)
+            .with_context(|| format!("Failed to extract text from `{}`", name)),
+        Some("md" | "markdown" | "txt" | "text") | None => String::from_utf8(data.to_vec())
+            .with_context(|| format!("`{}` is not valid UTF-8 text", name)),
+        Some(e) => bail!("Unsupported file type `.{}`", e),
+    }
+}
+
+/// Splits text in overlapping windows of words.
+fn chunk(text: &str) -> Vec<String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut chunks = vec![];
+    let mut start = 0;
+
+    while start < words.len() {
+        let end = (start + CHUNK_WORDS).min(words.len());
+        chunks.push(words[start..end].join(" "));
+        if end == words.len() {
+            break;
+        }
+        start = end - CHUNK_OVERLAP;
+    }
+
+    chunks
+}
+
+/// System prompt handing the retrieved chunks to the model.
+pub fn context_prompt(citations: &[Citation]) -> String {
+    let sources: Vec<String> = citations
+        .iter()
+        .enumerate()
+        .map(|(i, c)| format!("[{}] ({})\n{}", i + 1, c.name, c.excerpt))
+        .collect();
+
+    format!(
+        "Use the following excerpts from the user's documents to answer when they are relevant. \
+         Cite them with their number in brackets, e.g. [1].\n\n{}",
+        sources.join("\n\n")
+    )
+}
@@ -0,0 +1,28 @@
+//! The chat server, as a library shared by its binaries.
+
+pub mod app;
+pub mod attachments;
+pub mod auth;
+pub mod cache;
+pub mod chat;
+pub mod compare;
+pub mod config;
+pub mod context;
+pub mod export;
+pub mod generations;
+pub mod knowledge;
+pub mod limits;
+pub mod metrics;
+pub mod models;
+pub mod rooms;
+pub mod router;
+pub mod search;
+pub mod shutdown;
+pub mod state;
+pub mod store;
+pub mod summaries;
+pub mod telemetry;
+pub mod template;
+pub mod tokenize;
+pub mod tools;
+pub mod utils;
@@ -0,0 +1,192 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::{header::RETRY_AFTER, StatusCode},
+    response::{IntoResponse, Response},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use uuid::Uuid;
+
+use crate::models::ChatModel;
+use crate::utils::env_or;
+
+/// Past this many tracked clients, idle buckets are dropped.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Who a rate limit applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClientKey {
+    User(Uuid),
+    Ip(IpAddr),
+}
+
+#[derive(Debug)]
+pub enum LimitError {
+    RateLimited {
+        retry_after: Duration,
+    },
+    ProviderBusy(ChatModel),
+    /// The server is draining before a restart.
+    ShuttingDown,
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RateLimited { retry_after } => write!(
+                f,
+                "You're sending requests too quickly, try again in {} seconds.",
+                retry_after.as_secs().max(1)
+            ),
+            Self::ProviderBusy(model) => write!(
+                f,
+                "The {} model is busy answering other prompts, try again in a moment.",
+                model
+            ),
+            Self::ShuttingDown => write!(f, "The server is restarting, try again in a moment."),
+        }
+    }
+}
+
+impl LimitError {
+    fn retry_after(&self) -> Duration {
+        match self {
+            Self::RateLimited { retry_after } => *retry_after,
+            Self::ProviderBusy(_) => Duration::from_secs(1),
+            Self::ShuttingDown => Duration::from_secs(5),
+        }
+    }
+}
+
+impl IntoResponse for LimitError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Self::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::TOO_MANY_REQUESTS,
+        };
+        (
+            status,
+            [(RETRY_AFTER, self.retry_after().as_secs().max(1).to_string())],
+            self.to_string(),
+        )
+            .into_response()
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token bucket rate limiter: each client may burst up to `burst` requests, then gets
+/// `per_minute` requests per minute.
+#[derive(Clone)]
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: Arc<Mutex<HashMap<ClientKey, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        Self {
+            per_second: per_minute as f64 / 60.,
+            burst: burst.max(1) as f64,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Takes a token from the client's bucket.
+    pub fn check(&self, client: ClientKey) -> Result<(), LimitError> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() > MAX_BUCKETS {
+            let (per_second, burst) = (self.per_second, self.burst);
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated_at).as_secs_f64() * per_second < burst
+            });
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.updated_at).as_secs_f64() * self.per_second)
+            .min(self.burst);
+        bucket.updated_at = now;
+
+        if bucket.tokens < 1. {
+            let missing = 1. - bucket.tokens;
+            return Err(LimitError::RateLimited {
+                retry_after: Duration::from_secs_f64(missing / self.per_second.max(f64::EPSILON)),
+            });
+        }
+
+        bucket.tokens -= 1.;
+        Ok(())
+    }
+}
+
+/// Caps the number of answers streamed at once from each provider.
+#[derive(Clone)]
+pub struct ProviderLimits {
+    semaphores: HashMap<ChatModel, Arc<Semaphore>>,
+}
+
+impl ProviderLimits {
+    pub fn new(limits: impl IntoIterator<Item = (ChatModel, usize)>) -> Self {
+        Self {
+            semaphores: limits
+                .into_iter()
+                .map(|(model, limit)| (model, Arc::new(Semaphore::new(limit))))
+                .collect(),
+        }
+    }
+
+    /// Reserves a stream slot, released when the permit is dropped.
+    pub fn acquire(&self, model: ChatModel) -> Result<Option<OwnedSemaphorePermit>, LimitError> {
+        match self.semaphores.get(&model) {
+            Some(semaphore) => semaphore
+                .clone()
+                .try_acquire_owned()
+                .map(Some)
+                .map_err(|_| LimitError::ProviderBusy(model)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Every limit protecting the server, configured from the environment.
+#[derive(Clone)]
+pub struct Limits {
+    /// Prompts, per user.
+    pub prompts: RateLimiter,
+    /// Login attempts, per IP.
+    pub logins: RateLimiter,
+    pub providers: ProviderLimits,
+}
+
+impl Limits {
+    pub fn from_env() -> Self {
+        Self {
+            prompts: RateLimiter::new(
+                env_or("CRABOT_PROMPTS_PER_MINUTE", 20),
+                env_or("CRABOT_PROMPT_BURST", 5),
+            ),
+            logins: RateLimiter::new(env_or("CRABOT_LOGINS_PER_MINUTE", 5), 5),
+            providers: ProviderLimits::new([
+                (ChatModel::Lorem, env_or("CRABOT_MAX_STREAMS_LOREM", 64)),
+                (ChatModel::GPT3, env_or("CRABOT_MAX_STREAMS_GPT3", 8)),
+                (ChatModel::Mistral, env_or("CRABOT_MAX_STREAMS_MISTRAL", 8)),
+            ]),
+        }
+    }
+}
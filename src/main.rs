@@ -1,71 +1,35 @@
 use std::net::SocketAddr;
 
-use axum::{extract::MatchedPath, http::Request, Router};
-
-use router::index::index_router;
-use tower_http::services::ServeDir;
-use tower_http::trace::TraceLayer;
+use crabot::app::{create_app, open_state};
+use crabot::config::Config;
+use crabot::knowledge;
+use crabot::shutdown::{self, Shutdown};
+use crabot::telemetry;
 use tower_livereload::LiveReloadLayer;
 
-use tracing::info_span;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
 use dotenv::dotenv;
 
-mod models;
-mod router;
-mod template;
-mod utils;
-
-fn create_app() -> Router {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                // axum logs rejections from built-in extractors with the `axum::rejection`
-                // target, at `TRACE` level. `axum::rejection=trace` enables showing those events
-                "example_tracing_aka_logging=debug,tower_http=debug,axum::rejection=trace".into()
-            }),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
-    let trace_layer = TraceLayer::new_for_http().make_span_with(|request: &Request<_>| {
-        // Log the matched route's path (with placeholders not filled in).
-        // Use request.uri() or OriginalUri if you want the real path.
-        let matched_path = request
-            .extensions()
-            .get::<MatchedPath>()
-            .map(MatchedPath::as_str);
-
-        info_span!(
-            "http_request",
-            method = ?request.method(),
-            matched_path,
-            some_other_field = tracing::field::Empty,
-        )
-    });
-
-    let assets_path = std::env::current_dir().unwrap();
-    // build our application with a route
-
-    Router::new()
-        .merge(index_router())
-        .nest_service(
-            "/assets",
-            ServeDir::new(format!("{}/assets", assets_path.to_str().unwrap())),
-        )
-        .fallback_service(ServeDir::new(format!(
-            "{}/public",
-            assets_path.to_str().unwrap()
-        )))
-        .layer(trace_layer)
-}
-
 #[tokio::main]
 async fn main() {
     dotenv().ok();
+    let _telemetry = telemetry::init("crabot");
+
+    let shutdown = Shutdown::default();
+    let data_dir = std::env::var("CRABOT_DATA_DIR").unwrap_or_else(|_| "data".into());
+    let state = open_state(&data_dir, shutdown.clone(), Config::from_env())
+        .expect("Failed to open the data directory");
+
+    if knowledge::preload_enabled() {
+        let knowledge = state.knowledge.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = knowledge.preload() {
+                tracing::error!("Could not load the embedding model: {:#}", e);
+            }
+        });
+    }
+
     // TODO: Disable live reload on production
-    let app = create_app().layer(LiveReloadLayer::new());
+    let app = create_app(state).layer(LiveReloadLayer::new());
 
     // Run our app with hyper, listening globally on port 3000
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
@@ -75,6 +39,7 @@ async fn main() {
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
+    .with_graceful_shutdown(shutdown::signal(shutdown))
     .await
     .unwrap();
 }
@@ -0,0 +1,235 @@
+use std::{
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    exponential_buckets, histogram_opts, opts, proto::MetricFamily, HistogramVec, IntCounterVec,
+    IntGaugeVec, Registry,
+};
+
+use crate::models::ChatModel;
+
+/// Every metric exported on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub generations: IntCounterVec,
+    pub time_to_first_token: HistogramVec,
+    pub tokens_per_second: HistogramVec,
+    pub upstream_errors: IntCounterVec,
+    pub active_streams: IntGaugeVec,
+    pub model_load_duration: HistogramVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Process wide metrics, recorded from anywhere including pipelines.
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("crabot".into()), None).unwrap();
+
+        let http_requests = IntCounterVec::new(
+            opts!("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            histogram_opts!(
+                "http_request_duration_seconds",
+                "Time to produce response headers, streamed bodies excluded"
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let generations =
+            IntCounterVec::new(opts!("generations_total", "Answers generated"), &["model"])
+                .unwrap();
+        let time_to_first_token = HistogramVec::new(
+            histogram_opts!(
+                "time_to_first_token_seconds",
+                "Delay between a prompt and the first chunk of its answer",
+                exponential_buckets(0.05, 2., 10).unwrap()
+            ),
+            &["model"],
+        )
+        .unwrap();
+        let tokens_per_second = HistogramVec::new(
+            histogram_opts!(
+                "tokens_per_second",
+                "Estimated generation speed, once the first chunk arrived",
+                exponential_buckets(1., 2., 10).unwrap()
+            ),
+            &["model"],
+        )
+        .unwrap();
+        let upstream_errors = IntCounterVec::new(
+            opts!(
+                "upstream_errors_total",
+                "Failed requests to model providers"
+            ),
+            &["provider", "status"],
+        )
+        .unwrap();
+        let active_streams = IntGaugeVec::new(
+            opts!("active_streams", "Answers currently streamed"),
+            &["model"],
+        )
+        .unwrap();
+        let model_load_duration = HistogramVec::new(
+            histogram_opts!(
+                "model_load_duration_seconds",
+                "Time spent loading local models",
+                exponential_buckets(0.1, 2., 10).unwrap()
+            ),
+            &["model"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(generations.clone())).unwrap();
+        registry
+            .register(Box::new(time_to_first_token.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(tokens_per_second.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_errors.clone()))
+            .unwrap();
+        registry.register(Box::new(active_streams.clone())).unwrap();
+        registry
+            .register(Box::new(model_load_duration.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            generations,
+            time_to_first_token,
+            tokens_per_second,
+            upstream_errors,
+            active_streams,
+            model_load_duration,
+        }
+    }
+
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        self.registry.gather()
+    }
+
+    /// Counts a failed provider request, by HTTP status or `transport` for network errors.
+    pub fn upstream_error(&self, provider: &str, error: &ureq::Error) {
+        let status = match error {
+            ureq::Error::Status(code, _) => code.to_string(),
+            ureq::Error::Transport(_) => "transport".into(),
+        };
+        self.upstream_errors
+            .with_label_values(&[provider, &status])
+            .inc();
+    }
+
+    pub fn model_loaded(&self, model: &str, duration: Duration) {
+        self.model_load_duration
+            .with_label_values(&[model])
+            .observe(duration.as_secs_f64());
+    }
+}
+
+/// Tracks one streamed answer, from the prompt to its last chunk.
+///
+/// The stream counts as active until this is dropped.
+pub struct Generation {
+    model: String,
+    started_at: Instant,
+    first_chunk_at: Option<Instant>,
+}
+
+impl Generation {
+    pub fn start(model: ChatModel) -> Self {
+        let model = model.to_string();
+        let metrics = metrics();
+        metrics.generations.with_label_values(&[&model]).inc();
+        metrics.active_streams.with_label_values(&[&model]).inc();
+
+        Self {
+            model,
+            started_at: Instant::now(),
+            first_chunk_at: None,
+        }
+    }
+
+    pub fn chunk(&mut self) {
+        if self.first_chunk_at.is_none() {
+            let now = Instant::now();
+            metrics()
+                .time_to_first_token
+                .with_label_values(&[&self.model])
+                .observe((now - self.started_at).as_secs_f64());
+            self.first_chunk_at = Some(now);
+        }
+    }
+
+    /// Records the generation speed of the complete answer.
+    pub fn finish(&self, tokens: u64) {
+        let Some(first_chunk_at) = self.first_chunk_at else {
+            return;
+        };
+        let elapsed = first_chunk_at.elapsed().as_secs_f64();
+        if elapsed > 0. {
+            metrics()
+                .tokens_per_second
+                .with_label_values(&[&self.model])
+                .observe(tokens as f64 / elapsed);
+        }
+    }
+}
+
+impl Drop for Generation {
+    fn drop(&mut self) {
+        metrics()
+            .active_streams
+            .with_label_values(&[&self.model])
+            .dec();
+    }
+}
+
+/// Counts requests and measures their latency, labelled with the matched route so ids in paths
+/// don't explode the number of series.
+pub async fn track_requests(req: Request, next: Next) -> Response {
+    let method = req.method().to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+
+    let start = Instant::now();
+    let response = next.run(req).await;
+
+    let metrics = metrics();
+    metrics
+        .http_request_duration
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+    metrics
+        .http_requests
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+
+    response
+}
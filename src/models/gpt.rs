@@ -1,14 +1,25 @@
 use tokio_stream::wrappers::ReceiverStream;
 
-use std::str;
-
-use tokio::sync::mpsc::channel;
-
-use crate::{models::Pipeline, utils::sse::parse_event_stream};
+use tokio::sync::{mpsc::channel, oneshot};
+use tracing::{field::Empty, Instrument};
+
+use crate::{
+    models::{
+        upstream::Upstream, ChatMessage, Completion, CompletionChunk, Delta, Endpoint, Pipeline,
+    },
+    tools::{ToolCallDelta, Toolbox},
+    utils::tokens,
+};
 use serde::{Deserialize, Serialize};
-use serde_json;
-
-pub struct GPT3Pipeline {}
+use serde_json::{self, Value};
+
+pub struct GPT3Pipeline {
+    pub tools: Option<Toolbox>,
+    /// JSON Schema the response must conform to.
+    pub schema: Option<Value>,
+    pub endpoint: Endpoint,
+    pub upstream: Upstream,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 struct GPT3ChatCompletion {
@@ -31,75 +42,88 @@ struct GPT3Choice {
 enum GPT3Delta {
     Simple(String),
     Complex(ComplexDelta),
-    Empty(EmptyDelta),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ComplexDelta {
-    content: String,
+    content: Option<String>,
+    tool_calls: Option<Vec<ToolCallDelta>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct EmptyDelta {}
+impl CompletionChunk for GPT3ChatCompletion {
+    fn into_deltas(self) -> Vec<Delta> {
+        self.choices
+            .into_iter()
+            .map(|choice| match choice.delta {
+                Some(GPT3Delta::Simple(v)) => Delta {
+                    content: Some(v),
+                    finish_reason: choice.finish_reason,
+                    ..Default::default()
+                },
+                Some(GPT3Delta::Complex(c)) => Delta {
+                    content: c.content,
+                    tool_calls: c.tool_calls,
+                    finish_reason: choice.finish_reason,
+                },
+                None => Delta {
+                    finish_reason: choice.finish_reason,
+                    ..Default::default()
+                },
+            })
+            .collect()
+    }
+}
 
 impl Pipeline for GPT3Pipeline {
-    fn run(&self, prompt: String) -> ReceiverStream<String> {
-        let url = "https://api.openai.com/v1/chat/completions";
-        let model = "gpt-3.5-turbo";
-
-        let response = ureq::post(url)
-            .set(
-                "Authorization",
-                &format!("Bearer {}", std::env::var("OPENAI_API_KEY").unwrap()),
-            )
-            .send_json(ureq::json!({
-                "model": model,
-                "stream": true,
-                "messages": [
-                    {
-                        "role": "user",
-                        "content": prompt
-                    }
-                ]
-            }))
-            .unwrap();
-
-        let reader = response.into_reader();
-        let mut stream = parse_event_stream(reader);
+    fn stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        complete: oneshot::Sender<()>,
+    ) -> ReceiverStream<String> {
+        let url = self.endpoint.url("/chat/completions");
+        // Structured outputs and vision are not available on gpt-3.5-turbo.
+        let has_images = messages.iter().any(|m| m.content.has_images());
+        let model = match (&self.schema, has_images) {
+            (None, false) => "gpt-3.5-turbo",
+            _ => "gpt-4o-mini",
+        };
+        let api_key = self.endpoint.api_key.clone();
 
         let (tx, rx) = channel::<String>(1024);
-
-        tokio::spawn(async move {
-            while let Some(event) = stream.recv().await {
-                if event.data == "[DONE]\n" {
-                    break;
-                }
-
-                if event.name == "message" {
-                    let completion =
-                        match serde_json::from_str::<GPT3ChatCompletion>(event.data.as_str()) {
-                            Ok(c) => c,
-                            Err(e) => {
-                                tracing::error!(
-                                    "GPT: Could not deserialize event data:\n{}\n{}",
-                                    event.data,
-                                    e
-                                );
-                                continue;
-                            }
-                        };
-
-                    for choice in completion.choices {
-                        let msg = match choice.delta {
-                            Some(GPT3Delta::Simple(v)) => v,
-                            Some(GPT3Delta::Complex(c)) => c.content,
-                            _ => continue,
-                        };
-                        tx.send(msg).await.expect("Failed to send chunk to channel");
-                    }
-                }
-            }
-        });
+        let mut parameters = serde_json::Map::new();
+        if let Some(schema) = &self.schema {
+            parameters.insert(
+                "response_format".into(),
+                serde_json::json!({
+                    "type": "json_schema",
+                    "json_schema": { "name": "response", "schema": schema },
+                }),
+            );
+        }
+
+        let span = tracing::info_span!(
+            "pipeline",
+            provider = "openai",
+            model,
+            prompt_tokens = tokens::estimate_messages(&messages),
+            completion_tokens = Empty,
+        );
+        let completion = Completion {
+            name: "GPT",
+            provider: "openai",
+            model,
+            url,
+            api_key,
+            tools: self.tools.clone(),
+            parameters,
+            session: self.upstream.session("openai", model, &messages),
+        };
+
+        tokio::spawn(
+            completion
+                .stream::<GPT3ChatCompletion>(messages, tx, complete)
+                .instrument(span),
+        );
 
         ReceiverStream::new(rx)
     }
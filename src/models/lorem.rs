@@ -1,29 +1,265 @@
-use std::{thread, time::Duration};
-use tokio::sync::mpsc;
+use std::{fmt, str::FromStr, time::Duration};
+
+use fake::{
+    faker::lorem::en::{Sentence, Word, Words},
+    Fake,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
 use tokio_stream::wrappers::ReceiverStream;
-use uuid::Uuid;
+use tracing::Instrument;
+
+use crate::models::{ChatMessage, Pipeline, Role};
+use crate::utils::{schema, tokens};
+
+/// A simulated model, for demos and tests: no credentials, and as deterministic, fast, slow or
+/// broken as needed.
+#[derive(Default)]
+pub struct LoremPipeline {
+    /// When set, output is a random JSON document conforming to this schema.
+    pub schema: Option<Value>,
+    pub options: LoremOptions,
+}
+
+/// What Lorem answers, and how.
+#[derive(Debug, Clone)]
+pub struct LoremOptions {
+    /// Seeds the generated text, which is then the same for every prompt. Random when unset.
+    pub seed: Option<u64>,
+    /// Number of words of generated answers.
+    pub words: usize,
+    /// Delay between two tokens.
+    pub delay: Duration,
+    pub mode: LoremMode,
+    pub failure: Option<Failure>,
+}
 
-use crate::models::Pipeline;
+impl Default for LoremOptions {
+    fn default() -> Self {
+        Self {
+            seed: None,
+            words: 20,
+            delay: Duration::from_millis(100),
+            mode: LoremMode::Words,
+            failure: None,
+        }
+    }
+}
+
+impl LoremOptions {
+    /// Reads `CRABOT_LOREM_SEED`, `CRABOT_LOREM_WORDS`, `CRABOT_LOREM_DELAY_MS`,
+    /// `CRABOT_LOREM_MODE` and `CRABOT_LOREM_FAILURE`, defaulting any that is unset or invalid.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            seed: env("CRABOT_LOREM_SEED"),
+            words: env("CRABOT_LOREM_WORDS").unwrap_or(defaults.words),
+            delay: env("CRABOT_LOREM_DELAY_MS").map_or(defaults.delay, Duration::from_millis),
+            mode: env("CRABOT_LOREM_MODE").unwrap_or(defaults.mode),
+            failure: env("CRABOT_LOREM_FAILURE"),
+        }
+    }
+}
+
+fn env<T>(name: &str) -> Option<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let value = std::env::var(name).ok().filter(|v| !v.trim().is_empty())?;
+    value
+        .trim()
+        .parse()
+        .map_err(|e| tracing::warn!("{}: {}", name, e))
+        .ok()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoremMode {
+    /// Random words.
+    Words,
+    /// The prompt, streamed back.
+    Echo,
+    /// A heading, a paragraph, a list and a code block.
+    Markdown,
+}
+
+impl FromStr for LoremMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "words" => Ok(Self::Words),
+            "echo" => Ok(Self::Echo),
+            "markdown" => Ok(Self::Markdown),
+            other => Err(anyhow::anyhow!("Unknown Lorem mode `{}`", other)),
+        }
+    }
+}
 
-pub struct LoremPipeline {}
+/// A failure injected into answers, written `error:<tokens>`, `stall:<tokens>[:<ms>]` or
+/// `slow-start:<ms>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The answer breaks off after this many tokens, as when a provider connection drops, or
+    /// after its last token when shorter.
+    Error { after: usize },
+    /// No token for a while after this many tokens.
+    Stall { after: usize, duration: Duration },
+    /// No token for a while before the first one.
+    SlowStart(Duration),
+}
+
+/// How long stalls last when not given.
+const DEFAULT_STALL: Duration = Duration::from_secs(30);
+
+impl FromStr for Failure {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let kind = parts.next().unwrap_or_default();
+        let mut number = |name: &str| -> anyhow::Result<Option<u64>> {
+            parts
+                .next()
+                .map(|v| {
+                    v.parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid {} `{}` in `{}`", name, v, s))
+                })
+                .transpose()
+        };
+
+        let failure = match kind {
+            "error" => Self::Error {
+                after: number("token count")?.unwrap_or(0) as usize,
+            },
+            "stall" => Self::Stall {
+                after: number("token count")?.unwrap_or(0) as usize,
+                duration: number("duration")?.map_or(DEFAULT_STALL, Duration::from_millis),
+            },
+            "slow-start" => {
+                Self::SlowStart(number("duration")?.map_or(DEFAULT_STALL, Duration::from_millis))
+            }
+            other => anyhow::bail!("Unknown Lorem failure `{}`", other),
+        };
+        Ok(failure)
+    }
+}
+
+impl LoremPipeline {
+    /// The whole answer, split in the tokens it is streamed as.
+    fn tokens(&self, messages: &[ChatMessage]) -> Vec<String> {
+        let mut rng = match self.options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        if let Some(schema) = &self.schema {
+            let document = schema::sample(schema, &mut rng).to_string();
+            let chars: Vec<char> = document.chars().collect();
+            return chars.chunks(8).map(|c| c.iter().collect()).collect();
+        }
+
+        let text = match self.options.mode {
+            LoremMode::Words => (0..self.options.words)
+                .map(|_| format!("{} ", Word().fake_with_rng::<String, _>(&mut rng)))
+                .collect(),
+            LoremMode::Echo => messages
+                .iter()
+                .rev()
+                .find(|m| m.role == Role::User)
+                .map(|m| m.content.text())
+                .unwrap_or_default(),
+            LoremMode::Markdown => markdown(self.options.words, &mut rng),
+        };
+        text.split_inclusive(char::is_whitespace)
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// A sample of the Markdown models commonly answer with.
+fn markdown(words: usize, rng: &mut StdRng) -> String {
+    let title: Vec<String> = Words(2..4).fake_with_rng(rng);
+    let paragraph: Vec<String> = Words(words..words + 1).fake_with_rng(rng);
+    let items: Vec<String> = (0..3).map(|_| Sentence(3..6).fake_with_rng(rng)).collect();
+    let function: String = Word().fake_with_rng(rng);
+
+    format!(
+        "## {title}\n\n{paragraph}.\n\n{items}\n\n```rust\nfn {function}() -> u32 {{\n    {number}\n}}\n```\n",
+        title = capitalize(&title.join(" ")),
+        paragraph = capitalize(&paragraph.join(" ")),
+        items = items
+            .iter()
+            .map(|item| format!("- {}", item))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        number = rng.gen_range(0..1000),
+    )
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
 
 impl Pipeline for LoremPipeline {
-    fn run(&self, _prompt: String) -> ReceiverStream<String> {
+    fn stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        complete: oneshot::Sender<()>,
+    ) -> ReceiverStream<String> {
         let (tx, rx) = mpsc::channel::<String>(10);
+        let span = tracing::info_span!(
+            "pipeline",
+            provider = "lorem",
+            model = "lorem",
+            prompt_tokens = tokens::estimate_messages(&messages),
+        );
 
-        let _id = Uuid::new_v4();
-        tokio::spawn(async move {
-            use fake::faker::lorem::en::*;
-            use fake::Fake;
+        let tokens = self.tokens(&messages);
+        let count = tokens.len();
+        let delay = self.options.delay;
+        let failure = self.options.failure;
+        tokio::spawn(
+            async move {
+                if let Some(Failure::SlowStart(duration)) = failure {
+                    tokio::time::sleep(duration).await;
+                }
 
-            let word_generator = Word();
+                for (i, token) in tokens.into_iter().enumerate() {
+                    match failure {
+                        Some(Failure::Error { after }) if i == after => {
+                            tracing::error!("Lorem: Injected failure after {} tokens", i);
+                            return;
+                        }
+                        Some(Failure::Stall { after, duration }) if i == after => {
+                            tokio::time::sleep(duration).await;
+                        }
+                        _ => (),
+                    }
 
-            for _ in 0..20 {
-                let word: String = word_generator.fake();
-                tx.send(format!("{word} ")).await.unwrap();
-                thread::sleep(Duration::from_millis(100))
+                    if i > 0 {
+                        tokio::time::sleep(delay).await;
+                    }
+                    // The answer was abandoned.
+                    if tx.send(token).await.is_err() {
+                        return;
+                    }
+                }
+                // An error due past the end of the answer happens after its last token.
+                if let Some(Failure::Error { .. }) = failure {
+                    tracing::error!("Lorem: Injected failure after {} tokens", count);
+                    return;
+                }
+                let _ = complete.send(());
             }
-        });
+            .instrument(span),
+        );
 
         rx.into()
     }
@@ -1,14 +1,25 @@
 use tokio_stream::wrappers::ReceiverStream;
 
-use std::str;
+use tokio::sync::{mpsc::channel, oneshot};
+use tracing::{field::Empty, Instrument};
 
-use tokio::sync::mpsc::channel;
-
-use crate::{models::Pipeline, utils::sse::parse_event_stream};
+use crate::{
+    models::{
+        upstream::Upstream, ChatMessage, Completion, CompletionChunk, Delta, Endpoint, Pipeline,
+    },
+    tools::{ToolCallDelta, Toolbox},
+    utils::tokens,
+};
 use serde::{Deserialize, Serialize};
-use serde_json;
+use serde_json::{self, Value};
 
-pub struct MistralPipeline {}
+pub struct MistralPipeline {
+    pub tools: Option<Toolbox>,
+    /// JSON Schema the response must conform to.
+    pub schema: Option<Value>,
+    pub endpoint: Endpoint,
+    pub upstream: Upstream,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 struct MistralChatCompletion {
@@ -29,64 +40,86 @@ struct MistralChoice {
 #[derive(Debug, Serialize, Deserialize)]
 struct MistralMessage {
     role: Option<String>,
-    content: String,
+    content: Option<String>,
+    tool_calls: Option<Vec<ToolCallDelta>>,
 }
 
-impl Pipeline for MistralPipeline {
-    fn run(&self, prompt: String) -> ReceiverStream<String> {
-        let url = "https://api.mistral.ai/v1/chat/completions";
-        let model = "mistral-tiny";
-
-        let response = ureq::post(url)
-            .set(
-                "Authorization",
-                &format!("Bearer {}", std::env::var("MISTRAL_API_KEY").unwrap()),
-            )
-            .send_json(ureq::json!({
-                "model": model,
-                "stream": true,
-                "messages": [
-                    {
-                        "role": "user",
-                        "content": prompt
-                    }
-                ]
-            }))
-            .unwrap();
+impl CompletionChunk for MistralChatCompletion {
+    fn into_deltas(self) -> Vec<Delta> {
+        self.choices
+            .into_iter()
+            .map(|choice| Delta {
+                content: choice.delta.content,
+                tool_calls: choice.delta.tool_calls,
+                finish_reason: choice.finish_reason,
+            })
+            .collect()
+    }
+}
 
-        let reader = response.into_reader();
-        let mut stream = parse_event_stream(reader);
+impl Pipeline for MistralPipeline {
+    fn stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        complete: oneshot::Sender<()>,
+    ) -> ReceiverStream<String> {
+        let url = self.endpoint.url("/chat/completions");
+        // mistral-tiny does not support function calling.
+        let model = match self.tools {
+            Some(_) => "mistral-small-latest",
+            None => "mistral-tiny",
+        };
+        let api_key = self.endpoint.api_key.clone();
 
         let (tx, rx) = channel::<String>(1024);
+        let mut parameters = serde_json::Map::new();
+        if self.schema.is_some() {
+            parameters.insert(
+                "response_format".into(),
+                serde_json::json!({ "type": "json_object" }),
+            );
+        }
+
+        // The Mistral models used here have no vision support.
+        let mut messages: Vec<ChatMessage> = messages
+            .into_iter()
+            .map(ChatMessage::without_images)
+            .collect();
 
-        tokio::spawn(async move {
-            while let Some(event) = stream.recv().await {
-                if event.data == "[DONE]\n" {
-                    break;
-                }
+        // Mistral only offers a generic JSON mode, the schema itself has to be part of the prompt.
+        if let Some(schema) = &self.schema {
+            messages.insert(
+                0,
+                ChatMessage::system(format!(
+                    "Answer only with a JSON document matching this JSON Schema:\n{}",
+                    schema
+                )),
+            );
+        }
 
-                if event.name == "message" {
-                    let completion =
-                        match serde_json::from_str::<MistralChatCompletion>(event.data.as_str()) {
-                            Ok(c) => c,
-                            Err(e) => {
-                                tracing::error!(
-                                    "Mistral: Could not deserialize event data:\n{}\n{}",
-                                    event.data,
-                                    e
-                                );
-                                continue;
-                            }
-                        };
+        let span = tracing::info_span!(
+            "pipeline",
+            provider = "mistral",
+            model,
+            prompt_tokens = tokens::estimate_messages(&messages),
+            completion_tokens = Empty,
+        );
+        let completion = Completion {
+            name: "Mistral",
+            provider: "mistral",
+            model,
+            url,
+            api_key,
+            tools: self.tools.clone(),
+            parameters,
+            session: self.upstream.session("mistral", model, &messages),
+        };
 
-                    for choice in completion.choices {
-                        tx.send(choice.delta.content)
-                            .await
-                            .expect("Failed to send chunk to channel");
-                    }
-                }
-            }
-        });
+        tokio::spawn(
+            completion
+                .stream::<MistralChatCompletion>(messages, tx, complete)
+                .instrument(span),
+        );
 
         ReceiverStream::new(rx)
     }
@@ -0,0 +1,87 @@
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::State,
+    response::sse::{Event, Sse},
+    routing::post,
+    Json, Router,
+};
+use futures::stream;
+use serde_json::{json, Value};
+
+use crate::models::{Endpoint, ModelConfig};
+
+/// A local stand-in for OpenAI-compatible chat completion APIs, for deterministic runs without
+/// credentials. Prompts are answered with their canned response, or echoed back.
+pub struct MockProvider {
+    pub addr: SocketAddr,
+}
+
+impl MockProvider {
+    /// Serves `answers`, by prompt, on a free local port until the runtime stops.
+    pub async fn start(answers: HashMap<String, String>) -> std::io::Result<Self> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let app = Router::new()
+            .route("/v1/chat/completions", post(complete))
+            .with_state(Arc::new(answers));
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                tracing::error!("Mock provider stopped: {}", e);
+            }
+        });
+
+        Ok(Self { addr })
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}/v1", self.addr)
+    }
+
+    /// Points the GPT and Mistral pipelines to this provider.
+    pub fn install(&self, models: &mut ModelConfig) {
+        for endpoint in [&mut models.openai, &mut models.mistral] {
+            *endpoint = Endpoint {
+                base_url: self.base_url(),
+                api_key: "mock".into(),
+            };
+        }
+    }
+}
+
+async fn complete(
+    State(answers): State<Arc<HashMap<String, String>>>,
+    Json(body): Json<Value>,
+) -> Sse<impl futures::Stream<Item = Result<Event, Infallible>>> {
+    let prompt = body["messages"]
+        .as_array()
+        .and_then(|messages| messages.iter().rev().find(|m| m["role"] == "user"))
+        .and_then(|m| m["content"].as_str())
+        .unwrap_or_default();
+    let answer = answers.get(prompt).map_or(prompt, String::as_str);
+    let model = body["model"].as_str().unwrap_or("mock");
+
+    let chunk = |content: Value, finish_reason: Value| {
+        let chunk = json!({
+            "id": "mock",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": model,
+            "choices": [{
+                "index": 0,
+                "delta": { "role": "assistant", "content": content },
+                "finish_reason": finish_reason,
+            }],
+        });
+        Ok(Event::default().data(chunk.to_string()))
+    };
+    let mut events: Vec<_> = answer
+        .split_inclusive(' ')
+        .map(|word| chunk(json!(word), Value::Null))
+        .collect();
+    // As the providers do, the last chunk has no content.
+    events.push(chunk(Value::Null, json!("stop")));
+    events.push(Ok(Event::default().data("[DONE]")));
+
+    Sse::new(stream::iter(events))
+}
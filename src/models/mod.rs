@@ -1,11 +1,27 @@
-use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{mpsc::Sender, oneshot};
 use tokio_stream::wrappers::ReceiverStream;
+use tracing::field::Empty;
+
+use crate::{
+    metrics::metrics,
+    tools::{ToolCall, ToolCallBuilder, ToolCallDelta, Toolbox, MAX_TOOL_ROUNDS},
+    utils::{env_or, sse::parse_event_stream, tokens},
+};
+use lorem::LoremOptions;
+use upstream::Session;
 
 pub mod gpt;
 pub mod lorem;
 pub mod mistral;
+pub mod mock;
+pub mod replay;
+pub mod upstream;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub enum ChatModel {
     #[default]
     #[serde(rename = "lorem")]
@@ -16,6 +32,455 @@ pub enum ChatModel {
     Mistral,
 }
 
+impl ChatModel {
+    pub const ALL: [ChatModel; 3] = [Self::Lorem, Self::GPT3, Self::Mistral];
+
+    /// Environment variable holding the provider API key, for remote models.
+    pub fn credentials_var(self) -> Option<&'static str> {
+        match self {
+            Self::Lorem => None,
+            Self::GPT3 => Some("OPENAI_API_KEY"),
+            Self::Mistral => Some("MISTRAL_API_KEY"),
+        }
+    }
+
+    pub fn has_credentials(self) -> bool {
+        self.credentials_var()
+            .is_none_or(|var| std::env::var(var).is_ok_and(|v| !v.is_empty()))
+    }
+
+    /// Tokens the model reads at once, prompt and answer together: the smallest window of the
+    /// provider models it may use. Overridden in [`ContextConfig`](crate::context::ContextConfig).
+    pub fn context_window(self) -> u64 {
+        match self {
+            Self::Lorem => 8192,
+            // gpt-3.5-turbo, gpt-4o-mini has more.
+            Self::GPT3 => 16_385,
+            Self::Mistral => 32_000,
+        }
+    }
+
+    /// US dollars per million prompt tokens of the provider model answering plain prompts,
+    /// unless overridden with `CRABOT_PROMPT_PRICE_<MODEL>`.
+    pub fn prompt_price(self) -> f64 {
+        let default = match self {
+            Self::Lorem => 0.,
+            // gpt-3.5-turbo
+            Self::GPT3 => 0.5,
+            // mistral-tiny
+            Self::Mistral => 0.25,
+        };
+        let name = format!("CRABOT_PROMPT_PRICE_{}", self.to_string().to_uppercase());
+        env_or(&name, default)
+    }
+
+    /// Models served by this instance: those listed in `CRABOT_MODELS` (comma separated), or
+    /// every model with credentials.
+    pub fn enabled() -> Vec<ChatModel> {
+        match std::env::var("CRABOT_MODELS") {
+            Ok(models) => models
+                .split(',')
+                .filter(|m| !m.trim().is_empty())
+                .filter_map(|m| match m.parse() {
+                    Ok(model) => Some(model),
+                    Err(e) => {
+                        tracing::warn!("CRABOT_MODELS: {}", e);
+                        None
+                    }
+                })
+                .collect(),
+            Err(_) => Self::ALL
+                .into_iter()
+                .filter(|m| m.has_credentials())
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for ChatModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Lorem => "lorem",
+            Self::GPT3 => "gpt3",
+            Self::Mistral => "mistral",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for ChatModel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "lorem" => Ok(Self::Lorem),
+            "gpt3" => Ok(Self::GPT3),
+            "mistral" => Ok(Self::Mistral),
+            other => Err(anyhow::anyhow!("Unknown model `{}`", other)),
+        }
+    }
+}
+
+/// Where a provider API is reached. Another OpenAI-compatible server can stand in, e.g. a
+/// local mock.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    pub base_url: String,
+    /// Replays need no key, the provider rejects requests without one.
+    pub api_key: String,
+}
+
+impl Endpoint {
+    fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.into(),
+            api_key: String::new(),
+        }
+    }
+
+    /// Reads the `<PROVIDER>_BASE_URL` and `<PROVIDER>_API_KEY` variables.
+    fn from_env(provider: &str, default: Self) -> Self {
+        let var = |name: &str| std::env::var(format!("{}_{}", provider, name)).ok();
+        Self {
+            base_url: var("BASE_URL").unwrap_or(default.base_url),
+            api_key: var("API_KEY").unwrap_or(default.api_key),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url.trim_end_matches('/'), path)
+    }
+}
+
+/// How each model answers.
+#[derive(Debug, Clone)]
+pub struct ModelConfig {
+    pub lorem: LoremOptions,
+    pub openai: Endpoint,
+    pub mistral: Endpoint,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            lorem: LoremOptions::default(),
+            openai: Endpoint::new("https://api.openai.com/v1"),
+            mistral: Endpoint::new("https://api.mistral.ai/v1"),
+        }
+    }
+}
+
+impl ModelConfig {
+    /// Reads the `CRABOT_LOREM_*` options and the `OPENAI_*` and `MISTRAL_*` endpoints.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            lorem: LoremOptions::from_env(),
+            openai: Endpoint::from_env("OPENAI", defaults.openai),
+            mistral: Endpoint::from_env("MISTRAL", defaults.mistral),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+/// Message content, either plain text or a list of parts for multimodal prompts.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+impl Content {
+    /// The text parts of the content, images left out.
+    pub fn text(&self) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Parts(parts) => parts
+                .iter()
+                .filter_map(|p| match p {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    ContentPart::ImageUrl { .. } => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    pub fn has_images(&self) -> bool {
+        matches!(self, Self::Parts(parts) if parts.iter().any(|p| matches!(p, ContentPart::ImageUrl { .. })))
+    }
+}
+
+impl From<String> for Content {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+/// A message as sent to OpenAI-compatible chat completion APIs.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: Content,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    fn new(role: Role, content: String) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            tool_calls: vec![],
+            tool_call_id: None,
+        }
+    }
+
+    pub fn system(content: String) -> Self {
+        Self::new(Role::System, content)
+    }
+
+    pub fn user(content: String) -> Self {
+        Self::new(Role::User, content)
+    }
+
+    pub fn assistant(content: String) -> Self {
+        Self::new(Role::Assistant, content)
+    }
+
+    /// A user message with images attached, given as URLs (or `data:` URLs).
+    pub fn user_with_images(text: String, images: Vec<String>) -> Self {
+        if images.is_empty() {
+            return Self::user(text);
+        }
+
+        let mut parts = vec![ContentPart::Text { text }];
+        parts.extend(images.into_iter().map(|url| ContentPart::ImageUrl {
+            image_url: ImageUrl { url },
+        }));
+
+        Self {
+            content: Content::Parts(parts),
+            ..Self::user("".into())
+        }
+    }
+
+    /// Drops image parts, for providers without vision support.
+    pub fn without_images(self) -> Self {
+        match self.content {
+            Content::Parts(_) => Self {
+                content: self.content.text().into(),
+                ..self
+            },
+            Content::Text(_) => self,
+        }
+    }
+}
+
 pub trait Pipeline {
-    fn run(&self, prompt: String) -> ReceiverStream<String>;
+    /// Streams the answer to a prompt. `complete` is sent once the model ended the answer by
+    /// itself: not when it failed, was cut at a length limit or was abandoned.
+    fn stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        complete: oneshot::Sender<()>,
+    ) -> ReceiverStream<String>;
+
+    fn run(&self, messages: Vec<ChatMessage>) -> ReceiverStream<String> {
+        self.stream(messages, oneshot::channel().0)
+    }
+}
+
+/// A chunk of a streamed chat completion, in the wire format of an OpenAI-compatible provider.
+pub(crate) trait CompletionChunk: DeserializeOwned {
+    /// What each choice of the chunk adds to the answer.
+    fn into_deltas(self) -> Vec<Delta>;
+}
+
+#[derive(Default)]
+pub(crate) struct Delta {
+    pub content: Option<String>,
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+    /// Set on the last chunk of a choice, `stop` when the model ended the answer by itself.
+    pub finish_reason: Option<String>,
+}
+
+/// A streamed chat completion request to an OpenAI-compatible provider.
+pub(crate) struct Completion {
+    /// Prefix of log messages.
+    pub name: &'static str,
+    pub provider: &'static str,
+    pub model: &'static str,
+    pub url: String,
+    pub api_key: String,
+    pub tools: Option<Toolbox>,
+    /// Request parameters besides the model, messages and tools.
+    pub parameters: serde_json::Map<String, Value>,
+    pub session: Arc<Session>,
+}
+
+impl Completion {
+    /// Streams the answer to `tx`, running the tools the model calls and sending their results
+    /// back, for up to [`MAX_TOOL_ROUNDS`] requests. Records `completion_tokens` on the current
+    /// span.
+    pub async fn stream<C: CompletionChunk>(
+        self,
+        messages: Vec<ChatMessage>,
+        tx: Sender<String>,
+        complete: oneshot::Sender<()>,
+    ) {
+        let Self {
+            name,
+            provider,
+            model,
+            url,
+            api_key,
+            tools,
+            parameters,
+            session,
+        } = self;
+        let mut messages = messages;
+        let mut completion_tokens = 0;
+
+        'rounds: for round in 0..MAX_TOOL_ROUNDS {
+            // Spans last until dropped, this one covers the request and its streamed answer.
+            let upstream = tracing::info_span!(
+                "upstream_request",
+                provider,
+                model,
+                round,
+                http.status_code = Empty,
+                completion_tokens = Empty,
+            );
+
+            let mut body = serde_json::json!({
+                "model": model,
+                "stream": true,
+                "messages": messages,
+            });
+            if let Some(toolbox) = &tools {
+                body["tools"] = toolbox.registry.definitions();
+            }
+            for (key, value) in &parameters {
+                body[key] = value.clone();
+            }
+
+            let response = {
+                let (session, url, api_key) = (session.clone(), url.clone(), api_key.clone());
+                tokio::task::spawn_blocking(move || session.send(&url, &api_key, body))
+                    .await
+                    .expect("Failed to join request task")
+            };
+            let response = match response {
+                Ok(r) => {
+                    upstream.record("http.status_code", r.status);
+                    r
+                }
+                Err(e) => {
+                    if let ureq::Error::Status(status, _) = *e {
+                        upstream.record("http.status_code", status);
+                    }
+                    tracing::error!(parent: &upstream, "{}: Request failed: {}", name, e);
+                    metrics().upstream_error(provider, &e);
+                    break;
+                }
+            };
+
+            let mut stream = parse_event_stream(response.body);
+            let mut content = String::new();
+            let mut tool_calls = ToolCallBuilder::default();
+            let mut finish_reason = None;
+
+            while let Some(event) = stream.recv().await {
+                if event.data == "[DONE]\n" {
+                    break;
+                }
+                if event.name != "message" {
+                    continue;
+                }
+
+                let chunk = match serde_json::from_str::<C>(event.data.as_str()) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        tracing::error!(
+                            "{}: Could not deserialize event data:\n{}\n{}",
+                            name,
+                            event.data,
+                            e
+                        );
+                        continue;
+                    }
+                };
+
+                for delta in chunk.into_deltas() {
+                    if delta.finish_reason.is_some() {
+                        finish_reason = delta.finish_reason;
+                    }
+                    if let Some(deltas) = delta.tool_calls {
+                        tool_calls.push(deltas);
+                    }
+                    let msg = match delta.content {
+                        Some(v) if !v.is_empty() => v,
+                        _ => continue,
+                    };
+                    content.push_str(&msg);
+                    // The answer was abandoned: cancelled, interrupted or its client gone.
+                    if tx.send(msg).await.is_err() {
+                        break 'rounds;
+                    }
+                }
+            }
+
+            upstream.record("completion_tokens", tokens::estimate(&content));
+            completion_tokens += tokens::estimate(&content);
+            drop(upstream);
+
+            let tool_calls = tool_calls.build();
+            let Some(toolbox) = tools.as_ref().filter(|_| !tool_calls.is_empty()) else {
+                if finish_reason.as_deref() == Some("stop") {
+                    let _ = complete.send(());
+                }
+                break;
+            };
+
+            let mut results = Vec::with_capacity(tool_calls.len());
+            for call in tool_calls.iter() {
+                results.push(toolbox.run(call).await);
+            }
+
+            messages.push(ChatMessage {
+                role: Role::Assistant,
+                content: content.into(),
+                tool_calls,
+                tool_call_id: None,
+            });
+            messages.extend(results);
+        }
+
+        tracing::Span::current().record("completion_tokens", completion_tokens);
+        session.finish();
+    }
 }
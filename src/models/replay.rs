@@ -0,0 +1,73 @@
+use std::{path::Path, sync::Arc};
+
+use serde_json::Value;
+use tokio::sync::oneshot;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::models::{
+    gpt::GPT3Pipeline,
+    mistral::MistralPipeline,
+    upstream::{Cassette, Upstream},
+    ChatMessage, ModelConfig, Pipeline,
+};
+use crate::tools::Toolbox;
+
+/// Answers with a recorded cassette, through the pipeline of the provider it was recorded from
+/// so its response is parsed as the live one was.
+pub struct ReplayPipeline {
+    pub cassette: Arc<Cassette>,
+    /// Waits between chunks as long as they took to arrive, rather than replaying at once.
+    pub realtime: bool,
+    pub tools: Option<Toolbox>,
+    pub schema: Option<Value>,
+}
+
+impl ReplayPipeline {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(Self {
+            cassette: Arc::new(Cassette::open(path)?),
+            realtime: false,
+            tools: None,
+            schema: None,
+        })
+    }
+}
+
+impl Pipeline for ReplayPipeline {
+    fn stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        complete: oneshot::Sender<()>,
+    ) -> ReceiverStream<String> {
+        let upstream = Upstream::Replay {
+            cassette: self.cassette.clone(),
+            realtime: self.realtime,
+        };
+        let tools = self.tools.clone();
+        let schema = self.schema.clone();
+        // Replays don't reach the provider.
+        let defaults = ModelConfig::default();
+
+        match self.cassette.provider.as_str() {
+            "mistral" => MistralPipeline {
+                tools,
+                schema,
+                endpoint: defaults.mistral,
+                upstream,
+            }
+            .stream(messages, complete),
+            provider => {
+                if provider != "openai" {
+                    tracing::warn!("Replay: Unknown provider `{}`, parsed as OpenAI", provider);
+                }
+                GPT3Pipeline {
+                    tools,
+                    schema,
+                    endpoint: defaults.openai,
+                    upstream,
+                }
+                .stream(messages, complete)
+            }
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::models::{ChatMessage, Role};
+
+/// Raw bytes of an upstream response, as returned by one read.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Chunk {
+    /// Milliseconds since the previous chunk, or since the request for the first one.
+    pub delay_ms: u64,
+    /// The bytes when they are valid UTF-8, which they are unless a read split a character.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base64: Option<String>,
+}
+
+impl Chunk {
+    fn new(delay: Duration, data: &[u8]) -> Self {
+        let (text, base64) = match std::str::from_utf8(data) {
+            Ok(text) => (Some(text.to_string()), None),
+            Err(_) => (None, Some(STANDARD.encode(data))),
+        };
+        Self {
+            delay_ms: delay.as_millis() as u64,
+            text,
+            base64,
+        }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        match (&self.text, &self.base64) {
+            (Some(text), _) => text.as_bytes().to_vec(),
+            (None, Some(data)) => STANDARD.decode(data).unwrap_or_default(),
+            (None, None) => vec![],
+        }
+    }
+}
+
+/// One request to a provider and its streamed response.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Exchange {
+    pub request: Value,
+    pub status: u16,
+    pub chunks: Vec<Chunk>,
+}
+
+/// The upstream requests of one answer, tool rounds included, recorded to be replayed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Cassette {
+    pub provider: String,
+    pub model: String,
+    pub recorded_at: chrono::DateTime<chrono::Utc>,
+    pub exchanges: Vec<Exchange>,
+}
+
+impl Cassette {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        serde_json::from_slice(&data).with_context(|| format!("Invalid cassette {:?}", path))
+    }
+
+    /// Text of the last user message of the first request.
+    fn prompt(&self) -> Option<String> {
+        let messages = self.exchanges.first()?.request["messages"].as_array()?;
+        let message = messages.iter().rev().find(|m| m["role"] == "user")?;
+        let message: ChatMessage = serde_json::from_value(message.clone()).ok()?;
+        Some(message.content.text())
+    }
+}
+
+/// Where the GPT and Mistral pipelines get their answers from.
+#[derive(Debug, Clone, Default)]
+pub enum Upstream {
+    /// The provider's API.
+    #[default]
+    Http,
+    /// The provider's API, saving each answer as a cassette in this directory.
+    Record(PathBuf),
+    /// A recorded answer, in real time or as fast as possible.
+    Replay {
+        cassette: Arc<Cassette>,
+        realtime: bool,
+    },
+    /// The cassette of this directory recorded for the same prompt, or the first one for the
+    /// provider.
+    ReplayDir(PathBuf),
+}
+
+impl Upstream {
+    /// `CRABOT_REPLAY_DIR` replays cassettes recorded to `CRABOT_RECORD_DIR`.
+    pub fn from_env() -> Self {
+        let var = |name| std::env::var(name).ok().filter(|v| !v.is_empty());
+        match (var("CRABOT_REPLAY_DIR"), var("CRABOT_RECORD_DIR")) {
+            (Some(dir), _) => Self::ReplayDir(dir.into()),
+            (None, Some(dir)) => Self::Record(dir.into()),
+            (None, None) => Self::Http,
+        }
+    }
+
+    /// Whether requests reach the provider, which requires its API key.
+    pub fn is_live(&self) -> bool {
+        matches!(self, Self::Http | Self::Record(_))
+    }
+
+    /// Starts the upstream requests of one answer.
+    pub fn session(&self, provider: &str, model: &str, messages: &[ChatMessage]) -> Arc<Session> {
+        let cassette = Cassette {
+            provider: provider.into(),
+            model: model.into(),
+            recorded_at: chrono::Utc::now(),
+            exchanges: vec![],
+        };
+        let mode = match self {
+            Self::Http => Mode::Http,
+            Self::Record(dir) => Mode::Record(dir.clone()),
+            Self::Replay { cassette, realtime } => Mode::Replay {
+                cassette: cassette.clone(),
+                realtime: *realtime,
+            },
+            Self::ReplayDir(dir) => {
+                let prompt = messages
+                    .iter()
+                    .rev()
+                    .find(|m| m.role == Role::User)
+                    .map(|m| m.content.text());
+                match find_cassette(dir, provider, prompt.as_deref()) {
+                    Ok(cassette) => Mode::Replay {
+                        cassette: Arc::new(cassette),
+                        realtime: true,
+                    },
+                    Err(e) => {
+                        tracing::error!("Replay: {:#}", e);
+                        Mode::Replay {
+                            cassette: Arc::new(cassette.clone()),
+                            realtime: true,
+                        }
+                    }
+                }
+            }
+        };
+
+        Arc::new(Session {
+            mode,
+            cassette: Mutex::new(cassette),
+            replayed: Mutex::new(0),
+        })
+    }
+}
+
+fn find_cassette(dir: &Path, provider: &str, prompt: Option<&str>) -> anyhow::Result<Cassette> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {:?}", dir))?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("json"))
+        .collect();
+    paths.sort();
+
+    let cassettes: Vec<_> = paths
+        .iter()
+        .filter_map(|path| {
+            Cassette::open(path)
+                .map_err(|e| tracing::error!("Replay: {:#}", e))
+                .ok()
+        })
+        .filter(|c| c.provider == provider)
+        .collect();
+
+    let matching = cassettes
+        .iter()
+        .position(|c| prompt.is_some() && c.prompt().as_deref() == prompt);
+    match matching.or((!cassettes.is_empty()).then_some(0)) {
+        Some(i) => Ok(cassettes[i].clone()),
+        None => anyhow::bail!("No {} cassette in {:?}", provider, dir),
+    }
+}
+
+enum Mode {
+    Http,
+    Record(PathBuf),
+    Replay {
+        cassette: Arc<Cassette>,
+        realtime: bool,
+    },
+}
+
+/// A response whose body is streamed.
+pub struct Response {
+    pub status: u16,
+    pub body: Box<dyn Read + Send>,
+}
+
+/// The upstream requests of one answer.
+pub struct Session {
+    mode: Mode,
+    /// Exchanges recorded so far.
+    cassette: Mutex<Cassette>,
+    /// Exchanges replayed so far.
+    replayed: Mutex<usize>,
+}
+
+impl Session {
+    /// Posts a chat completion request. Blocks until the response headers are received.
+    pub fn send(
+        self: &Arc<Self>,
+        url: &str,
+        api_key: &str,
+        body: Value,
+    ) -> Result<Response, Box<ureq::Error>> {
+        match &self.mode {
+            Mode::Http => post(url, api_key, body),
+            Mode::Record(_) => self.record(url, api_key, body),
+            Mode::Replay { cassette, realtime } => {
+                let mut replayed = self.replayed.lock().unwrap();
+                let exchange = cassette.exchanges.get(*replayed).cloned();
+                *replayed += 1;
+                replay(exchange, *realtime)
+            }
+        }
+    }
+
+    fn record(
+        self: &Arc<Self>,
+        url: &str,
+        api_key: &str,
+        body: Value,
+    ) -> Result<Response, Box<ureq::Error>> {
+        let started_at = Instant::now();
+        let request = body.clone();
+        let (status, body, error) = match post(url, api_key, body) {
+            Ok(response) => (response.status, response.body, None),
+            Err(e) => match *e {
+                ureq::Error::Status(status, response) => {
+                    let status_text = response.status_text().to_string();
+                    let body = response.into_string().unwrap_or_default();
+                    (
+                        status,
+                        Box::new(io::Cursor::new(body.clone())) as Box<dyn Read + Send>,
+                        Some((status_text, body)),
+                    )
+                }
+                e => return Err(Box::new(e)),
+            },
+        };
+
+        let index = {
+            let mut cassette = self.cassette.lock().unwrap();
+            cassette.exchanges.push(Exchange {
+                request,
+                status,
+                chunks: vec![],
+            });
+            cassette.exchanges.len() - 1
+        };
+        let mut reader = Recorder {
+            inner: body,
+            session: self.clone(),
+            index,
+            last_read_at: started_at,
+        };
+
+        if let Some((status_text, body)) = error {
+            // Recorded in full, the caller only gets the status.
+            let _ = io::copy(&mut reader, &mut io::sink());
+            return Err(status_error(status, &status_text, &body));
+        }
+
+        Ok(Response {
+            status,
+            body: Box::new(reader),
+        })
+    }
+
+    /// Saves what was recorded, once the answer is complete.
+    pub fn finish(&self) {
+        let Mode::Record(dir) = &self.mode else {
+            return;
+        };
+        let cassette = self.cassette.lock().unwrap();
+        if cassette.exchanges.is_empty() {
+            return;
+        }
+
+        let path = dir.join(format!("{}-{}.json", cassette.provider, Uuid::new_v4()));
+        let result = fs::create_dir_all(dir)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(fs::write(&path, serde_json::to_vec_pretty(&*cassette)?)?));
+        match result {
+            Ok(()) => tracing::info!("Recorded {:?}", path),
+            Err(e) => tracing::error!("Could not record {:?}: {}", path, e),
+        }
+    }
+}
+
+fn post(url: &str, api_key: &str, body: Value) -> Result<Response, Box<ureq::Error>> {
+    let response = ureq::post(url)
+        .set("Authorization", &format!("Bearer {}", api_key))
+        .send_json(body)
+        .map_err(Box::new)?;
+
+    Ok(Response {
+        status: response.status(),
+        body: Box::new(response.into_reader()),
+    })
+}
+
+fn status_error(status: u16, status_text: &str, body: &str) -> Box<ureq::Error> {
+    let response = ureq::Response::new(status, status_text, body)
+        .expect("Failed to build a response from a recorded status");
+    Box::new(ureq::Error::Status(status, response))
+}
+
+fn replay(exchange: Option<Exchange>, realtime: bool) -> Result<Response, Box<ureq::Error>> {
+    let Some(exchange) = exchange else {
+        return Err(status_error(
+            404,
+            "Not Found",
+            "No more recorded exchanges in the cassette",
+        ));
+    };
+
+    if exchange.status >= 400 {
+        let body: Vec<u8> = exchange.chunks.iter().flat_map(Chunk::bytes).collect();
+        return Err(status_error(
+            exchange.status,
+            "Recorded error",
+            &String::from_utf8_lossy(&body),
+        ));
+    }
+
+    Ok(Response {
+        status: exchange.status,
+        body: Box::new(Player {
+            chunks: exchange.chunks.into_iter(),
+            pending: io::Cursor::new(vec![]),
+            realtime,
+        }),
+    })
+}
+
+/// Copies the bytes of a response to its exchange as they are read.
+struct Recorder {
+    inner: Box<dyn Read + Send>,
+    session: Arc<Session>,
+    index: usize,
+    last_read_at: Instant,
+}
+
+impl Read for Recorder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            let now = Instant::now();
+            let chunk = Chunk::new(now - self.last_read_at, &buf[..n]);
+            self.last_read_at = now;
+            self.session.cassette.lock().unwrap().exchanges[self.index]
+                .chunks
+                .push(chunk);
+        }
+        Ok(n)
+    }
+}
+
+/// Reads recorded chunks back, split and timed as they were received. Waiting between chunks
+/// blocks, as network reads do, so it is only read from the reader thread of `parse_event_stream`.
+struct Player {
+    chunks: std::vec::IntoIter<Chunk>,
+    pending: io::Cursor<Vec<u8>>,
+    realtime: bool,
+}
+
+impl Read for Player {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.pending.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+
+            let Some(chunk) = self.chunks.next() else {
+                return Ok(0);
+            };
+            if self.realtime {
+                thread::sleep(Duration::from_millis(chunk.delay_ms));
+            }
+            self.pending = io::Cursor::new(chunk.bytes());
+        }
+    }
+}
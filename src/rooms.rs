@@ -0,0 +1,164 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use futures::StreamExt;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::generations::{GenerationLog, Update};
+
+/// Events a room can fall behind on before its slowest viewer starts missing some.
+const CAPACITY: usize = 256;
+
+/// What viewers of a shared conversation are told.
+#[derive(Debug, Clone)]
+pub enum RoomEvent {
+    /// An update of an answer. `origin` is the page the prompt was posted from, which already
+    /// follows the answer on its own.
+    Update {
+        origin: Option<Uuid>,
+        message_id: Uuid,
+        update: Update,
+    },
+    /// Usernames of everyone viewing the conversation.
+    Presence(Vec<String>),
+}
+
+/// Who a connected page belongs to.
+struct Presence {
+    user: Uuid,
+    username: String,
+}
+
+struct Room {
+    events: broadcast::Sender<RoomEvent>,
+    /// Each connected page, by viewer id.
+    viewers: HashMap<Uuid, Presence>,
+}
+
+impl Room {
+    fn announce_presence(&self) {
+        let names: BTreeSet<_> = self.viewers.values().map(|p| p.username.clone()).collect();
+        let _ = self
+            .events
+            .send(RoomEvent::Presence(names.into_iter().collect()));
+    }
+}
+
+/// Conversations being viewed live, by conversation id. Rooms exist while they have viewers.
+#[derive(Clone, Default)]
+pub struct Rooms {
+    rooms: Arc<Mutex<HashMap<Uuid, Room>>>,
+}
+
+impl Rooms {
+    /// Broadcasts an event to the viewers of a conversation, if any.
+    pub fn publish(&self, conversation: Uuid, event: RoomEvent) {
+        if let Some(room) = self.rooms.lock().unwrap().get(&conversation) {
+            let _ = room.events.send(event);
+        }
+    }
+
+    /// Tells the viewers of a conversation who is present again, which has them re-check they
+    /// may still view it, after members were removed.
+    pub fn refresh(&self, conversation: Uuid) {
+        if let Some(room) = self.rooms.lock().unwrap().get(&conversation) {
+            room.announce_presence();
+        }
+    }
+
+    /// Publishes the updates of a generation to its conversation's viewers as they come.
+    pub fn forward(&self, conversation: Uuid, origin: Option<Uuid>, log: &GenerationLog) {
+        let rooms = self.clone();
+        let message_id = log.id;
+        let events = log.events(None);
+        tokio::spawn(async move {
+            let mut events = std::pin::pin!(events);
+            while let Some(event) = events.next().await {
+                rooms.publish(
+                    conversation,
+                    RoomEvent::Update {
+                        origin,
+                        message_id,
+                        update: event.update,
+                    },
+                );
+            }
+        });
+    }
+
+    /// Subscribes a page of a user to a conversation, it is counted as present until the
+    /// returned viewer is dropped. The viewer id is the page's to tell which prompts it posted.
+    pub fn join(&self, conversation: Uuid, user: Uuid, username: String) -> Viewer {
+        let viewer = Uuid::new_v4();
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = rooms.entry(conversation).or_insert_with(|| Room {
+            events: broadcast::channel(CAPACITY).0,
+            viewers: HashMap::new(),
+        });
+        room.viewers.insert(viewer, Presence { user, username });
+        let events = room.events.subscribe();
+        room.announce_presence();
+
+        Viewer {
+            rooms: self.clone(),
+            conversation,
+            id: viewer,
+            events,
+        }
+    }
+
+    /// Whether a viewer of a conversation is a page of this user.
+    pub fn is_viewer(&self, conversation: Uuid, viewer: Uuid, user: Uuid) -> bool {
+        self.rooms
+            .lock()
+            .unwrap()
+            .get(&conversation)
+            .and_then(|room| room.viewers.get(&viewer))
+            .is_some_and(|presence| presence.user == user)
+    }
+
+    fn leave(&self, conversation: Uuid, viewer: Uuid) {
+        let mut rooms = self.rooms.lock().unwrap();
+        let Some(room) = rooms.get_mut(&conversation) else {
+            return;
+        };
+        room.viewers.remove(&viewer);
+        if room.viewers.is_empty() {
+            rooms.remove(&conversation);
+        } else {
+            room.announce_presence();
+        }
+    }
+}
+
+/// A page following a conversation live.
+pub struct Viewer {
+    rooms: Rooms,
+    conversation: Uuid,
+    pub id: Uuid,
+    events: broadcast::Receiver<RoomEvent>,
+}
+
+impl Viewer {
+    /// Next event of the room. Events missed by a lagging viewer are skipped.
+    pub async fn recv(&mut self) -> Option<RoomEvent> {
+        loop {
+            match self.events.recv().await {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!("Room viewer {} missed {} events", self.id, missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl Drop for Viewer {
+    fn drop(&mut self) {
+        self.rooms.leave(self.conversation, self.id);
+    }
+}
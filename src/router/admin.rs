@@ -0,0 +1,251 @@
+use askama::Template;
+
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::{delete, get, post},
+    Form, Router,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::auth::{
+    keys::{ApiKey, NewApiKey},
+    middleware::AdminUser,
+    User, UserRole,
+};
+use crate::models::ChatModel;
+use crate::state::AppState;
+use crate::template::HtmlTemplate;
+
+pub fn admin_router() -> Router<AppState> {
+    Router::new()
+        .route("/admin", get(get_admin))
+        .route("/admin/users", post(create_user))
+        .route("/admin/users/:id", delete(delete_user))
+        .route("/admin/keys", get(get_keys).post(create_key))
+        .route("/admin/keys/:id", delete(revoke_key))
+}
+
+struct UserRow {
+    user: User,
+    conversations: usize,
+}
+
+#[derive(Template)]
+#[template(path = "pages/admin.html")]
+struct AdminTemplate {
+    current_user: Uuid,
+    users: Vec<UserRow>,
+    error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "elements/users.html")]
+struct UsersTemplate {
+    current_user: Uuid,
+    users: Vec<UserRow>,
+    error: Option<String>,
+}
+
+fn user_rows(state: &AppState) -> Vec<UserRow> {
+    state
+        .users
+        .list()
+        .into_iter()
+        .map(|user| UserRow {
+            conversations: state.store.list_for(user.id).len(),
+            user,
+        })
+        .collect()
+}
+
+async fn get_admin(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+) -> impl IntoResponse {
+    HtmlTemplate(AdminTemplate {
+        current_user: admin.id,
+        users: user_rows(&state),
+        error: None,
+    })
+}
+
+#[derive(Deserialize)]
+struct NewUser {
+    username: String,
+    password: String,
+    #[serde(default)]
+    admin: bool,
+}
+
+async fn create_user(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    Form(new_user): Form<NewUser>,
+) -> impl IntoResponse {
+    let users = state.users.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let role = match new_user.admin {
+            true => UserRole::Admin,
+            false => UserRole::User,
+        };
+        users.create(&new_user.username, &new_user.password, role)
+    })
+    .await
+    .expect("Failed to join user creation task");
+
+    let error = result.err().map(|e| e.to_string());
+
+    HtmlTemplate(UsersTemplate {
+        current_user: admin.id,
+        users: user_rows(&state),
+        error,
+    })
+}
+
+async fn delete_user(
+    State(state): State<AppState>,
+    AdminUser(admin): AdminUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    // Conversations are kept, admins can still read them.
+    let error = if id == admin.id {
+        Some("You cannot delete your own account".to_string())
+    } else {
+        state
+            .users
+            .remove(id)
+            .and_then(|_| state.sessions.remove_user(id))
+            .and_then(|_| state.api_keys.revoke_user(id))
+            .err()
+            .map(|e| {
+                tracing::error!("Auth: Could not remove user: {}", e);
+                e.to_string()
+            })
+    };
+
+    HtmlTemplate(UsersTemplate {
+        current_user: admin.id,
+        users: user_rows(&state),
+        error,
+    })
+}
+
+struct KeyRow {
+    key: ApiKey,
+    owner: String,
+}
+
+#[derive(Template)]
+#[template(path = "elements/keys.html")]
+struct KeysTemplate {
+    users: Vec<User>,
+    keys: Vec<KeyRow>,
+    /// Secret of the key just created, only ever shown once.
+    secret: Option<String>,
+    error: Option<String>,
+}
+
+impl KeysTemplate {
+    fn new(state: &AppState, secret: Option<String>, error: Option<String>) -> Self {
+        let users = state.users.list();
+        let keys = state
+            .api_keys
+            .list()
+            .into_iter()
+            .map(|key| KeyRow {
+                owner: users
+                    .iter()
+                    .find(|u| u.id == key.owner)
+                    .map(|u| u.username.clone())
+                    .unwrap_or_else(|| "(deleted)".into()),
+                key,
+            })
+            .collect();
+
+        Self {
+            users,
+            keys,
+            secret,
+            error,
+        }
+    }
+}
+
+async fn get_keys(State(state): State<AppState>, _: AdminUser) -> impl IntoResponse {
+    HtmlTemplate(KeysTemplate::new(&state, None, None))
+}
+
+#[derive(Deserialize)]
+struct NewKeyForm {
+    name: String,
+    owner: Uuid,
+    /// Comma separated model names, empty for all of them.
+    #[serde(default)]
+    models: String,
+    #[serde(default)]
+    daily_tokens: String,
+    #[serde(default)]
+    requests_per_minute: String,
+}
+
+/// Parses an optional numeric form field, empty meaning unlimited.
+fn limit<T: std::str::FromStr>(value: &str, field: &str) -> anyhow::Result<Option<T>> {
+    match value.trim() {
+        "" => Ok(None),
+        v => v
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow::anyhow!("Invalid {}: `{}`", field, v)),
+    }
+}
+
+fn parse_new_key(state: &AppState, form: NewKeyForm) -> anyhow::Result<NewApiKey> {
+    let models = form
+        .models
+        .split(',')
+        .filter(|m| !m.trim().is_empty())
+        .map(str::parse::<ChatModel>)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if state.users.get(form.owner).is_none() {
+        anyhow::bail!("Unknown user");
+    }
+
+    Ok(NewApiKey {
+        daily_tokens: limit(&form.daily_tokens, "daily token budget")?,
+        requests_per_minute: limit(&form.requests_per_minute, "rate limit")?,
+        name: form.name,
+        owner: form.owner,
+        models,
+    })
+}
+
+async fn create_key(
+    State(state): State<AppState>,
+    _: AdminUser,
+    Form(form): Form<NewKeyForm>,
+) -> impl IntoResponse {
+    let new_key = parse_new_key(&state, form);
+
+    match new_key.and_then(|k| state.api_keys.create(k)) {
+        Ok((key, secret)) => {
+            tracing::info!("Auth: Created API key {} ({})", key.name, key.id);
+            HtmlTemplate(KeysTemplate::new(&state, Some(secret), None))
+        }
+        Err(e) => HtmlTemplate(KeysTemplate::new(&state, None, Some(e.to_string()))),
+    }
+}
+
+async fn revoke_key(
+    State(state): State<AppState>,
+    _: AdminUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let error = state.api_keys.revoke(id).err().map(|e| {
+        tracing::error!("Auth: Could not revoke API key: {}", e);
+        e.to_string()
+    });
+
+    HtmlTemplate(KeysTemplate::new(&state, None, error))
+}
@@ -0,0 +1,50 @@
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use tower_http::{services::ServeFile, set_header::SetResponseHeaderLayer};
+use uuid::Uuid;
+
+use crate::auth::middleware::CurrentUser;
+use crate::state::AppState;
+
+/// Serves uploaded files. They are sandboxed so an uploaded HTML page cannot run scripts on
+/// crabot's origin.
+pub fn attachments_router() -> Router<AppState> {
+    Router::new()
+        .route("/attachments/:id/:name", get(get_attachment))
+        .layer(SetResponseHeaderLayer::overriding(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("sandbox"),
+        ))
+        .layer(SetResponseHeaderLayer::overriding(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        ))
+}
+
+/// Files of conversations the user can't read are not found, like the conversations.
+async fn get_attachment(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path((id, name)): Path<(Uuid, String)>,
+    request: Request,
+) -> Result<Response, StatusCode> {
+    let attachment = state
+        .store
+        .find_attachment(id, &user)
+        .filter(|a| a.name == name)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let path = state.attachments.path(&attachment);
+    match ServeFile::new(&path).try_call(request).await {
+        Ok(response) => Ok(response.into_response()),
+        Err(e) => {
+            tracing::error!("Could not serve {:?}: {}", path, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use askama::Template;
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header::SET_COOKIE, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Form, Router,
+};
+use axum_extra::extract::CookieJar;
+use serde::Deserialize;
+
+use crate::auth::session::{SESSION_COOKIE, SESSION_DAYS};
+use crate::limits::ClientKey;
+use crate::state::AppState;
+use crate::template::HtmlTemplate;
+
+pub fn auth_router() -> Router<AppState> {
+    Router::new()
+        .route("/login", get(get_login).post(post_login))
+        .route("/logout", post(post_logout))
+}
+
+#[derive(Template)]
+#[template(path = "pages/login.html")]
+struct LoginTemplate {
+    /// No account exists yet, the form creates the first administrator.
+    setup: bool,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+async fn get_login(State(state): State<AppState>) -> impl IntoResponse {
+    HtmlTemplate(LoginTemplate {
+        setup: state.users.is_empty(),
+        error: None,
+    })
+}
+
+async fn post_login(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    Form(credentials): Form<Credentials>,
+) -> Response {
+    let setup = state.users.is_empty();
+
+    // Slows down password guessing.
+    let ip = connect_info.map_or(Ipv4Addr::UNSPECIFIED.into(), |ConnectInfo(addr)| addr.ip());
+    if let Err(e) = state.limits.logins.check(ClientKey::Ip(ip)) {
+        tracing::warn!("Auth: Too many login attempts from {}", ip);
+        let page = HtmlTemplate(LoginTemplate {
+            setup,
+            error: Some(e.to_string()),
+        });
+        return (StatusCode::TOO_MANY_REQUESTS, page).into_response();
+    }
+    let users = state.users.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        if setup {
+            users.create_first(&credentials.username, &credentials.password)
+        } else {
+            users
+                .authenticate(&credentials.username, &credentials.password)
+                .ok_or_else(|| anyhow::anyhow!("Invalid username or password"))
+        }
+    })
+    .await
+    .expect("Failed to join login task");
+
+    let session = result.and_then(|user| {
+        tracing::info!("Auth: {} logged in", user.username);
+        state.sessions.create(user.id)
+    });
+
+    match session {
+        Ok(token) => (
+            [(
+                SET_COOKIE,
+                format!(
+                    "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+                    SESSION_COOKIE,
+                    token,
+                    SESSION_DAYS * 24 * 60 * 60
+                ),
+            )],
+            Redirect::to("/"),
+        )
+            .into_response(),
+        Err(e) => HtmlTemplate(LoginTemplate {
+            setup: state.users.is_empty(),
+            error: Some(e.to_string()),
+        })
+        .into_response(),
+    }
+}
+
+async fn post_logout(State(state): State<AppState>, jar: CookieJar) -> impl IntoResponse {
+    if let Some(cookie) = jar.get(SESSION_COOKIE) {
+        if let Err(e) = state.sessions.remove(cookie.value()) {
+            tracing::error!("Auth: Could not remove session: {}", e);
+        }
+    }
+
+    (
+        [(
+            SET_COOKIE,
+            format!(
+                "{}=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax",
+                SESSION_COOKIE
+            ),
+        )],
+        Redirect::to("/login"),
+    )
+}
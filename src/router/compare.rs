@@ -0,0 +1,259 @@
+use askama::Template;
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, Path, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Extension, Form, Router,
+};
+use futures::stream::{self, once};
+use serde::Deserialize;
+use std::{collections::HashSet, convert::Infallible};
+use tokio_stream::StreamExt as _;
+use uuid::Uuid;
+
+use super::index::chunk_span;
+use crate::auth::{
+    keys::ApiKey,
+    middleware::{AdminUser, CurrentUser},
+};
+use crate::chat::{ChatError, PostMessage};
+use crate::compare::{self, Column, CompareUpdate, Comparison};
+use crate::models::ChatModel;
+use crate::state::AppState;
+use crate::template::HtmlTemplate;
+
+pub fn compare_router() -> Router<AppState> {
+    Router::new()
+        .route("/compare", get(get_compare).post(post_compare))
+        .route("/compare/export", get(export_comparisons))
+        .route("/compare/:id/preferred", post(set_preferred))
+}
+
+#[derive(Template)]
+#[template(path = "pages/compare.html")]
+struct CompareTemplate {
+    conversation_id: Option<Uuid>,
+    models: Vec<ChatModel>,
+}
+
+#[derive(Template)]
+#[template(path = "elements/comparison.html")]
+struct ComparisonTemplate<'a> {
+    comparison: &'a Comparison,
+}
+
+#[derive(Template)]
+#[template(path = "elements/column_stats.html")]
+struct ColumnStatsTemplate<'a> {
+    column: &'a Column,
+}
+
+#[derive(Template)]
+#[template(path = "elements/votes.html")]
+struct VotesTemplate<'a> {
+    comparison: &'a Comparison,
+}
+
+#[derive(Template)]
+#[template(path = "elements/error.html")]
+struct ErrorTemplate<'a> {
+    message: &'a str,
+}
+
+#[derive(Deserialize)]
+struct CompareQuery {
+    conversation: Option<Uuid>,
+}
+
+async fn get_compare(Query(query): Query<CompareQuery>) -> impl IntoResponse {
+    HtmlTemplate(CompareTemplate {
+        conversation_id: query.conversation,
+        models: ChatModel::enabled(),
+    })
+}
+
+/// A prompt and the models to compare, each checked model sent as its own `models` field.
+struct CompareInput {
+    data: PostMessage,
+    models: Vec<ChatModel>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for CompareInput {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_form = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/x-www-form-urlencoded"));
+        if !is_form {
+            return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response());
+        }
+
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let invalid =
+            || (StatusCode::UNPROCESSABLE_ENTITY, "Invalid comparison form").into_response();
+
+        let (models, fields): (Vec<_>, Vec<_>) =
+            serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+                .map_err(|_| invalid())?
+                .into_iter()
+                .partition(|(name, _)| name == "models");
+        let mut models = models
+            .iter()
+            .map(|(_, model)| model.parse::<ChatModel>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response())?;
+        // Each model once, in the order they were picked.
+        let mut picked = HashSet::new();
+        models.retain(|model| picked.insert(*model));
+
+        // Other fields go through the same deserializer as the chat form.
+        let data = serde_urlencoded::to_string(&fields)
+            .ok()
+            .and_then(|fields| serde_urlencoded::from_str::<PostMessage>(&fields).ok())
+            .ok_or_else(invalid)?;
+
+        Ok(Self { data, models })
+    }
+}
+
+async fn post_compare(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    api_key: Option<Extension<ApiKey>>,
+    headers: HeaderMap,
+    CompareInput { data, models }: CompareInput,
+) -> Result<Response, Response> {
+    let is_htmx = headers.contains_key("HX-Request");
+    if models.len() < 2 {
+        let message = "Select at least two models to compare";
+        return match is_htmx {
+            true => Ok(error_events(message)),
+            false => Err((StatusCode::UNPROCESSABLE_ENTITY, message).into_response()),
+        };
+    }
+
+    let api_key = api_key.map(|Extension(key)| key);
+    let (comparison, updates) = match compare::start(&state, &user, api_key, data, models).await {
+        Ok(started) => started,
+        // The compare form cannot display error responses, tell it over the event stream.
+        Err(e @ (ChatError::Limit(_) | ChatError::InvalidSchema(_))) if is_htmx => {
+            return Ok(error_events(&e.to_string()))
+        }
+        Err(e) => return Err(e.into_response()),
+    };
+
+    let columns = Event::default().event("comparison").data(
+        ComparisonTemplate {
+            comparison: &comparison,
+        }
+        .render()
+        .unwrap()
+        .replace(['\r', '\n'], ""),
+    );
+    let events = updates.map(|update| render_update(&update));
+
+    Ok(Sse::new(
+        once(async { columns })
+            .chain(events)
+            .map(Ok::<_, Infallible>),
+    )
+    .keep_alive(KeepAlive::default())
+    .into_response())
+}
+
+fn error_events(message: &str) -> Response {
+    let html = ErrorTemplate { message }
+        .render()
+        .unwrap()
+        .replace(['\r', '\n'], "");
+    let events = [
+        Ok::<_, Infallible>(Event::default().event("error").data(html)),
+        Ok(Event::default().event("end")),
+    ];
+    Sse::new(stream::iter(events)).into_response()
+}
+
+/// Renders a comparison update as the HTML fragment the compare page swaps in.
+fn render_update(update: &CompareUpdate) -> Event {
+    let (name, html) = match update {
+        CompareUpdate::Chunk { column, text } => ("chunk", chunk_span(*column, text)),
+        CompareUpdate::Done(column) => ("stats", ColumnStatsTemplate { column }.render().unwrap()),
+        CompareUpdate::End(comparison) => ("end", VotesTemplate { comparison }.render().unwrap()),
+    };
+
+    Event::default()
+        .event(name)
+        .data(html.replace(['\r', '\n'], ""))
+}
+
+#[derive(Deserialize)]
+struct Vote {
+    column: Uuid,
+}
+
+async fn set_preferred(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<Uuid>,
+    Form(vote): Form<Vote>,
+) -> Result<Response, StatusCode> {
+    state
+        .comparisons
+        .get(id)
+        .filter(|c| c.owner == user.id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let comparison = state
+        .comparisons
+        .set_preferred(id, vote.column)
+        .map_err(|e| {
+            tracing::error!("Could not record vote: {}", e);
+            StatusCode::UNPROCESSABLE_ENTITY
+        })?;
+
+    Ok(HtmlTemplate(VotesTemplate {
+        comparison: &comparison,
+    })
+    .into_response())
+}
+
+/// Every comparison with its vote, one JSON object per line.
+async fn export_comparisons(
+    State(state): State<AppState>,
+    AdminUser(_admin): AdminUser,
+) -> Result<Response, StatusCode> {
+    let mut jsonl = String::new();
+    for comparison in state.comparisons.list() {
+        let line = serde_json::to_string(&comparison).map_err(|e| {
+            tracing::error!("Could not export comparison: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        jsonl.push_str(&line);
+        jsonl.push('\n');
+    }
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/jsonl"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"comparisons.jsonl\"",
+            ),
+        ],
+        jsonl,
+    )
+        .into_response())
+}
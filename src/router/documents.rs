@@ -0,0 +1,151 @@
+use askama::Template;
+
+use axum::{
+    extract::{DefaultBodyLimit, Multipart, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Router,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::auth::middleware::CurrentUser;
+use crate::knowledge::{extract_text, Document};
+use crate::state::AppState;
+use crate::template::HtmlTemplate;
+
+/// Largest accepted upload, PDFs can get big.
+const MAX_UPLOAD_BYTES: usize = 20 * 1024 * 1024;
+
+pub fn documents_router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/documents",
+            get(get_documents)
+                .post(upload_document)
+                .layer(DefaultBodyLimit::max(MAX_UPLOAD_BYTES)),
+        )
+        .route("/documents/:id", delete(delete_document))
+}
+
+#[derive(Template)]
+#[template(path = "elements/documents.html")]
+struct DocumentsTemplate {
+    conversation_id: Uuid,
+    documents: Vec<Document>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DocumentsQuery {
+    conversation: Uuid,
+}
+
+async fn get_documents(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<DocumentsQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if !state.store.can_access(query.conversation, &user) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(HtmlTemplate(DocumentsTemplate {
+        conversation_id: query.conversation,
+        documents: state.knowledge.documents(query.conversation),
+        error: None,
+    }))
+}
+
+async fn upload_document(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, Response> {
+    let mut conversation = None;
+    let mut shared = false;
+    let mut file = None;
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(IntoResponse::into_response)?
+    {
+        match field.name() {
+            Some("conversation") => {
+                let value = field.text().await.map_err(IntoResponse::into_response)?;
+                conversation = value.parse::<Uuid>().ok();
+            }
+            Some("shared") => shared = true,
+            Some("file") => {
+                let name = field.file_name().unwrap_or("document.txt").to_string();
+                let data = field.bytes().await.map_err(IntoResponse::into_response)?;
+                file = Some((name, data));
+            }
+            _ => (),
+        }
+    }
+
+    let conversation_id =
+        conversation.ok_or((StatusCode::BAD_REQUEST, "Missing conversation").into_response())?;
+    let (name, data) = file.ok_or((StatusCode::BAD_REQUEST, "Missing file").into_response())?;
+
+    if !state.store.can_access(conversation_id, &user) {
+        return Err(StatusCode::NOT_FOUND.into_response());
+    }
+    // Shared documents show up in every user's conversations.
+    if shared && !user.is_admin() {
+        return Ok(HtmlTemplate(DocumentsTemplate {
+            conversation_id,
+            documents: state.knowledge.documents(conversation_id),
+            error: Some("Only administrators can share documents".into()),
+        }));
+    }
+
+    let knowledge = state.knowledge.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let text = extract_text(&name, &data)?;
+        knowledge.add_document(name, &text, (!shared).then_some(conversation_id))
+    })
+    .await
+    .expect("Failed to join indexing task");
+
+    let error = result.err().map(|e| {
+        tracing::error!("Knowledge: Could not add document: {:#}", e);
+        format!("{:#}", e)
+    });
+
+    Ok(HtmlTemplate(DocumentsTemplate {
+        conversation_id,
+        documents: state.knowledge.documents(conversation_id),
+        error,
+    }))
+}
+
+async fn delete_document(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DocumentsQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let allowed = match state.knowledge.document(id).map(|d| d.conversation) {
+        Some(Some(conversation)) => state.store.can_access(conversation, &user),
+        Some(None) => user.is_admin(),
+        None => false,
+    };
+    if !allowed {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let error = state.knowledge.remove_document(id).err().map(|e| {
+        tracing::error!("Knowledge: Could not remove document: {}", e);
+        e.to_string()
+    });
+
+    Ok(HtmlTemplate(DocumentsTemplate {
+        conversation_id: query.conversation,
+        documents: state.knowledge.documents(query.conversation),
+        error,
+    }))
+}
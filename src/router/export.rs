@@ -0,0 +1,118 @@
+use axum::{
+    extract::{DefaultBodyLimit, Multipart, Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::auth::middleware::CurrentUser;
+use crate::export::{import, ExportFormat};
+use crate::state::AppState;
+
+/// ChatGPT exports bundle every conversation of an account in a single file.
+const MAX_IMPORT_BYTES: usize = 50 * 1024 * 1024;
+
+pub fn export_router() -> Router<AppState> {
+    Router::new()
+        .route("/c/:id/export", get(export_conversation))
+        .route(
+            "/import",
+            post(import_conversations).layer(DefaultBodyLimit::max(MAX_IMPORT_BYTES)),
+        )
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+async fn export_conversation(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ExportQuery>,
+) -> Result<impl IntoResponse, Response> {
+    let conversation = state
+        .store
+        .get(id)
+        .filter(|c| c.is_visible_to(&user))
+        .ok_or((StatusCode::NOT_FOUND, "Conversation not found").into_response())?;
+
+    let body = query.format.export(&conversation).map_err(|e| {
+        tracing::error!("Export: Could not export conversation {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })?;
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                query.format.content_type().to_string(),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"crabot-{}.{}\"",
+                    id,
+                    query.format.extension()
+                ),
+            ),
+        ],
+        body,
+    ))
+}
+
+async fn import_conversations(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, Response> {
+    let mut data = None;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(IntoResponse::into_response)?
+    {
+        if field.name() == Some("file") {
+            data = Some(field.bytes().await.map_err(IntoResponse::into_response)?);
+        }
+    }
+
+    let data = data.ok_or((StatusCode::BAD_REQUEST, "Missing file").into_response())?;
+    let conversations =
+        import(&data).map_err(|e| (StatusCode::BAD_REQUEST, format!("{:#}", e)).into_response())?;
+
+    let mut imported = vec![];
+    for mut conversation in conversations {
+        // Imports are copies: reusing the exported ids would let them take over the originals,
+        // or their messages' search entries, and importing twice keeps both copies.
+        conversation.id = Uuid::new_v4();
+        for message in conversation.messages.iter_mut() {
+            message.id = Uuid::new_v4();
+        }
+        // Imports are private to whoever uploaded them.
+        conversation.owner = Some(user.id);
+        conversation.members.clear();
+        let id = conversation.id;
+        state.store.insert(conversation).map_err(|e| {
+            tracing::error!("Import: Could not store conversation {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })?;
+        imported.push(id);
+    }
+
+    let redirect = match imported.first() {
+        Some(id) => format!("/c/{}", id),
+        None => "/".into(),
+    };
+
+    Ok((
+        [("HX-Redirect", redirect)],
+        Json(json!({ "imported": imported })),
+    ))
+}
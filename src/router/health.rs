@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Serialize;
+
+use crate::knowledge;
+use crate::models::ChatModel;
+use crate::state::AppState;
+
+pub fn health_router() -> Router<AppState> {
+    Router::new()
+        .route("/healthz", get(get_healthz))
+        .route("/readyz", get(get_readyz))
+        .route("/version", get(get_version))
+}
+
+/// Liveness: answers as long as the process serves requests.
+async fn get_healthz() -> &'static str {
+    "ok"
+}
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn from_result(result: anyhow::Result<()>) -> Self {
+        match result {
+            Ok(()) => Self {
+                ok: true,
+                error: None,
+            },
+            Err(e) => Self {
+                ok: false,
+                error: Some(format!("{:#}", e)),
+            },
+        }
+    }
+
+    fn failed(error: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            error: Some(error.into()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    shutting_down: bool,
+    storage: Check,
+    embeddings: Check,
+    /// Credentials of every enabled model.
+    providers: HashMap<ChatModel, Check>,
+}
+
+/// Readiness: storage is writable, local models are loaded and enabled models have credentials.
+/// Answers `503` while any check fails, and once shutdown started.
+async fn get_readyz(State(state): State<AppState>) -> Response {
+    let shutting_down = state.shutdown.is_started();
+    let storage = Check::from_result(state.store.check_writable());
+    let embeddings = match knowledge::preload_enabled() && !state.knowledge.embedder_loaded() {
+        true => Check::failed("Embedding model not loaded"),
+        false => Check::from_result(Ok(())),
+    };
+    let providers: HashMap<_, _> = ChatModel::enabled()
+        .into_iter()
+        .map(|model| match model.has_credentials() {
+            true => (model, Check::from_result(Ok(()))),
+            false => (
+                model,
+                Check::failed(format!(
+                    "{} is not set",
+                    model.credentials_var().unwrap_or_default()
+                )),
+            ),
+        })
+        .collect();
+
+    let ready =
+        !shutting_down && storage.ok && embeddings.ok && providers.values().all(|check| check.ok);
+    let status = match ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (
+        status,
+        Json(Readiness {
+            ready,
+            shutting_down,
+            storage,
+            embeddings,
+            providers,
+        }),
+    )
+        .into_response()
+}
+
+#[derive(Serialize)]
+struct Version {
+    version: &'static str,
+    git_sha: &'static str,
+    models: Vec<ChatModel>,
+}
+
+async fn get_version() -> Json<Version> {
+    Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: option_env!("CRABOT_GIT_SHA").unwrap_or("unknown"),
+        models: ChatModel::enabled(),
+    })
+}
@@ -1,90 +1,291 @@
-use askama::Template;
+use askama::{Html, MarkupDisplay, Template};
 
+use axum::extract::{DefaultBodyLimit, FromRequest, Multipart, Path, Request, State};
+use axum::http::{header::CONTENT_TYPE, HeaderMap, StatusCode};
 use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::{
-    response::IntoResponse,
-    routing::{get, post},
-    Form, Router,
+    async_trait,
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Form, Router,
 };
-use futures::stream::{once, Stream};
-use serde::Deserialize;
+use futures::stream::{self, once};
 use std::convert::Infallible;
 use uuid::Uuid;
 
-use crate::models::{
-    gpt::GPT3Pipeline, lorem::LoremPipeline, mistral::MistralPipeline, ChatModel, Pipeline,
-};
+use crate::auth::{keys::ApiKey, middleware::CurrentUser, User};
+use crate::chat::{self, ChatError, PostMessage, Upload};
+use crate::generations::{GenerationEvent, Update};
+use crate::state::AppState;
+use crate::store::{Conversation, Message};
 use crate::template::HtmlTemplate;
+use crate::tools::ToolInvocation;
 use tokio_stream::StreamExt as _;
 
-pub fn index_router() -> Router {
+/// Largest accepted prompt, attachments included.
+const MAX_PROMPT_BYTES: usize = 20 * 1024 * 1024;
+
+pub fn index_router() -> Router<AppState> {
     Router::new()
-        .route("/", get(get_messages))
-        .route("/", post(post_message))
+        .route(
+            "/",
+            get(get_messages)
+                .post(post_message)
+                .layer(DefaultBodyLimit::max(MAX_PROMPT_BYTES)),
+        )
+        .route("/c/:id", get(get_conversation))
+        .route("/generations/:id/stream", get(get_generation_stream))
 }
 
 #[derive(Template)]
 #[template(path = "pages/index.html")]
 struct MessagesTemplate {
-    messages: Vec<MessageTemplate>,
+    user: User,
+    conversation_id: Uuid,
+    messages: Vec<Message>,
+    /// Listed in the sidebar.
+    conversations: Vec<Conversation>,
 }
 
-async fn get_messages() -> impl IntoResponse {
-    // TODO: Add a database?
-    let messages = vec![];
-    HtmlTemplate(MessagesTemplate { messages })
+async fn get_messages(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+) -> impl IntoResponse {
+    HtmlTemplate(MessagesTemplate {
+        conversation_id: Uuid::new_v4(),
+        messages: vec![],
+        conversations: state.store.list_joined(user.id),
+        user,
+    })
+}
+
+async fn get_conversation(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if !state.store.can_access(id, &user) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let messages = state.store.get(id).map(|c| c.messages).unwrap_or_default();
+    Ok(HtmlTemplate(MessagesTemplate {
+        conversation_id: id,
+        messages,
+        conversations: state.store.list_joined(user.id),
+        user,
+    }))
 }
 
 #[derive(Template)]
 #[template(path = "elements/message.html")]
-struct MessageTemplate {
-    id: Uuid,
-    input: PostMessage,
-    response: String,
+struct MessageTemplate<'a> {
+    message: &'a Message,
 }
 
-impl MessageTemplate {
-    pub fn new(input: PostMessage, response: String) -> Self {
-        Self {
-            id: Uuid::new_v4(),
-            input,
-            response,
-        }
-    }
+#[derive(Template)]
+#[template(path = "elements/tool.html")]
+struct ToolTemplate<'a> {
+    message_id: Uuid,
+    invocation: &'a ToolInvocation,
 }
 
-#[derive(Deserialize, Clone)]
-struct PostMessage {
-    prompt: String,
-    #[serde(default)]
-    model: ChatModel,
+#[derive(Template)]
+#[template(path = "elements/error.html")]
+struct ErrorTemplate<'a> {
+    message: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "elements/validation.html")]
+struct ValidationTemplate<'a> {
+    message_id: Uuid,
+    violations: &'a Vec<String>,
+}
+
+/// A posted message, sent url-encoded or, when files are attached, as multipart.
+struct MessageInput {
+    data: PostMessage,
+    uploads: Vec<Upload>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for MessageInput {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_multipart = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("multipart/form-data"));
+
+        if !is_multipart {
+            let Form(data) = Form::<PostMessage>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            return Ok(Self {
+                data,
+                uploads: vec![],
+            });
+        }
+
+        let mut multipart = Multipart::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let mut fields = vec![];
+        let mut uploads = vec![];
+
+        while let Some(field) = multipart
+            .next_field()
+            .await
+            .map_err(IntoResponse::into_response)?
+        {
+            let name = field.name().unwrap_or_default().to_string();
+            match field.file_name().map(str::to_string) {
+                // Browsers send an empty part for file inputs left empty.
+                Some(file_name) if file_name.is_empty() => (),
+                Some(file_name) => {
+                    let content_type = field
+                        .content_type()
+                        .unwrap_or("application/octet-stream")
+                        .to_string();
+                    let data = field.bytes().await.map_err(IntoResponse::into_response)?;
+                    uploads.push(Upload {
+                        name: file_name,
+                        content_type,
+                        data: data.to_vec(),
+                    });
+                }
+                None => {
+                    let value = field.text().await.map_err(IntoResponse::into_response)?;
+                    fields.push((name, value));
+                }
+            }
+        }
+
+        // Text fields go through the same deserializer as url-encoded forms.
+        let data = serde_urlencoded::to_string(&fields)
+            .ok()
+            .and_then(|fields| serde_urlencoded::from_str::<PostMessage>(&fields).ok())
+            .ok_or_else(|| {
+                (StatusCode::UNPROCESSABLE_ENTITY, "Invalid message form").into_response()
+            })?;
+
+        Ok(Self { data, uploads })
+    }
 }
 
 async fn post_message(
-    Form(data): Form<PostMessage>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let pipeline: Box<dyn Pipeline> = match data.model {
-        ChatModel::GPT3 => Box::new(GPT3Pipeline {}),
-        ChatModel::Mistral => Box::new(MistralPipeline {}),
-        ChatModel::Lorem => Box::new(LoremPipeline {}),
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    api_key: Option<Extension<ApiKey>>,
+    headers: HeaderMap,
+    MessageInput { data, uploads }: MessageInput,
+) -> Result<Response, Response> {
+    let api_key = api_key.map(|Extension(key)| key);
+    let log = match chat::start(&state, &user, api_key, data, uploads).await {
+        Ok(log) => log,
+        // The chat form cannot display error responses, tell it over the event stream instead.
+        Err(e @ (ChatError::Limit(_) | ChatError::InvalidSchema(_)))
+            if headers.contains_key("HX-Request") =>
+        {
+            let html = ErrorTemplate {
+                message: &e.to_string(),
+            }
+            .render()
+            .unwrap()
+            .replace(['\r', '\n'], "");
+            let events = [
+                Ok::<_, Infallible>(Event::default().event("error").data(html)),
+                Ok(Event::default().event("end")),
+            ];
+            return Ok(Sse::new(stream::iter(events)).into_response());
+        }
+        Err(e) => return Err(e.into_response()),
     };
 
-    let rx = pipeline.run(data.prompt.clone());
-    let response = MessageTemplate::new(data, "".into());
-    let res = response.render().unwrap().replace(['\r', '\n'], "");
+    // Tells the client where to resume from if its connection drops.
+    let resume = Event::default()
+        .event("generation")
+        .data(format!("/generations/{}/stream", log.id));
+    let events = log.events(None).map(move |e| render_event(log.id, e));
 
-    let initial_event = once(async move { Ok(Event::default().data(res)) });
+    Ok(Sse::new(
+        once(async { resume })
+            .chain(events)
+            .map(Ok::<_, Infallible>),
+    )
+    .keep_alive(KeepAlive::default())
+    .into_response())
+}
+
+fn render_event(message_id: Uuid, event: GenerationEvent) -> Event {
+    render_update(message_id, &event.update).id(event.id.to_string())
+}
+
+/// Appends answer text to the element of a message or column. The text is escaped, answers
+/// may echo markup from prompts, and are broadcast to every member of a conversation.
+pub(super) fn chunk_span(id: Uuid, text: &str) -> String {
+    format!(
+        "<span hx-swap-oob='beforeend:#chunk-{id}'>{text}</span>",
+        id = id,
+        text = MarkupDisplay::new_unsafe(text, Html)
+    )
+}
+
+/// Renders a generation update as the HTML fragment the chat page swaps in.
+pub(super) fn render_update(message_id: Uuid, update: &Update) -> Event {
+    let (name, html) = match update {
+        Update::Start(message) => ("message", MessageTemplate { message }.render().unwrap()),
+        Update::Chunk(word) => ("chunk", chunk_span(message_id, word)),
+        Update::Tool(invocation) => (
+            "tool",
+            ToolTemplate {
+                message_id,
+                invocation,
+            }
+            .render()
+            .unwrap(),
+        ),
+        Update::Validation(violations) => (
+            "validation",
+            ValidationTemplate {
+                message_id,
+                violations,
+            }
+            .render()
+            .unwrap(),
+        ),
+        Update::End(_) => ("end", String::new()),
+    };
 
-    let rx_stream = rx.map(move |word| {
-        Ok(Event::default().event("chunk").data(format!(
-            "<span hx-swap-oob='beforeend:#chunk-{id}'>{word}</span>",
-            id = response.id,
-            word = word
-        )))
-    });
+    Event::default()
+        .event(name)
+        .data(html.replace(['\r', '\n'], ""))
+}
 
-    let end_event = once(async move { Ok(Event::default().event("end")) });
-    let stream = initial_event.chain(rx_stream).chain(end_event);
+/// Replays a generation after the `Last-Event-ID` header, then follows it live.
+async fn get_generation_stream(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let log = state
+        .generations
+        .get(id)
+        .filter(|log| log.owner == user.id || user.is_admin())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let after = headers
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok());
 
-    Sse::new(stream).keep_alive(KeepAlive::default())
+    Ok(Sse::new(
+        log.events(after)
+            .map(move |e| Ok::<_, Infallible>(render_event(id, e))),
+    )
+    .keep_alive(KeepAlive::default())
+    .into_response())
 }
@@ -0,0 +1,41 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use prometheus::{Encoder, TextEncoder};
+
+use crate::metrics::metrics;
+use crate::state::AppState;
+
+pub fn metrics_router() -> Router<AppState> {
+    Router::new().route("/metrics", get(get_metrics))
+}
+
+/// Prometheus text exposition. Requires `Authorization: Bearer $CRABOT_METRICS_TOKEN` when that
+/// variable is set.
+async fn get_metrics(bearer: Option<TypedHeader<Authorization<Bearer>>>) -> Response {
+    if let Ok(token) = std::env::var("CRABOT_METRICS_TOKEN") {
+        if bearer.is_none_or(|TypedHeader(Authorization(b))| b.token() != token) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    if let Err(e) = encoder.encode(&metrics().gather(), &mut buffer) {
+        tracing::error!("Metrics: Could not encode metrics: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    (
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        buffer,
+    )
+        .into_response()
+}
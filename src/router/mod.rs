@@ -1 +1,13 @@
+pub mod admin;
+pub mod attachments;
+pub mod auth;
+pub mod compare;
+pub mod documents;
+pub mod export;
+pub mod health;
 pub mod index;
+pub mod metrics;
+pub mod rooms;
+pub mod search;
+pub mod tokenize;
+pub mod ws;
@@ -0,0 +1,185 @@
+use askama::Template;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{delete, get},
+    Form, Router,
+};
+use futures::{stream, StreamExt as _};
+use serde::Deserialize;
+use std::convert::Infallible;
+use uuid::Uuid;
+
+use super::index::render_update;
+use crate::auth::{middleware::CurrentUser, User};
+use crate::rooms::RoomEvent;
+use crate::state::AppState;
+use crate::store::Conversation;
+use crate::template::HtmlTemplate;
+
+pub fn rooms_router() -> Router<AppState> {
+    Router::new()
+        .route("/c/:id/live", get(get_live))
+        .route("/c/:id/members", get(get_members).post(add_member))
+        .route("/c/:id/members/:user", delete(remove_member))
+}
+
+#[derive(Template)]
+#[template(path = "elements/members.html")]
+struct MembersTemplate {
+    conversation_id: Uuid,
+    owner: Option<String>,
+    members: Vec<User>,
+    can_manage: bool,
+    error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "elements/viewer.html")]
+struct ViewerTemplate {
+    viewer: Uuid,
+}
+
+#[derive(Template)]
+#[template(path = "elements/presence.html")]
+struct PresenceTemplate<'a> {
+    viewers: &'a Vec<String>,
+}
+
+fn visible_conversation(state: &AppState, id: Uuid, user: &User) -> Option<Conversation> {
+    state.store.get(id).filter(|c| c.is_visible_to(user))
+}
+
+fn members_template(state: &AppState, id: Uuid, user: &User, error: Option<String>) -> Response {
+    let Some(conversation) = visible_conversation(state, id, user) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    HtmlTemplate(MembersTemplate {
+        conversation_id: id,
+        owner: conversation
+            .owner
+            .and_then(|id| state.users.get(id))
+            .map(|u| u.username),
+        members: conversation
+            .members
+            .iter()
+            .filter_map(|id| state.users.get(*id))
+            .collect(),
+        can_manage: conversation.can_manage(user),
+        error,
+    })
+    .into_response()
+}
+
+async fn get_members(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Response {
+    members_template(&state, id, &user, None)
+}
+
+#[derive(Deserialize)]
+struct AddMember {
+    username: String,
+}
+
+async fn add_member(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<Uuid>,
+    Form(form): Form<AddMember>,
+) -> Result<Response, StatusCode> {
+    let conversation = visible_conversation(&state, id, &user).ok_or(StatusCode::NOT_FOUND)?;
+    if !conversation.can_manage(&user) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let error = match state.users.find(&form.username) {
+        Some(member) => state.store.add_member(id, member.id).err().map(|e| {
+            tracing::error!("Could not share conversation: {}", e);
+            e.to_string()
+        }),
+        None => Some(format!("No user named `{}`", form.username.trim())),
+    };
+
+    Ok(members_template(&state, id, &user, error))
+}
+
+async fn remove_member(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path((id, member)): Path<(Uuid, Uuid)>,
+) -> Result<Response, StatusCode> {
+    let conversation = visible_conversation(&state, id, &user).ok_or(StatusCode::NOT_FOUND)?;
+    // Members can leave on their own.
+    if !conversation.can_manage(&user) && member != user.id {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let error = state.store.remove_member(id, member).err().map(|e| {
+        tracing::error!("Could not unshare conversation: {}", e);
+        e.to_string()
+    });
+    state.rooms.refresh(id);
+
+    Ok(members_template(&state, id, &user, error))
+}
+
+/// Streams the answers other viewers of a conversation get, and who is viewing it. The stream
+/// starts with the id of this viewer, which the page posts its prompts with, and ends when the
+/// viewer loses access, or when the server shuts down.
+async fn get_live(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Result<Response, StatusCode> {
+    visible_conversation(&state, id, &user).ok_or(StatusCode::NOT_FOUND)?;
+
+    let viewer = state.rooms.join(id, user.id, user.username.clone());
+    let joined = Event::default().event("viewer").data(
+        ViewerTemplate { viewer: viewer.id }
+            .render()
+            .unwrap()
+            .replace(['\r', '\n'], ""),
+    );
+    let store = state.store.clone();
+    let events = stream::unfold(viewer, move |mut viewer| {
+        let (store, user) = (store.clone(), user.clone());
+        async move {
+            loop {
+                let event = viewer.recv().await?;
+                // Members removed from the conversation stop following it.
+                if !store.can_access(id, &user) {
+                    return None;
+                }
+                let event = match event {
+                    RoomEvent::Update { origin, .. } if origin == Some(viewer.id) => continue,
+                    RoomEvent::Update {
+                        message_id, update, ..
+                    } => render_update(message_id, &update),
+                    RoomEvent::Presence(viewers) => Event::default().event("presence").data(
+                        PresenceTemplate { viewers: &viewers }
+                            .render()
+                            .unwrap()
+                            .replace(['\r', '\n'], ""),
+                    ),
+                };
+                return Some((Ok::<_, Infallible>(event), viewer));
+            }
+        }
+    });
+    let events = stream::once(async { Ok(joined) })
+        .chain(events)
+        .take_until(state.shutdown.started());
+
+    Ok(Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
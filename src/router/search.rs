@@ -0,0 +1,115 @@
+use askama::Template;
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::auth::middleware::CurrentUser;
+use crate::search::{self, Fragment};
+use crate::state::AppState;
+use crate::template::HtmlTemplate;
+
+const MAX_RESULTS: usize = 20;
+
+pub fn search_router() -> Router<AppState> {
+    Router::new().route("/search", get(get_search))
+}
+
+struct SearchResult {
+    conversation: Uuid,
+    message: Uuid,
+    label: String,
+    snippet: Vec<Fragment>,
+}
+
+#[derive(Template)]
+#[template(path = "elements/search.html")]
+struct SearchTemplate {
+    query: String,
+    results: Vec<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    #[serde(default)]
+    q: String,
+}
+
+async fn get_search(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(SearchQuery { q }): Query<SearchQuery>,
+) -> impl IntoResponse {
+    let query = q.trim().to_string();
+    if query.is_empty() {
+        return HtmlTemplate(SearchTemplate {
+            query,
+            results: vec![],
+        });
+    }
+
+    let mut hits = state.store.search(&query, user.id);
+    let conversations = state.store.list_joined(user.id);
+
+    if search::semantic_enabled() {
+        // Newest first, they are embedded first.
+        let mut messages: Vec<_> = conversations
+            .iter()
+            .flat_map(|c| c.messages.iter())
+            .collect();
+        messages.sort_by_key(|m| std::cmp::Reverse(m.created_at));
+        let messages: Vec<(Uuid, String)> = messages
+            .into_iter()
+            .map(|m| (m.id, format!("{}\n{}", m.prompt, m.response)))
+            .collect();
+        let message_conversations: HashMap<Uuid, Uuid> = conversations
+            .iter()
+            .flat_map(|c| c.messages.iter().map(|m| (m.id, c.id)))
+            .collect();
+
+        let embeddings = state.embeddings.clone();
+        let knowledge = state.knowledge.clone();
+        let text = query.clone();
+        let similar =
+            tokio::task::spawn_blocking(move || embeddings.similar(&knowledge, &text, &messages))
+                .await
+                .expect("Failed to join search task");
+
+        match similar {
+            Ok(similar) => hits = search::fuse(hits, similar, &message_conversations),
+            Err(e) => tracing::error!("Search: Could not rank by embeddings: {:#}", e),
+        }
+    }
+
+    let results = hits
+        .into_iter()
+        .filter_map(|hit| {
+            let conversation = conversations.iter().find(|c| c.id == hit.conversation)?;
+            let message = conversation.messages.iter().find(|m| m.id == hit.message)?;
+
+            // Whichever of the prompt and the response matched, the prompt for semantic matches.
+            let snippet = [&message.prompt, &message.response]
+                .into_iter()
+                .map(|text| search::snippet(text, &query))
+                .find(|snippet| snippet.iter().any(|f| f.highlighted))
+                .unwrap_or_else(|| search::snippet(&message.prompt, &query));
+
+            Some(SearchResult {
+                conversation: conversation.id,
+                message: message.id,
+                label: conversation.label(),
+                snippet,
+            })
+        })
+        .take(MAX_RESULTS)
+        .collect();
+
+    HtmlTemplate(SearchTemplate { query, results })
+}
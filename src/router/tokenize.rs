@@ -0,0 +1,122 @@
+use askama::Template;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Form, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::models::ChatModel;
+use crate::state::AppState;
+use crate::template::HtmlTemplate;
+use crate::tokenize::Token;
+
+/// Longer texts are refused, tokens are all rendered.
+const MAX_TEXT_CHARS: usize = 100_000;
+
+pub fn tokenize_router() -> Router<AppState> {
+    Router::new().route("/tokenize", get(get_tokenize).post(post_tokenize))
+}
+
+#[derive(Template)]
+#[template(path = "pages/tokenize.html")]
+struct TokenizePageTemplate {
+    tokenizers: Vec<String>,
+}
+
+#[derive(Template)]
+#[template(path = "elements/tokens.html")]
+struct TokensTemplate {
+    tokenized: Tokenized,
+}
+
+/// How much of a model's context window, and of its price, a text would take.
+#[derive(Serialize)]
+struct Usage {
+    model: ChatModel,
+    context_window: u64,
+    /// Share of the context window, in percent.
+    percent: f64,
+    /// US dollars, as part of a prompt.
+    cost: f64,
+}
+
+#[derive(Serialize)]
+struct Tokenized {
+    tokenizer: String,
+    count: usize,
+    tokens: Vec<Token>,
+    usage: Vec<Usage>,
+}
+
+#[derive(Deserialize)]
+struct TokenizeForm {
+    tokenizer: String,
+    #[serde(default)]
+    text: String,
+}
+
+async fn get_tokenize(State(state): State<AppState>) -> impl IntoResponse {
+    HtmlTemplate(TokenizePageTemplate {
+        tokenizers: state.tokenizers.names(),
+    })
+}
+
+/// Tokenizes a text, as HTML for the page and JSON otherwise.
+async fn post_tokenize(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Form(form): Form<TokenizeForm>,
+) -> Result<Response, Response> {
+    if !state.tokenizers.contains(&form.tokenizer) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Unknown tokenizer `{}`", form.tokenizer),
+        )
+            .into_response());
+    }
+    if form.text.chars().count() > MAX_TEXT_CHARS {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Texts are limited to {} characters", MAX_TEXT_CHARS),
+        )
+            .into_response());
+    }
+
+    let tokens = {
+        let tokenizers = state.tokenizers.clone();
+        let (name, text) = (form.tokenizer.clone(), form.text);
+        tokio::task::spawn_blocking(move || tokenizers.tokenize(&name, &text))
+            .await
+            .expect("Failed to join tokenizer task")
+            .map_err(|e| {
+                tracing::error!("Could not tokenize text: {:#}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)).into_response()
+            })?
+    };
+
+    let count = tokens.len();
+    let usage = ChatModel::enabled()
+        .into_iter()
+        .map(|model| Usage {
+            model,
+            context_window: state.context.window(model),
+            percent: 100. * count as f64 / state.context.window(model).max(1) as f64,
+            cost: count as f64 * model.prompt_price() / 1_000_000.,
+        })
+        .collect();
+    let tokenized = Tokenized {
+        tokenizer: form.tokenizer,
+        count,
+        tokens,
+        usage,
+    };
+
+    Ok(match headers.contains_key("HX-Request") {
+        true => HtmlTemplate(TokensTemplate { tokenized }).into_response(),
+        false => Json(tokenized).into_response(),
+    })
+}
@@ -0,0 +1,232 @@
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+    routing::get,
+    Extension, Router,
+};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::auth::{keys::ApiKey, middleware::CurrentUser, User};
+use crate::chat::{self, PostMessage};
+use crate::generations::Update;
+use crate::models::ChatModel;
+use crate::state::AppState;
+use crate::tools::ToolInvocation;
+
+pub fn ws_router() -> Router<AppState> {
+    Router::new().route("/ws", get(get_ws))
+}
+
+/// Messages sent by clients, as JSON objects tagged by `type`.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Same fields as the chat form. `request` is echoed back to match the answer to its prompt.
+    Prompt {
+        #[serde(default)]
+        request: Option<String>,
+        #[serde(flatten)]
+        data: PostMessage,
+    },
+    /// Stops an answer, which is stored as it is.
+    Cancel { message: Uuid },
+}
+
+/// Messages sent to clients. `message` is the id of the message being answered.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Start {
+        request: Option<String>,
+        message: Uuid,
+        conversation: Uuid,
+        model: ChatModel,
+    },
+    /// Whether the model is still writing the answer.
+    Typing {
+        message: Uuid,
+        typing: bool,
+    },
+    Chunk {
+        message: Uuid,
+        text: String,
+    },
+    Tool {
+        message: Uuid,
+        invocation: ToolInvocation,
+    },
+    Validation {
+        message: Uuid,
+        violations: Vec<String>,
+    },
+    End {
+        message: Uuid,
+        response: String,
+        interrupted: bool,
+        cancelled: bool,
+        /// Replayed from the response cache.
+        cached: bool,
+        /// Exchanges left out of the prompt to fit the context window.
+        context_trimmed: usize,
+    },
+    Error {
+        request: Option<String>,
+        message: Option<Uuid>,
+        error: String,
+    },
+}
+
+async fn get_ws(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    api_key: Option<Extension<ApiKey>>,
+) -> Response {
+    let api_key = api_key.map(|Extension(key)| key);
+    ws.on_upgrade(move |socket| handle_socket(socket, state, user, api_key))
+}
+
+/// Runs prompts concurrently on one connection, their answers interleaved on the way back.
+async fn handle_socket(socket: WebSocket, state: AppState, user: User, api_key: Option<ApiKey>) {
+    let (mut sink, mut stream) = socket.split();
+    let (tx, mut rx) = mpsc::channel::<ServerMessage>(64);
+
+    let writer = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let text = serde_json::to_string(&message).expect("Failed to serialize message");
+            if sink.send(WsMessage::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(Ok(message)) = stream.next().await {
+        let text = match message {
+            WsMessage::Text(text) => text,
+            WsMessage::Close(_) => break,
+            // Pings are answered by axum.
+            _ => continue,
+        };
+
+        match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(ClientMessage::Prompt { request, data }) => {
+                tokio::spawn(run_prompt(
+                    state.clone(),
+                    user.clone(),
+                    api_key.clone(),
+                    request,
+                    data,
+                    tx.clone(),
+                ));
+            }
+            Ok(ClientMessage::Cancel { message }) => {
+                match state
+                    .generations
+                    .get(message)
+                    .filter(|log| log.owner == user.id || user.is_admin())
+                {
+                    Some(log) => log.cancel(),
+                    None => {
+                        let _ = tx
+                            .send(ServerMessage::Error {
+                                request: None,
+                                message: Some(message),
+                                error: "No answer in progress with this id".into(),
+                            })
+                            .await;
+                    }
+                }
+            }
+            Err(e) => {
+                let _ = tx
+                    .send(ServerMessage::Error {
+                        request: None,
+                        message: None,
+                        error: format!("Invalid message: {}", e),
+                    })
+                    .await;
+            }
+        }
+    }
+
+    // Answers still being generated complete and get stored all the same.
+    writer.abort();
+}
+
+async fn run_prompt(
+    state: AppState,
+    user: User,
+    api_key: Option<ApiKey>,
+    request: Option<String>,
+    data: PostMessage,
+    tx: mpsc::Sender<ServerMessage>,
+) {
+    let conversation = data.conversation;
+    let log = match chat::start(&state, &user, api_key, data, vec![]).await {
+        Ok(log) => log,
+        Err(e) => {
+            let _ = tx
+                .send(ServerMessage::Error {
+                    request,
+                    message: None,
+                    error: e.to_string(),
+                })
+                .await;
+            return;
+        }
+    };
+
+    let id = log.id;
+    let mut events = std::pin::pin!(log.events(None));
+    while let Some(event) = events.next().await {
+        let messages = match event.update {
+            Update::Start(message) => vec![
+                ServerMessage::Start {
+                    request: request.clone(),
+                    message: id,
+                    conversation,
+                    model: message.model,
+                },
+                ServerMessage::Typing {
+                    message: id,
+                    typing: true,
+                },
+            ],
+            Update::Chunk(text) => vec![ServerMessage::Chunk { message: id, text }],
+            Update::Tool(invocation) => vec![ServerMessage::Tool {
+                message: id,
+                invocation,
+            }],
+            Update::Validation(violations) => vec![ServerMessage::Validation {
+                message: id,
+                violations,
+            }],
+            Update::End(message) => vec![
+                ServerMessage::Typing {
+                    message: id,
+                    typing: false,
+                },
+                ServerMessage::End {
+                    message: id,
+                    response: message.response.clone(),
+                    interrupted: message.interrupted,
+                    cancelled: message.cancelled,
+                    cached: message.cached,
+                    context_trimmed: message.context_trimmed,
+                },
+            ],
+        };
+
+        for message in messages {
+            if tx.send(message).await.is_err() {
+                return;
+            }
+        }
+    }
+}
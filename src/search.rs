@@ -0,0 +1,335 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::{Arc, RwLock},
+};
+
+use uuid::Uuid;
+
+use crate::knowledge::KnowledgeBase;
+use crate::store::Conversation;
+
+/// BM25 parameters, the usual defaults.
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+/// Messages less similar than this to the query are not semantic matches.
+const MIN_SIMILARITY: f32 = 0.3;
+/// Messages embedded by one search at most, so the first one doesn't embed the whole history.
+pub const MAX_EMBEDDED_PER_SEARCH: usize = 256;
+/// Rank offset of reciprocal rank fusion, damping the weight of the very first results.
+const RRF_K: f32 = 60.;
+/// Characters of context shown around the first match of a snippet.
+const SNIPPET_CONTEXT: usize = 80;
+
+/// Whether searches also rank messages by embedding similarity, set
+/// `CRABOT_SEARCH_EMBEDDINGS=true` to enable it.
+pub fn semantic_enabled() -> bool {
+    std::env::var("CRABOT_SEARCH_EMBEDDINGS").is_ok_and(|v| v == "true" || v == "1")
+}
+
+/// Lowercased words of a text, with their byte offsets.
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut start = None;
+    text.char_indices()
+        .chain(std::iter::once((text.len(), ' ')))
+        .filter_map(move |(i, c)| match (start, c.is_alphanumeric()) {
+            (None, true) => {
+                start = Some(i);
+                None
+            }
+            (Some(s), false) => {
+                start = None;
+                Some((s, &text[s..i]))
+            }
+            _ => None,
+        })
+}
+
+pub fn terms(text: &str) -> Vec<String> {
+    words(text).map(|(_, w)| w.to_lowercase()).collect()
+}
+
+/// A message as indexed.
+struct Document {
+    conversation: Uuid,
+    /// Occurrences of each term.
+    frequencies: HashMap<String, u32>,
+    length: usize,
+}
+
+/// A message matching a query.
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub conversation: Uuid,
+    pub message: Uuid,
+    pub score: f32,
+}
+
+/// Full-text index over the prompts and responses of stored messages, ranked with BM25.
+#[derive(Default)]
+pub struct SearchIndex {
+    documents: HashMap<Uuid, Document>,
+    /// Messages containing each term.
+    postings: HashMap<String, HashSet<Uuid>>,
+    /// Indexed messages of each conversation.
+    conversations: HashMap<Uuid, Vec<Uuid>>,
+    total_length: usize,
+}
+
+impl SearchIndex {
+    /// Indexes a conversation, replacing what was indexed of it before.
+    pub fn index(&mut self, conversation: &Conversation) {
+        self.remove(conversation.id);
+
+        let mut ids = Vec::with_capacity(conversation.messages.len());
+        for message in conversation.messages.iter() {
+            let mut frequencies = HashMap::new();
+            let mut length = 0;
+            for term in terms(&message.prompt)
+                .into_iter()
+                .chain(terms(&message.response))
+            {
+                *frequencies.entry(term).or_insert(0) += 1;
+                length += 1;
+            }
+
+            for term in frequencies.keys() {
+                self.postings
+                    .entry(term.clone())
+                    .or_default()
+                    .insert(message.id);
+            }
+            self.total_length += length;
+            self.documents.insert(
+                message.id,
+                Document {
+                    conversation: conversation.id,
+                    frequencies,
+                    length,
+                },
+            );
+            ids.push(message.id);
+        }
+        self.conversations.insert(conversation.id, ids);
+    }
+
+    fn remove(&mut self, conversation: Uuid) {
+        for id in self.conversations.remove(&conversation).unwrap_or_default() {
+            let Some(document) = self.documents.remove(&id) else {
+                continue;
+            };
+            self.total_length -= document.length;
+            for term in document.frequencies.keys() {
+                if let Some(ids) = self.postings.get_mut(term) {
+                    ids.remove(&id);
+                    if ids.is_empty() {
+                        self.postings.remove(term);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Messages of the conversations passing `filter` containing any of the query terms, best
+    /// first.
+    pub fn search(&self, query: &str, filter: impl Fn(Uuid) -> bool) -> Vec<Hit> {
+        let count = self.documents.len() as f32;
+        let average_length = self.total_length as f32 / count.max(1.);
+
+        let mut scores: HashMap<Uuid, f32> = HashMap::new();
+        let query: HashSet<String> = terms(query).into_iter().collect();
+        for term in query.iter() {
+            let Some(ids) = self.postings.get(term) else {
+                continue;
+            };
+            let matching = ids.len() as f32;
+            let idf = ((count - matching + 0.5) / (matching + 0.5) + 1.).ln();
+
+            for id in ids {
+                let document = &self.documents[id];
+                let frequency = document.frequencies[term] as f32;
+                let length = document.length as f32 / average_length;
+                *scores.entry(*id).or_default() +=
+                    idf * frequency * (K1 + 1.) / (frequency + K1 * (1. - B + B * length));
+            }
+        }
+
+        let mut hits: Vec<Hit> = scores
+            .into_iter()
+            .map(|(message, score)| Hit {
+                conversation: self.documents[&message].conversation,
+                message,
+                score,
+            })
+            .filter(|hit| filter(hit.conversation))
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits
+    }
+}
+
+/// Message embeddings, computed by the searches needing them and kept in memory along with a
+/// hash of the text they embed, so edited messages are embedded again.
+#[derive(Clone, Default)]
+pub struct MessageEmbeddings {
+    vectors: Arc<RwLock<HashMap<Uuid, Embedding>>>,
+}
+
+struct Embedding {
+    /// Hash of the embedded text.
+    hash: u64,
+    vector: Vec<f32>,
+}
+
+fn content_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl MessageEmbeddings {
+    /// Messages similar enough to the query, with their cosine similarity. At most
+    /// [`MAX_EMBEDDED_PER_SEARCH`] messages without an up to date embedding are embedded, the
+    /// first ones given; the others are left out until later searches. Blocking.
+    pub fn similar(
+        &self,
+        knowledge: &KnowledgeBase,
+        query: &str,
+        messages: &[(Uuid, String)],
+    ) -> anyhow::Result<HashMap<Uuid, f32>> {
+        let hashes: Vec<u64> = messages
+            .iter()
+            .map(|(_, text)| content_hash(text))
+            .collect();
+        let missing: Vec<_> = {
+            let vectors = self.vectors.read().unwrap();
+            messages
+                .iter()
+                .zip(hashes.iter())
+                .filter(|((id, _), hash)| vectors.get(id).is_none_or(|e| e.hash != **hash))
+                .take(MAX_EMBEDDED_PER_SEARCH)
+                .collect()
+        };
+        if !missing.is_empty() {
+            let texts: Vec<String> = missing.iter().map(|((_, text), _)| text.clone()).collect();
+            let embeddings = knowledge.embed(&texts)?;
+            let mut vectors = self.vectors.write().unwrap();
+            for (((id, _), hash), embedding) in missing.into_iter().zip(embeddings) {
+                vectors.insert(
+                    *id,
+                    Embedding {
+                        hash: *hash,
+                        vector: embedding,
+                    },
+                );
+            }
+        }
+
+        let query = knowledge
+            .embed(&[query.to_string()])?
+            .pop()
+            .unwrap_or_default();
+        let vectors = self.vectors.read().unwrap();
+        Ok(messages
+            .iter()
+            .zip(hashes.iter())
+            .filter_map(|((id, _), hash)| {
+                let embedding = vectors.get(id).filter(|e| e.hash == *hash)?;
+                // Embeddings are normalized, the dot product is the cosine similarity.
+                let similarity = embedding
+                    .vector
+                    .iter()
+                    .zip(query.iter())
+                    .map(|(a, b)| a * b)
+                    .sum::<f32>();
+                (similarity >= MIN_SIMILARITY).then_some((*id, similarity))
+            })
+            .collect())
+    }
+}
+
+/// Merges full-text hits with semantic matches by reciprocal rank fusion, so messages ranking
+/// well in both come first.
+pub fn fuse(
+    hits: Vec<Hit>,
+    similar: HashMap<Uuid, f32>,
+    conversations: &HashMap<Uuid, Uuid>,
+) -> Vec<Hit> {
+    let mut scores: HashMap<Uuid, f32> = HashMap::new();
+    for (rank, hit) in hits.iter().enumerate() {
+        *scores.entry(hit.message).or_default() += 1. / (RRF_K + rank as f32 + 1.);
+    }
+    let mut similar: Vec<_> = similar.into_iter().collect();
+    similar.sort_by(|a, b| b.1.total_cmp(&a.1));
+    for (rank, (message, _)) in similar.iter().enumerate() {
+        *scores.entry(*message).or_default() += 1. / (RRF_K + rank as f32 + 1.);
+    }
+
+    let mut fused: Vec<Hit> = scores
+        .into_iter()
+        .filter_map(|(message, score)| {
+            Some(Hit {
+                conversation: *conversations.get(&message)?,
+                message,
+                score,
+            })
+        })
+        .collect();
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+    fused
+}
+
+/// Part of a snippet, highlighted when it matches a query term.
+pub struct Fragment {
+    pub text: String,
+    pub highlighted: bool,
+}
+
+/// An excerpt of a text around the first occurrence of a query term, with every occurrence
+/// highlighted. Starts at the beginning of the text when no term occurs.
+pub fn snippet(text: &str, query: &str) -> Vec<Fragment> {
+    let query: HashSet<String> = terms(query).into_iter().collect();
+    let matches: Vec<(usize, usize)> = words(text)
+        .filter(|(_, w)| query.contains(&w.to_lowercase()))
+        .map(|(i, w)| (i, i + w.len()))
+        .collect();
+
+    let first = matches.first().map_or(0, |m| m.0);
+    let mut start = first.saturating_sub(SNIPPET_CONTEXT);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (first + 2 * SNIPPET_CONTEXT).min(text.len());
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+
+    let mut fragments = vec![];
+    let mut push = |text: &str, highlighted| {
+        if !text.is_empty() {
+            fragments.push(Fragment {
+                text: text.to_string(),
+                highlighted,
+            });
+        }
+    };
+
+    if start > 0 {
+        push("…", false);
+    }
+    let mut position = start;
+    for (match_start, match_end) in matches {
+        if match_start < position || match_end > end {
+            continue;
+        }
+        push(&text[position..match_start], false);
+        push(&text[match_start..match_end], true);
+        position = match_end;
+    }
+    push(&text[position..end], false);
+    if end < text.len() {
+        push("…", false);
+    }
+
+    fragments
+}
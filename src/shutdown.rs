@@ -0,0 +1,137 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use tokio::{sync::watch, time::timeout};
+
+use crate::utils::env_or;
+
+/// Time left to interrupted streams to store their partial answer.
+const INTERRUPT_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Running,
+    /// New prompts are refused, streamed answers may finish.
+    Draining,
+    /// Answers still streaming must stop.
+    Interrupting,
+}
+
+/// Shutdown progress shared with the routes, and the number of answers streaming.
+#[derive(Clone)]
+pub struct Shutdown {
+    phase: Arc<watch::Sender<Phase>>,
+    streams: Arc<watch::Sender<usize>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            phase: Arc::new(watch::channel(Phase::Running).0),
+            streams: Arc::new(watch::channel(0).0),
+        }
+    }
+}
+
+/// Counts a streamed answer as active until dropped.
+pub struct StreamGuard {
+    streams: Arc<watch::Sender<usize>>,
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.streams.send_modify(|n| *n -= 1);
+    }
+}
+
+impl Shutdown {
+    pub fn begin(&self) {
+        self.phase.send_if_modified(|phase| match phase {
+            Phase::Running => {
+                *phase = Phase::Draining;
+                true
+            }
+            _ => false,
+        });
+    }
+
+    pub fn is_started(&self) -> bool {
+        *self.phase.borrow() != Phase::Running
+    }
+
+    /// Resolves once shutdown begins, when streams that never end on their own must close.
+    pub fn started(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut phase = self.phase.subscribe();
+        async move {
+            let _ = phase.wait_for(|p| *p != Phase::Running).await;
+        }
+    }
+
+    /// Resolves once streamed answers must stop, with whatever they got so far.
+    pub fn interrupted(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut phase = self.phase.subscribe();
+        async move {
+            let _ = phase.wait_for(|p| *p == Phase::Interrupting).await;
+        }
+    }
+
+    pub fn track_stream(&self) -> StreamGuard {
+        self.streams.send_modify(|n| *n += 1);
+        StreamGuard {
+            streams: self.streams.clone(),
+        }
+    }
+
+    async fn drained(&self) {
+        let mut streams = self.streams.subscribe();
+        let _ = streams.wait_for(|n| *n == 0).await;
+    }
+
+    /// Refuses new prompts and waits for streamed answers to complete, interrupting those still
+    /// running after `deadline`.
+    pub async fn drain(&self, deadline: Duration) {
+        self.begin();
+        if timeout(deadline, self.drained()).await.is_ok() {
+            return;
+        }
+
+        tracing::warn!(
+            "Interrupting {} answers still streaming",
+            *self.streams.borrow()
+        );
+        self.phase.send_replace(Phase::Interrupting);
+        if timeout(INTERRUPT_GRACE, self.drained()).await.is_err() {
+            tracing::warn!("Some interrupted answers could not be stored");
+        }
+    }
+}
+
+/// Resolves on SIGINT or SIGTERM, once streamed answers are drained. The server stops accepting
+/// connections afterwards.
+///
+/// Streams get `CRABOT_SHUTDOWN_TIMEOUT` seconds (30 by default) to complete.
+pub async fn signal(shutdown: Shutdown) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    let deadline = Duration::from_secs(env_or("CRABOT_SHUTDOWN_TIMEOUT", 30));
+    tracing::info!("Shutting down, draining answers for up to {:?}", deadline);
+    shutdown.drain(deadline).await;
+}
@@ -0,0 +1,79 @@
+use crate::attachments::AttachmentStore;
+use crate::auth::{keys::ApiKeyStore, session::SessionStore, UserStore};
+use crate::cache::ResponseCache;
+use crate::compare::ComparisonStore;
+use crate::context::ContextConfig;
+use crate::generations::Generations;
+use crate::knowledge::KnowledgeBase;
+use crate::limits::Limits;
+use crate::models::ModelConfig;
+use crate::rooms::Rooms;
+use crate::search::MessageEmbeddings;
+use crate::shutdown::Shutdown;
+use crate::store::Store;
+use crate::summaries::{Summarizer, SummaryConfig};
+use crate::tokenize::Tokenizers;
+use crate::utils::tokens::TokenCounter;
+
+/// Shared state handed to every route.
+#[derive(Clone)]
+pub struct AppState {
+    pub store: Store,
+    pub knowledge: KnowledgeBase,
+    pub attachments: AttachmentStore,
+    pub users: UserStore,
+    pub sessions: SessionStore,
+    pub api_keys: ApiKeyStore,
+    pub comparisons: ComparisonStore,
+    pub limits: Limits,
+    pub shutdown: Shutdown,
+    pub generations: Generations,
+    pub rooms: Rooms,
+    pub cache: ResponseCache,
+    pub embeddings: MessageEmbeddings,
+    pub summarizer: Summarizer,
+    pub context: ContextConfig,
+    pub models: ModelConfig,
+    /// See [`FetchTool`](crate::tools::fetch::FetchTool).
+    pub fetch_base_url: Option<String>,
+    pub tokens: TokenCounter,
+    pub tokenizers: Tokenizers,
+}
+
+impl AppState {
+    pub fn new(
+        store: Store,
+        knowledge: KnowledgeBase,
+        attachments: AttachmentStore,
+        users: UserStore,
+        sessions: SessionStore,
+        api_keys: ApiKeyStore,
+        comparisons: ComparisonStore,
+    ) -> Self {
+        Self {
+            summarizer: Summarizer::new(
+                store.clone(),
+                SummaryConfig::default(),
+                ModelConfig::default(),
+            ),
+            limits: Limits::from_env(),
+            context: ContextConfig::default(),
+            models: ModelConfig::default(),
+            fetch_base_url: None,
+            tokens: TokenCounter::default(),
+            tokenizers: Tokenizers::default(),
+            shutdown: Shutdown::default(),
+            generations: Generations::default(),
+            rooms: Rooms::default(),
+            cache: ResponseCache::default(),
+            embeddings: MessageEmbeddings::default(),
+            store,
+            knowledge,
+            attachments,
+            users,
+            sessions,
+            api_keys,
+            comparisons,
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::attachments::Attachment;
+use crate::auth::User;
+use crate::knowledge::Citation;
+use crate::models::{ChatMessage, ChatModel};
+use crate::search::{Hit, SearchIndex};
+use crate::tools::ToolInvocation;
+
+/// A prompt and the answer it produced.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Message {
+    pub id: Uuid,
+    pub prompt: String,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    #[serde(default)]
+    pub model: ChatModel,
+    pub response: String,
+    #[serde(default)]
+    pub tools: Vec<ToolInvocation>,
+    /// Document excerpts given to the model as context.
+    #[serde(default)]
+    pub citations: Vec<Citation>,
+    /// JSON Schema the response was requested to follow.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Value>,
+    /// Schema violations found in the response, set whenever a schema was requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub violations: Option<Vec<String>>,
+    /// Set when the server stopped before the answer was complete.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+    /// Set when the user stopped the answer.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+    /// Set when the answer was replayed from the response cache.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+    /// Exchanges of the conversation left out of the prompt to fit the context window.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub context_trimmed: usize,
+    /// Set when the exchanges left out were sent as a summary.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub context_summarized: bool,
+    /// Who posted the prompt, in shared conversations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Message {
+    pub fn new(prompt: String, model: ChatModel) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            prompt,
+            attachments: vec![],
+            model,
+            response: "".into(),
+            tools: vec![],
+            citations: vec![],
+            schema: None,
+            violations: None,
+            interrupted: false,
+            cancelled: false,
+            cached: false,
+            context_trimmed: 0,
+            context_summarized: false,
+            author: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// The prompt followed by the text content of its attachments.
+    pub fn prompt_with_attachments(&self) -> String {
+        let mut prompt = self.prompt.clone();
+        for attachment in self.attachments.iter() {
+            if let Some(text) = &attachment.text {
+                prompt.push_str(&format!(
+                    "\n\n--- Attached file: {} ---\n{}",
+                    attachment.name, text
+                ));
+            }
+        }
+        prompt
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Conversation {
+    pub id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Account the conversation belongs to, unset for conversations predating accounts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Uuid>,
+    /// Other users the conversation is shared with, who can read and post to it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<Uuid>,
+    /// Rolling summary of long conversations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Number of messages the summary covers.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub summarized: usize,
+    pub created_at: DateTime<Utc>,
+    pub messages: Vec<Message>,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+impl Conversation {
+    pub fn new(id: Uuid, owner: Option<Uuid>) -> Self {
+        Self {
+            id,
+            title: None,
+            owner,
+            members: vec![],
+            summary: None,
+            summarized: 0,
+            created_at: Utc::now(),
+            messages: vec![],
+        }
+    }
+
+    /// Owners and members see their conversations, admins see everything.
+    pub fn is_visible_to(&self, user: &User) -> bool {
+        self.can_manage(user) || self.members.contains(&user.id)
+    }
+
+    /// Whether a user owns the conversation or was added to it, which admins aren't by default.
+    pub fn has_participant(&self, user: Uuid) -> bool {
+        self.owner == Some(user) || self.members.contains(&user)
+    }
+
+    /// What the conversation is listed as: its title, or else its first prompt.
+    pub fn label(&self) -> String {
+        self.title
+            .clone()
+            .or_else(|| self.messages.first().map(|m| m.prompt.clone()))
+            .unwrap_or_else(|| "New conversation".into())
+    }
+
+    /// Whether a user may change who the conversation is shared with.
+    pub fn can_manage(&self, user: &User) -> bool {
+        user.is_admin() || self.owner == Some(user.id)
+    }
+
+    /// Flattens the conversation into the message list sent to chat completion APIs.
+    pub fn history(&self) -> Vec<ChatMessage> {
+        self.messages
+            .iter()
+            .flat_map(|m| {
+                [
+                    ChatMessage::user(m.prompt_with_attachments()),
+                    ChatMessage::assistant(m.response.clone()),
+                ]
+            })
+            .collect()
+    }
+}
+
+/// Conversations kept in memory and mirrored to one JSON file each on disk.
+#[derive(Clone)]
+pub struct Store {
+    dir: PathBuf,
+    conversations: Arc<RwLock<HashMap<Uuid, Conversation>>>,
+    index: Arc<RwLock<SearchIndex>>,
+}
+
+impl Store {
+    pub fn open(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().join("conversations");
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+
+        let mut conversations = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+
+            let conversation = fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|data| Ok(serde_json::from_slice::<Conversation>(&data)?));
+
+            match conversation {
+                Ok(c) => {
+                    conversations.insert(c.id, c);
+                }
+                Err(e) => tracing::error!("Store: Could not load {:?}: {}", path, e),
+            }
+        }
+
+        let mut index = SearchIndex::default();
+        for conversation in conversations.values() {
+            index.index(conversation);
+        }
+
+        Ok(Self {
+            dir,
+            conversations: Arc::new(RwLock::new(conversations)),
+            index: Arc::new(RwLock::new(index)),
+        })
+    }
+
+    /// Checks conversations can still be written, by creating and removing a probe file.
+    pub fn check_writable(&self) -> anyhow::Result<()> {
+        let probe = self.dir.join(".probe");
+        fs::write(&probe, b"").with_context(|| format!("Failed to write {:?}", probe))?;
+        fs::remove_file(&probe).with_context(|| format!("Failed to remove {:?}", probe))?;
+        Ok(())
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<Conversation> {
+        self.conversations.read().unwrap().get(&id).cloned()
+    }
+
+    /// Returns every conversation, most recent first.
+    pub fn list(&self) -> Vec<Conversation> {
+        let mut conversations: Vec<_> = self
+            .conversations
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        conversations.sort_by_key(|c| std::cmp::Reverse(c.created_at));
+        conversations
+    }
+
+    /// Conversations owned by a user, most recent first.
+    pub fn list_for(&self, owner: Uuid) -> Vec<Conversation> {
+        let mut conversations = self.list();
+        conversations.retain(|c| c.owner == Some(owner));
+        conversations
+    }
+
+    /// Whether a user may read and post to a conversation, which is the case of conversations
+    /// that don't exist yet.
+    pub fn can_access(&self, id: Uuid, user: &User) -> bool {
+        self.conversations
+            .read()
+            .unwrap()
+            .get(&id)
+            .is_none_or(|c| c.is_visible_to(user))
+    }
+
+    /// An attachment of a conversation the user can read.
+    pub fn find_attachment(&self, id: Uuid, user: &User) -> Option<Attachment> {
+        self.conversations
+            .read()
+            .unwrap()
+            .values()
+            .filter(|c| c.is_visible_to(user))
+            .flat_map(|c| c.messages.iter())
+            .flat_map(|m| m.attachments.iter())
+            .find(|a| a.id == id)
+            .cloned()
+    }
+
+    /// Adds or replaces a whole conversation.
+    pub fn insert(&self, conversation: Conversation) -> anyhow::Result<()> {
+        let mut conversations = self.conversations.write().unwrap();
+        self.write(&conversation)?;
+        conversations.insert(conversation.id, conversation);
+        Ok(())
+    }
+
+    /// Shares a conversation with another user.
+    pub fn add_member(&self, id: Uuid, member: Uuid) -> anyhow::Result<()> {
+        self.update(id, |c| {
+            if c.owner != Some(member) && !c.members.contains(&member) {
+                c.members.push(member);
+            }
+        })
+    }
+
+    pub fn remove_member(&self, id: Uuid, member: Uuid) -> anyhow::Result<()> {
+        self.update(id, |c| c.members.retain(|m| *m != member))
+    }
+
+    pub fn set_title(&self, id: Uuid, title: String) -> anyhow::Result<()> {
+        self.update(id, |c| c.title = Some(title))
+    }
+
+    /// Replaces the summary of a conversation, which covers its first `summarized` messages.
+    pub fn set_summary(&self, id: Uuid, summary: String, summarized: usize) -> anyhow::Result<()> {
+        self.update(id, |c| {
+            c.summary = Some(summary);
+            c.summarized = summarized;
+        })
+    }
+
+    fn update(&self, id: Uuid, f: impl FnOnce(&mut Conversation)) -> anyhow::Result<()> {
+        let mut conversations = self.conversations.write().unwrap();
+        let conversation = conversations
+            .get_mut(&id)
+            .with_context(|| format!("No conversation {}", id))?;
+        f(conversation);
+        self.write(conversation)
+    }
+
+    /// Appends a message to a conversation, creating the conversation for `owner` if needed.
+    pub fn push_message(
+        &self,
+        conversation_id: Uuid,
+        owner: Uuid,
+        message: Message,
+    ) -> anyhow::Result<()> {
+        let mut conversations = self.conversations.write().unwrap();
+        let conversation = conversations
+            .entry(conversation_id)
+            .or_insert_with(|| Conversation::new(conversation_id, Some(owner)));
+        conversation.messages.push(message);
+        self.write(conversation)
+    }
+
+    /// Conversations a user owns or was added to, most recent first.
+    pub fn list_joined(&self, user: Uuid) -> Vec<Conversation> {
+        let mut conversations = self.list();
+        conversations.retain(|c| c.has_participant(user));
+        conversations
+    }
+
+    /// Messages matching a query in the conversations a user owns or was added to, best first.
+    pub fn search(&self, query: &str, user: Uuid) -> Vec<Hit> {
+        let conversations = self.conversations.read().unwrap();
+        self.index.read().unwrap().search(query, |id| {
+            conversations
+                .get(&id)
+                .is_some_and(|c| c.has_participant(user))
+        })
+    }
+
+    /// Saves a conversation, and indexes it for search. Called with the conversations locked,
+    /// so concurrent changes are saved in the order they were made. The file is replaced whole,
+    /// a crash mid-write leaves the previous version.
+    fn write(&self, conversation: &Conversation) -> anyhow::Result<()> {
+        self.index.write().unwrap().index(conversation);
+        let path = self.dir.join(format!("{}.json", conversation.id));
+        let temp = self.dir.join(format!(".{}.json.tmp", conversation.id));
+        fs::write(&temp, serde_json::to_vec_pretty(conversation)?)
+            .with_context(|| format!("Failed to write {:?}", temp))?;
+        fs::rename(&temp, &path).with_context(|| format!("Failed to replace {:?}", path))
+    }
+}
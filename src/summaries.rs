@@ -0,0 +1,190 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use tokio_stream::StreamExt as _;
+use uuid::Uuid;
+
+use crate::chat;
+use crate::models::{ChatMessage, ChatModel, ModelConfig};
+use crate::store::{Conversation, Message, Store};
+use crate::utils::env_or;
+
+/// Longest title kept, in characters.
+const MAX_TITLE_CHARS: usize = 60;
+
+const TITLE_PROMPT: &str = "Write a title of at most six words for the conversation below. \
+Answer with the title only, without quotes or punctuation at the end.";
+
+const SUMMARY_PROMPT: &str = "Summarize the conversation below in a few sentences, keeping \
+the facts, decisions and open questions needed to continue it. Answer with the summary only.";
+
+#[derive(Debug, Clone)]
+pub struct SummaryConfig {
+    /// Model writing titles and summaries, nothing is written when unset.
+    pub model: Option<ChatModel>,
+    /// Conversations are summarized from this many messages on.
+    pub summarize_after: usize,
+    /// The summary is rewritten every this many new messages.
+    pub summarize_every: usize,
+}
+
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        Self {
+            model: None,
+            summarize_after: 10,
+            summarize_every: 5,
+        }
+    }
+}
+
+impl SummaryConfig {
+    /// Titles and summaries are written by `CRABOT_SUMMARY_MODEL` (e.g. `mistral`), from
+    /// `CRABOT_SUMMARIZE_AFTER` messages on and every `CRABOT_SUMMARIZE_EVERY` new ones.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let model = std::env::var("CRABOT_SUMMARY_MODEL")
+            .ok()
+            .filter(|m| !m.trim().is_empty())
+            .and_then(|m| {
+                m.parse()
+                    .map_err(|e| tracing::warn!("CRABOT_SUMMARY_MODEL: {}", e))
+                    .ok()
+            });
+
+        Self {
+            model,
+            summarize_after: env_or("CRABOT_SUMMARIZE_AFTER", defaults.summarize_after),
+            summarize_every: env_or("CRABOT_SUMMARIZE_EVERY", defaults.summarize_every).max(1),
+        }
+    }
+}
+
+/// Names conversations after their first exchange and keeps a rolling summary of long ones,
+/// in the background.
+#[derive(Clone)]
+pub struct Summarizer {
+    config: SummaryConfig,
+    models: ModelConfig,
+    store: Store,
+    /// Conversations being worked on, so exchanges in quick succession don't overlap.
+    running: Arc<Mutex<HashSet<Uuid>>>,
+}
+
+impl Summarizer {
+    pub fn new(store: Store, config: SummaryConfig, models: ModelConfig) -> Self {
+        Self {
+            config,
+            models,
+            store,
+            running: Arc::default(),
+        }
+    }
+
+    /// Writes whatever the conversation is missing, once its latest message is stored.
+    pub fn schedule(&self, conversation: Uuid) {
+        let Some(model) = self.config.model else {
+            return;
+        };
+        if !self.running.lock().unwrap().insert(conversation) {
+            return;
+        }
+
+        let summarizer = self.clone();
+        tokio::spawn(async move {
+            if let Some(c) = summarizer.store.get(conversation) {
+                summarizer.update(model, &c).await;
+            }
+            summarizer.running.lock().unwrap().remove(&conversation);
+        });
+    }
+
+    async fn update(&self, model: ChatModel, conversation: &Conversation) {
+        if let (None, Some(first)) = (&conversation.title, conversation.messages.first()) {
+            let title = title(&self.models, model, first).await;
+            if !title.is_empty() {
+                if let Err(e) = self.store.set_title(conversation.id, title) {
+                    tracing::error!("Summaries: Could not store title: {}", e);
+                }
+            }
+        }
+
+        let count = conversation.messages.len();
+        let summarized = conversation.summarized.min(count);
+        let due = count >= self.config.summarize_after
+            && count - summarized >= self.config.summarize_every;
+        if due {
+            let new = &conversation.messages[summarized..];
+            let previous = conversation.summary.as_deref();
+            let summary = summarize(&self.models, model, previous, new).await;
+            if !summary.is_empty() {
+                if let Err(e) = self.store.set_summary(conversation.id, summary, count) {
+                    tracing::error!("Summaries: Could not store summary: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// The conversation as a transcript, for models to read as a single message.
+fn transcript(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .map(|m| format!("User: {}\n\nAssistant: {}", m.prompt, m.response))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+async fn complete(
+    models: &ModelConfig,
+    model: ChatModel,
+    instructions: &str,
+    text: String,
+) -> String {
+    let messages = vec![
+        ChatMessage::system(instructions.into()),
+        ChatMessage::user(text),
+    ];
+    // The pipeline isn't `Send`, only its stream is held across awaits.
+    let chunks = chat::pipeline(models, model, None, None).run(messages);
+    let chunks: Vec<String> = chunks.collect().await;
+    chunks.concat().trim().to_string()
+}
+
+/// A short title for a conversation, from its first exchange.
+pub async fn title(models: &ModelConfig, model: ChatModel, first: &Message) -> String {
+    let first = transcript(std::slice::from_ref(first));
+    let text = complete(models, model, TITLE_PROMPT, first).await;
+    let line = text.lines().next().unwrap_or_default();
+    let title = line
+        .trim()
+        .trim_start_matches("Title:")
+        .trim_matches(|c: char| c == '"' || c == '\'' || c == '*' || c.is_whitespace())
+        .trim_end_matches(['.', '!'])
+        .to_string();
+
+    match title.char_indices().nth(MAX_TITLE_CHARS) {
+        Some((end, _)) => format!("{}…", title[..end].trim_end()),
+        None => title,
+    }
+}
+
+/// Folds messages into the summary of what preceded them.
+pub async fn summarize(
+    models: &ModelConfig,
+    model: ChatModel,
+    previous: Option<&str>,
+    messages: &[Message],
+) -> String {
+    let mut text = String::new();
+    if let Some(previous) = previous {
+        text.push_str(&format!(
+            "Summary of the conversation so far: {}\n\n",
+            previous
+        ));
+    }
+    text.push_str(&transcript(messages));
+    complete(models, model, SUMMARY_PROMPT, text).await
+}
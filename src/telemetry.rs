@@ -0,0 +1,94 @@
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig as _;
+use opentelemetry_sdk::{runtime, trace, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::{layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt};
+
+/// Flushes pending spans when dropped, keep it alive until the program exits.
+pub struct TelemetryGuard {
+    exporting: bool,
+}
+
+impl TelemetryGuard {
+    pub fn new(exporting: bool) -> Self {
+        Self { exporting }
+    }
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if self.exporting {
+            global::shutdown_tracer_provider();
+        }
+    }
+}
+
+/// Whether an OTLP collector is configured through the standard environment variables.
+fn otlp_configured() -> bool {
+    [
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    ]
+    .iter()
+    .any(|v| std::env::var(v).is_ok_and(|v| !v.is_empty()))
+}
+
+/// Layer exporting spans over OTLP/HTTP (protobuf) to `endpoint`, or to the collector set with
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` (or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`), which take
+/// precedence. Must be called from within a Tokio runtime.
+pub fn otlp_layer<S>(
+    service_name: &str,
+    endpoint: Option<&str>,
+) -> Option<OpenTelemetryLayer<S, trace::Tracer>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    if !otlp_configured() && endpoint.is_none() {
+        return None;
+    }
+
+    let mut exporter = opentelemetry_otlp::new_exporter().http();
+    if let Some(endpoint) = endpoint {
+        exporter = exporter.with_endpoint(endpoint);
+    }
+
+    let service_name =
+        std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| service_name.to_string());
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(
+            trace::config()
+                .with_resource(Resource::new([KeyValue::new("service.name", service_name)])),
+        )
+        .install_batch(runtime::Tokio);
+
+    match tracer {
+        Ok(tracer) => Some(tracing_opentelemetry::layer().with_tracer(tracer)),
+        Err(e) => {
+            eprintln!("Failed to set up OTLP trace export: {}", e);
+            None
+        }
+    }
+}
+
+/// Logs to stdout, filtered by `RUST_LOG`, and exports traces if a collector is configured.
+pub fn init(service_name: &str) -> TelemetryGuard {
+    let otlp = otlp_layer(service_name, None);
+    let guard = TelemetryGuard::new(otlp.is_some());
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+                // axum logs rejections from built-in extractors with the `axum::rejection`
+                // target, at `TRACE` level. `axum::rejection=trace` enables showing those events
+                "crabot=debug,tower_http=debug,axum::rejection=trace".into()
+            }),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .with(otlp)
+        .init();
+
+    guard
+}
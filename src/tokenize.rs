@@ -0,0 +1,275 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use regex::Regex;
+use serde::Serialize;
+use tokenizers::Tokenizer;
+
+use crate::utils::tokens;
+
+/// Always available, downloaded from the Hugging Face Hub on first use.
+const GPT_NEOX: &str = "gpt-neox";
+
+/// Pre-tokenization of the GPT-2 era encodings (`r50k_base`, `p50k_base`), without the
+/// `\s+(?!\S)` alternative the regex crate can't express, see [`Bpe::pieces`].
+const GPT2_PATTERN: &str = r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+";
+
+/// Pre-tokenization of `cl100k_base`, used for every other encoding.
+const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+";
+
+/// A token of a tokenized text.
+#[derive(Debug, Clone, Serialize)]
+pub struct Token {
+    pub id: u32,
+    /// What the token stands for, with `�` for partial characters.
+    pub text: String,
+}
+
+/// A byte pair encoding in the tiktoken format: one base64 encoded token and its rank per
+/// line.
+pub struct Bpe {
+    ranks: HashMap<Vec<u8>, u32>,
+    pattern: Regex,
+    /// Whether whitespace runs ending with a line break are pieces of their own.
+    newline_runs: bool,
+}
+
+impl Bpe {
+    /// Loads a `.tiktoken` file, picking the pre-tokenization from its name.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let data =
+            fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+
+        let mut ranks = HashMap::new();
+        for (i, line) in data.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (token, rank) = line
+                .split_once(' ')
+                .with_context(|| format!("Invalid line {} of {:?}", i + 1, path))?;
+            let token = STANDARD
+                .decode(token)
+                .with_context(|| format!("Invalid token on line {} of {:?}", i + 1, path))?;
+            let rank = rank
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid rank on line {} of {:?}", i + 1, path))?;
+            ranks.insert(token, rank);
+        }
+
+        let name = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        let gpt2 = ["gpt2", "r50k", "p50k"].iter().any(|p| name.starts_with(p));
+        Ok(Self {
+            ranks,
+            pattern: Regex::new(if gpt2 { GPT2_PATTERN } else { CL100K_PATTERN })?,
+            newline_runs: !gpt2,
+        })
+    }
+
+    /// Splits a text in the pieces encoded separately. Whitespace before a word is left to
+    /// the word, as tiktoken's `\s+(?!\S)` does.
+    fn pieces<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        let mut pieces = vec![];
+        let mut start = 0;
+        while let Some(m) = self.pattern.find_at(text, start) {
+            let mut end = m.end();
+            let piece = m.as_str();
+            let before_word = text[end..].starts_with(|c: char| !c.is_whitespace());
+            let newline_run = self.newline_runs && piece.ends_with(['\r', '\n']);
+            if before_word && !newline_run && piece.chars().all(char::is_whitespace) {
+                if let Some((last, _)) = piece.char_indices().last().filter(|(i, _)| *i > 0) {
+                    end = m.start() + last;
+                }
+            }
+            pieces.push(&text[m.start()..end]);
+            start = end;
+        }
+        pieces
+    }
+
+    /// Merges the bytes of a piece, lowest ranked pair first and leftmost among equals. Parts
+    /// are a linked list and candidate pairs a heap, so long pieces take O(n log n).
+    fn merge<'a>(&self, piece: &'a [u8]) -> Vec<&'a [u8]> {
+        let n = piece.len();
+        // Parts are identified by their start: the next part starts at `next`, `n` at the end.
+        let mut next: Vec<usize> = (1..=n).collect();
+        let mut prev: Vec<Option<usize>> = (0..n).map(|i| i.checked_sub(1)).collect();
+        let mut alive = vec![true; n];
+
+        let mut candidates = BinaryHeap::new();
+        let rank = |start: usize, end: usize| self.ranks.get(&piece[start..end]).copied();
+        for start in 0..n.saturating_sub(1) {
+            if let Some(rank) = rank(start, start + 2) {
+                candidates.push(Reverse((rank, start, start + 1, start + 2)));
+            }
+        }
+
+        while let Some(Reverse((_, start, middle, end))) = candidates.pop() {
+            // Pairs whose parts were merged since are stale.
+            let current = alive[start] && alive[middle] && next[start] == middle;
+            if !current || next[middle] != end {
+                continue;
+            }
+
+            alive[middle] = false;
+            next[start] = end;
+            if end < n {
+                prev[end] = Some(start);
+                if let Some(rank) = rank(start, next[end]) {
+                    candidates.push(Reverse((rank, start, end, next[end])));
+                }
+            }
+            if let Some(before) = prev[start] {
+                if let Some(rank) = rank(before, end) {
+                    candidates.push(Reverse((rank, before, start, end)));
+                }
+            }
+        }
+
+        let mut parts = vec![];
+        let mut start = 0;
+        while start < n {
+            parts.push(&piece[start..next[start]]);
+            start = next[start];
+        }
+        parts
+    }
+
+    pub fn encode(&self, text: &str) -> anyhow::Result<Vec<Token>> {
+        let mut tokens = vec![];
+        for piece in self.pieces(text) {
+            let parts = match self.ranks.get(piece.as_bytes()) {
+                Some(_) => vec![piece.as_bytes()],
+                None => self.merge(piece.as_bytes()),
+            };
+            for bytes in parts {
+                let id = self
+                    .ranks
+                    .get(bytes)
+                    .with_context(|| format!("{:?} is not in the vocabulary", bytes))?;
+                tokens.push(Token {
+                    id: *id,
+                    text: String::from_utf8_lossy(bytes).into_owned(),
+                });
+            }
+        }
+        Ok(tokens)
+    }
+}
+
+enum Encoder {
+    HuggingFace(Box<Tokenizer>),
+    Tiktoken(Bpe),
+}
+
+impl Encoder {
+    fn encode(&self, text: &str) -> anyhow::Result<Vec<Token>> {
+        match self {
+            Self::HuggingFace(tokenizer) => {
+                let encoding = tokenizer.encode(text, false).map_err(anyhow::Error::msg)?;
+                encoding
+                    .get_ids()
+                    .iter()
+                    .map(|id| {
+                        let text = tokenizer
+                            .decode(&[*id], false)
+                            .map_err(anyhow::Error::msg)?;
+                        Ok(Token { id: *id, text })
+                    })
+                    .collect()
+            }
+            Self::Tiktoken(bpe) => bpe.encode(text),
+        }
+    }
+}
+
+/// Tokenizers to inspect texts with: GPT-NeoX, and the `tokenizer.json` and `.tiktoken` files
+/// found under `tokenizers/` in the data directory. Each is loaded on first use.
+#[derive(Clone, Default)]
+pub struct Tokenizers {
+    files: BTreeMap<String, PathBuf>,
+    loaded: Arc<Mutex<HashMap<String, Arc<Encoder>>>>,
+}
+
+impl Tokenizers {
+    pub fn open(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().join("tokenizers");
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {:?}", dir))?;
+
+        let mut files = BTreeMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .map(str::to_string);
+            match (name, path.extension().and_then(|e| e.to_str())) {
+                (Some(name), Some("json" | "tiktoken")) if name != GPT_NEOX => {
+                    files.insert(name, path);
+                }
+                _ => (),
+            }
+        }
+
+        Ok(Self {
+            files,
+            loaded: Arc::default(),
+        })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        std::iter::once(GPT_NEOX.to_string())
+            .chain(self.files.keys().cloned())
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        name == GPT_NEOX || self.files.contains_key(name)
+    }
+
+    /// Tokenizes a text, loading the tokenizer first if needed. Blocking.
+    pub fn tokenize(&self, name: &str, text: &str) -> anyhow::Result<Vec<Token>> {
+        let encoder = {
+            // Held while loading, so a tokenizer is only downloaded once.
+            let mut loaded = self.loaded.lock().unwrap();
+            match loaded.get(name) {
+                Some(encoder) => encoder.clone(),
+                None => {
+                    let encoder = Arc::new(self.load(name)?);
+                    loaded.insert(name.to_string(), encoder.clone());
+                    encoder
+                }
+            }
+        };
+        encoder.encode(text)
+    }
+
+    fn load(&self, name: &str) -> anyhow::Result<Encoder> {
+        if name == GPT_NEOX {
+            return Ok(Encoder::HuggingFace(Box::new(tokens::load(GPT_NEOX)?)));
+        }
+        let path = self
+            .files
+            .get(name)
+            .with_context(|| format!("Unknown tokenizer `{}`", name))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("tiktoken") => Ok(Encoder::Tiktoken(Bpe::open(path)?)),
+            _ => {
+                let path = path.to_str().context("Invalid tokenizer path")?;
+                Ok(Encoder::HuggingFace(Box::new(tokens::load(path)?)))
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Value};
+
+use crate::tools::Tool;
+
+pub struct CalculatorTool {}
+
+impl Tool for CalculatorTool {
+    fn name(&self) -> &'static str {
+        "calculator"
+    }
+
+    fn description(&self) -> &'static str {
+        "Evaluates an arithmetic expression. Supports + - * / % ^ and parentheses."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "expression": {
+                    "type": "string",
+                    "description": "The expression to evaluate, e.g. `(1 + 2) * 3`"
+                }
+            },
+            "required": ["expression"]
+        })
+    }
+
+    fn call(&self, arguments: Value) -> Result<String> {
+        let expression = arguments["expression"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Missing `expression` argument"))?;
+
+        Ok(evaluate(expression)?.to_string())
+    }
+}
+
+pub fn evaluate(expression: &str) -> Result<f64> {
+    let mut parser = Parser {
+        chars: expression.chars().filter(|c| !c.is_whitespace()).collect(),
+        pos: 0,
+    };
+
+    let value = parser.expression()?;
+    if let Some(c) = parser.peek() {
+        bail!("Unexpected `{}` at position {}", c, parser.pos);
+    }
+
+    Ok(value)
+}
+
+/// Recursive descent parser, from lowest to highest precedence:
+/// expression := term (('+' | '-') term)*
+/// term       := factor (('*' | '/' | '%') factor)*
+/// factor     := unary ('^' factor)?
+/// unary      := '-' unary | atom
+/// atom       := number | '(' expression ')'
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn expression(&mut self) -> Result<f64> {
+        let mut value = self.term()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            value = if op == '+' { value + rhs } else { value - rhs };
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<f64> {
+        let mut value = self.factor()?;
+        while let Some(op @ ('*' | '/' | '%')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.factor()?;
+            value = match op {
+                '*' => value * rhs,
+                _ if rhs == 0. => bail!("Division by zero"),
+                '/' => value / rhs,
+                _ => value % rhs,
+            };
+        }
+        Ok(value)
+    }
+
+    fn factor(&mut self) -> Result<f64> {
+        let base = self.unary()?;
+        if self.peek() == Some('^') {
+            self.pos += 1;
+            return Ok(base.powf(self.factor()?));
+        }
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> Result<f64> {
+        if self.peek() == Some('-') {
+            self.pos += 1;
+            return Ok(-self.unary()?);
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<f64> {
+        match self.next() {
+            Some('(') => {
+                let value = self.expression()?;
+                match self.next() {
+                    Some(')') => Ok(value),
+                    _ => bail!("Expected `)`"),
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.pos - 1;
+                while matches!(self.peek(), Some(c) if c.is_ascii_digit() || c == '.') {
+                    self.pos += 1;
+                }
+                let number: String = self.chars[start..self.pos].iter().collect();
+                number
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid number `{}`", number))
+            }
+            Some(c) => bail!("Unexpected `{}` at position {}", c, self.pos - 1),
+            None => bail!("Unexpected end of expression"),
+        }
+    }
+}
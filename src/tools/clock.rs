@@ -0,0 +1,37 @@
+use anyhow::Result;
+use chrono::{FixedOffset, Utc};
+use serde_json::{json, Value};
+
+use crate::tools::Tool;
+
+pub struct ClockTool {}
+
+impl Tool for ClockTool {
+    fn name(&self) -> &'static str {
+        "clock"
+    }
+
+    fn description(&self) -> &'static str {
+        "Returns the current date and time, in UTC unless an offset is given."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "utc_offset_hours": {
+                    "type": "number",
+                    "description": "Offset from UTC in hours, e.g. -5 or 5.5"
+                }
+            }
+        })
+    }
+
+    fn call(&self, arguments: Value) -> Result<String> {
+        let offset_hours = arguments["utc_offset_hours"].as_f64().unwrap_or(0.);
+        let offset = FixedOffset::east_opt((offset_hours * 3600.) as i32)
+            .ok_or_else(|| anyhow::anyhow!("Invalid UTC offset: {}", offset_hours))?;
+
+        Ok(Utc::now().with_timezone(&offset).to_rfc2822())
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    io::{self, Read},
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Value};
+
+use crate::tools::Tool;
+
+/// Maximum number of bytes of a page handed back to the model.
+const MAX_BODY_BYTES: u64 = 16 * 1024;
+
+/// Fetches a URL over HTTP.
+///
+/// A base URL (`FETCH_TOOL_BASE_URL`) redirects every request to that host (keeping the path
+/// and query), which lets the tool run against a local stand-in instead of the internet.
+/// Otherwise, hosts resolving to loopback, private or link-local addresses are refused,
+/// redirects included, so the model can't reach the server's network.
+pub struct FetchTool {
+    agent: ureq::Agent,
+    base_url: Option<String>,
+}
+
+impl FetchTool {
+    pub fn new(base_url: Option<String>) -> Self {
+        let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(10));
+        let agent = match base_url {
+            Some(_) => agent,
+            None => agent.resolver(resolve_public),
+        };
+
+        Self {
+            agent: agent.build(),
+            base_url,
+        }
+    }
+}
+
+/// Resolves a `host:port`, refusing hosts with any non-public address.
+fn resolve_public(address: &str) -> io::Result<Vec<SocketAddr>> {
+    let addresses: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+    if addresses.iter().any(|a| !is_public(a.ip())) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("Refusing to fetch `{}`, a private address", address),
+        ));
+    }
+    Ok(addresses)
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // This network, and carrier-grade NAT.
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // Unique local and link-local.
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+impl FetchTool {
+    fn resolve(&self, url: &str) -> Result<String> {
+        let Some((scheme, rest)) = url.split_once("://") else {
+            bail!("Invalid URL `{}`", url);
+        };
+        if scheme != "http" && scheme != "https" {
+            bail!("Unsupported scheme `{}`", scheme);
+        }
+
+        Ok(match &self.base_url {
+            Some(base) => {
+                let path = rest.find('/').map(|i| &rest[i..]).unwrap_or("/");
+                format!("{}{}", base.trim_end_matches('/'), path)
+            }
+            None => url.to_string(),
+        })
+    }
+}
+
+impl Tool for FetchTool {
+    fn name(&self) -> &'static str {
+        "fetch_url"
+    }
+
+    fn description(&self) -> &'static str {
+        "Downloads a web page and returns its text content (truncated)."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "url": {
+                    "type": "string",
+                    "description": "Absolute http(s) URL to fetch"
+                }
+            },
+            "required": ["url"]
+        })
+    }
+
+    fn call(&self, arguments: Value) -> Result<String> {
+        let url = arguments["url"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Missing `url` argument"))?;
+        let url = self.resolve(url)?;
+
+        let response = self.agent.get(&url).call()?;
+        let is_html = response.content_type() == "text/html";
+
+        // The cut may split a character.
+        let mut body = vec![];
+        response
+            .into_reader()
+            .take(MAX_BODY_BYTES)
+            .read_to_end(&mut body)?;
+        let body = String::from_utf8_lossy(&body);
+
+        Ok(if is_html {
+            strip_tags(&body)
+        } else {
+            body.into_owned()
+        })
+    }
+}
+
+/// Crude HTML to text conversion, good enough to feed a page to a model.
+fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,207 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::mpsc::Sender;
+use uuid::Uuid;
+
+use crate::models::{ChatMessage, Role};
+use crate::store::Store;
+
+pub mod calculator;
+pub mod clock;
+pub mod fetch;
+pub mod search;
+
+/// Maximum number of model round trips spent resolving tool calls for a single prompt.
+pub const MAX_TOOL_ROUNDS: usize = 5;
+
+/// A function the model can ask the server to execute.
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    /// JSON Schema describing the arguments object.
+    fn parameters(&self) -> Value;
+    fn call(&self, arguments: Value) -> anyhow::Result<String>;
+}
+
+#[derive(Clone, Default)]
+pub struct ToolRegistry {
+    tools: BTreeMap<&'static str, Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    /// Registry holding every tool shipped with crabot, for a user: conversation search only
+    /// covers the conversations they take part in. See [`fetch::FetchTool`] for `fetch_base_url`.
+    pub fn builtin(store: Store, owner: Uuid, fetch_base_url: Option<String>) -> Self {
+        let mut registry = Self::default();
+        registry.register(calculator::CalculatorTool {});
+        registry.register(clock::ClockTool {});
+        registry.register(fetch::FetchTool::new(fetch_base_url));
+        registry.register(search::SearchTool { store, owner });
+        registry
+    }
+
+    pub fn register(&mut self, tool: impl Tool + 'static) {
+        self.tools.insert(tool.name(), Arc::new(tool));
+    }
+
+    /// Tool definitions in the format expected by the `tools` request parameter.
+    pub fn definitions(&self) -> Value {
+        self.tools
+            .values()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name(),
+                        "description": tool.description(),
+                        "parameters": tool.parameters(),
+                    }
+                })
+            })
+            .collect()
+    }
+
+    pub async fn invoke(&self, call: &ToolCall) -> ToolInvocation {
+        let name = call.function.name.clone();
+        let arguments = call.function.arguments.clone();
+
+        let result = match self.tools.get(name.as_str()).cloned() {
+            Some(tool) => tokio::task::spawn_blocking(move || {
+                let arguments = match arguments.trim() {
+                    "" => json!({}),
+                    a => serde_json::from_str(a)?,
+                };
+                tool.call(arguments)
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.into())),
+            None => Err(anyhow::anyhow!("Unknown tool `{}`", name)),
+        };
+
+        let (output, is_error) = match result {
+            Ok(output) => (output, false),
+            Err(e) => (e.to_string(), true),
+        };
+
+        ToolInvocation {
+            id: call.id.clone(),
+            name: call.function.name.clone(),
+            arguments: call.function.arguments.clone(),
+            output,
+            is_error,
+        }
+    }
+}
+
+/// Tools enabled for a single pipeline run, along with where to report their invocations.
+#[derive(Clone)]
+pub struct Toolbox {
+    pub registry: ToolRegistry,
+    pub invocations: Sender<ToolInvocation>,
+}
+
+impl Toolbox {
+    /// Executes a tool call and returns the message feeding its result back to the model.
+    pub async fn run(&self, call: &ToolCall) -> ChatMessage {
+        let invocation = self.registry.invoke(call).await;
+        let message = ChatMessage {
+            role: Role::Tool,
+            content: invocation.output.clone().into(),
+            tool_calls: vec![],
+            tool_call_id: Some(call.id.clone()),
+        };
+
+        if let Err(e) = self.invocations.send(invocation).await {
+            tracing::error!("Tools: Could not report invocation: {}", e);
+        }
+
+        message
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FunctionCall {
+    pub name: String,
+    pub arguments: String,
+}
+
+/// A fragment of a tool call, as streamed in `delta.tool_calls`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ToolCallDelta {
+    index: Option<usize>,
+    id: Option<String>,
+    function: Option<FunctionCallDelta>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FunctionCallDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+/// Accumulates streamed tool call fragments into complete calls.
+#[derive(Default)]
+pub struct ToolCallBuilder {
+    calls: BTreeMap<usize, ToolCall>,
+}
+
+impl ToolCallBuilder {
+    pub fn push(&mut self, deltas: Vec<ToolCallDelta>) {
+        for (position, delta) in deltas.into_iter().enumerate() {
+            // Providers sending whole calls at once (e.g. Mistral) omit the index.
+            let index = delta.index.unwrap_or(position);
+            let call = self.calls.entry(index).or_insert_with(|| ToolCall {
+                id: "".into(),
+                kind: "function".into(),
+                function: FunctionCall {
+                    name: "".into(),
+                    arguments: "".into(),
+                },
+            });
+
+            if let Some(id) = delta.id {
+                call.id = id;
+            }
+            if let Some(function) = delta.function {
+                if let Some(name) = function.name {
+                    call.function.name.push_str(&name);
+                }
+                if let Some(arguments) = function.arguments {
+                    call.function.arguments.push_str(&arguments);
+                }
+            }
+        }
+    }
+
+    pub fn build(self) -> Vec<ToolCall> {
+        self.calls
+            .into_iter()
+            .map(|(index, mut call)| {
+                if call.id.is_empty() {
+                    call.id = format!("call_{index}");
+                }
+                call
+            })
+            .collect()
+    }
+}
+
+/// A tool call executed on behalf of the model, kept for display.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ToolInvocation {
+    pub id: String,
+    pub name: String,
+    pub arguments: String,
+    pub output: String,
+    pub is_error: bool,
+}
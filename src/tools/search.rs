@@ -0,0 +1,74 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::store::Store;
+use crate::tools::Tool;
+
+const DEFAULT_LIMIT: usize = 5;
+
+/// Full-text search over the prompts and responses of a user's conversations.
+pub struct SearchTool {
+    pub store: Store,
+    /// Only conversations this user owns or was added to are searched.
+    pub owner: Uuid,
+}
+
+impl Tool for SearchTool {
+    fn name(&self) -> &'static str {
+        "search_conversations"
+    }
+
+    fn description(&self) -> &'static str {
+        "Searches previous conversations with the user for messages matching the query, best first."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "query": {
+                    "type": "string",
+                    "description": "Words to look for"
+                },
+                "limit": {
+                    "type": "integer",
+                    "description": "Maximum number of results"
+                }
+            },
+            "required": ["query"]
+        })
+    }
+
+    fn call(&self, arguments: Value) -> Result<String> {
+        let query = arguments["query"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Missing `query` argument"))?;
+        let limit = arguments["limit"]
+            .as_u64()
+            .map(|l| l as usize)
+            .unwrap_or(DEFAULT_LIMIT);
+
+        let results: Vec<Value> = self
+            .store
+            .search(query, self.owner)
+            .into_iter()
+            .filter_map(|hit| {
+                let conversation = self.store.get(hit.conversation)?;
+                let message = conversation
+                    .messages
+                    .into_iter()
+                    .find(|m| m.id == hit.message)?;
+                Some(json!({
+                    "conversation": hit.conversation,
+                    "created_at": message.created_at,
+                    "prompt": message.prompt,
+                    "response": message.response,
+                }))
+            })
+            .take(limit)
+            .collect();
+
+        Ok(serde_json::to_string(&results)?)
+    }
+}
@@ -1 +1,11 @@
+pub mod schema;
 pub mod sse;
+pub mod tokens;
+
+/// The value of an environment variable, or `default` when it is unset or doesn't parse.
+pub(crate) fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
@@ -0,0 +1,94 @@
+use fake::{faker::lorem::en::Word, Fake};
+use jsonschema::JSONSchema;
+use rand::Rng;
+use serde_json::{json, Map, Value};
+
+/// Validates a model response against a JSON Schema, returning every violation found.
+pub fn validate(schema: &Value, text: &str) -> Vec<String> {
+    let compiled = match JSONSchema::compile(schema) {
+        Ok(c) => c,
+        Err(e) => return vec![format!("Invalid schema: {}", e)],
+    };
+
+    let instance: Value = match serde_json::from_str(strip_code_fence(text)) {
+        Ok(v) => v,
+        Err(e) => return vec![format!("Response is not valid JSON: {}", e)],
+    };
+
+    let result = compiled.validate(&instance);
+    match result {
+        Ok(()) => vec![],
+        Err(errors) => errors
+            .map(|e| match e.instance_path.to_string() {
+                path if path.is_empty() => e.to_string(),
+                path => format!("{}: {}", path, e),
+            })
+            .collect(),
+    }
+}
+
+/// Models tend to wrap JSON in a markdown code block even when asked not to.
+fn strip_code_fence(text: &str) -> &str {
+    let text = text.trim();
+    match text.strip_prefix("```") {
+        Some(rest) => {
+            let rest = rest.trim_start_matches("json");
+            rest.strip_suffix("```").unwrap_or(rest).trim()
+        }
+        None => text,
+    }
+}
+
+/// Generates a random value conforming to (the commonly used subset of) a JSON Schema.
+pub fn sample<R: Rng + ?Sized>(schema: &Value, rng: &mut R) -> Value {
+    if let Some(value) = schema.get("const") {
+        return value.clone();
+    }
+    if let Some(values) = schema["enum"].as_array().filter(|v| !v.is_empty()) {
+        return values[rng.gen_range(0..values.len())].clone();
+    }
+    if let Some(variants) = schema["anyOf"]
+        .as_array()
+        .or(schema["oneOf"].as_array())
+        .filter(|v| !v.is_empty())
+    {
+        return sample(&variants[0], rng);
+    }
+
+    let kind = match &schema["type"] {
+        Value::Array(types) => types.first().and_then(Value::as_str),
+        t => t.as_str(),
+    };
+
+    match kind {
+        Some("object") => {
+            let mut object = Map::new();
+            if let Some(properties) = schema["properties"].as_object() {
+                for (name, property) in properties {
+                    object.insert(name.clone(), sample(property, rng));
+                }
+            }
+            Value::Object(object)
+        }
+        Some("array") => {
+            let min = schema["minItems"].as_u64().unwrap_or(1) as usize;
+            let max = schema["maxItems"].as_u64().unwrap_or(3).max(min as u64) as usize;
+            (0..rng.gen_range(min..=max))
+                .map(|_| sample(&schema["items"], rng))
+                .collect()
+        }
+        Some("integer") => {
+            let min = schema["minimum"].as_i64().unwrap_or(0);
+            let max = schema["maximum"].as_i64().unwrap_or(100).max(min);
+            json!(rng.gen_range(min..=max))
+        }
+        Some("number") => {
+            let min = schema["minimum"].as_f64().unwrap_or(0.);
+            let max = schema["maximum"].as_f64().unwrap_or(100.).max(min);
+            json!(rng.gen_range(min..=max))
+        }
+        Some("boolean") => json!(rng.gen_bool(0.5)),
+        Some("null") => Value::Null,
+        _ => json!(Word().fake_with_rng::<String, _>(rng)),
+    }
+}
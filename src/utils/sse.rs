@@ -8,10 +8,12 @@ pub struct SSEvent<T> {
     pub data: T,
 }
 
+/// Parses a response body into events. Reads block, so they run on their own thread, until the
+/// body ends or the receiver is dropped.
 pub fn parse_event_stream(mut stream: impl Read + 'static + Send) -> Receiver<SSEvent<String>> {
     let (tx, rx) = channel(10);
 
-    tokio::spawn(async move {
+    tokio::task::spawn_blocking(move || {
         let mut buf = Vec::new();
         let mut incomplete_line = String::new();
 
@@ -73,9 +75,10 @@ pub fn parse_event_stream(mut stream: impl Read + 'static + Send) -> Receiver<SS
                             continue;
                         }
 
-                        tx.send(event)
-                            .await
-                            .expect("Failed to send chunk to channel");
+                        // The answer was abandoned, the rest of the body is not needed.
+                        if tx.blocking_send(event).is_err() {
+                            return;
+                        }
                     }
 
                     // Store incomplete line for next iteration
@@ -0,0 +1,92 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::Context;
+use tokenizers::Tokenizer;
+
+use crate::models::ChatMessage;
+
+/// Tokens added by chat templates around each message, for its role and separators.
+const MESSAGE_OVERHEAD: u64 = 4;
+
+/// Rough token count of a text, about four characters per token for English with the GPT and
+/// Mistral tokenizers. Good enough for budgets, not for fitting a context window.
+pub fn estimate(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+/// Rough token count of a whole prompt.
+pub fn estimate_messages(messages: &[ChatMessage]) -> u64 {
+    messages.iter().map(|m| estimate(&m.content.text())).sum()
+}
+
+/// Counts tokens with a tokenizer when one is configured, and estimates them otherwise.
+#[derive(Clone, Default)]
+pub struct TokenCounter {
+    tokenizer: Option<Arc<Tokenizer>>,
+}
+
+impl TokenCounter {
+    pub fn new(tokenizer: Tokenizer) -> Self {
+        Self {
+            tokenizer: Some(Arc::new(tokenizer)),
+        }
+    }
+
+    /// Loads `CRABOT_TOKENIZER`: a `tokenizer.json` file, or `gpt-neox` to download the GPT-NeoX
+    /// tokenizer from the Hugging Face Hub. Blocking.
+    pub fn from_env() -> Self {
+        let Some(name) = std::env::var("CRABOT_TOKENIZER")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+        else {
+            return Self::default();
+        };
+
+        match load(&name) {
+            Ok(tokenizer) => Self::new(tokenizer),
+            Err(e) => {
+                tracing::error!("CRABOT_TOKENIZER: {:#}, estimating token counts instead", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.tokenizer.is_some()
+    }
+
+    pub fn count(&self, text: &str) -> u64 {
+        let Some(tokenizer) = &self.tokenizer else {
+            return estimate(text);
+        };
+        match tokenizer.encode(text, false) {
+            Ok(encoding) => encoding.len() as u64,
+            Err(e) => {
+                tracing::warn!("Could not tokenize text: {}", e);
+                estimate(text)
+            }
+        }
+    }
+
+    /// Tokens of a whole prompt, message overheads included.
+    pub fn count_messages(&self, messages: &[ChatMessage]) -> u64 {
+        messages
+            .iter()
+            .map(|m| self.count(&m.content.text()) + MESSAGE_OVERHEAD)
+            .sum()
+    }
+}
+
+/// Loads a tokenizer by name, or from a `tokenizer.json` file.
+pub fn load(name: &str) -> anyhow::Result<Tokenizer> {
+    let path = match name {
+        "gpt-neox" => hf_hub::api::sync::Api::new()?
+            .model("EleutherAI/gpt-neox-20b".to_string())
+            .get("tokenizer.json")
+            .context("Failed to download the GPT-NeoX tokenizer")?,
+        path => Path::new(path).to_path_buf(),
+    };
+    Tokenizer::from_file(&path)
+        .map_err(anyhow::Error::msg)
+        .with_context(|| format!("Failed to load tokenizer {:?}", path))
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header::CONTENT_TYPE, Request, StatusCode},
+};
+
+use common::TestApp;
+use crabot::auth::{UserRole, UserStore};
+
+#[test]
+fn only_the_first_user_is_created_by_setup() {
+    let dir = TestApp::temp_dir();
+    let users = UserStore::open(&dir).unwrap();
+
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let setups: Vec<_> = ["alice", "mallory"]
+            .into_iter()
+            .map(|username| {
+                let users = &users;
+                scope.spawn(move || users.create_first(username, "correct horse"))
+            })
+            .collect();
+        setups.into_iter().map(|s| s.join().unwrap()).collect()
+    });
+
+    assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+    assert_eq!(users.list().len(), 1);
+    assert_eq!(users.list()[0].role, UserRole::Admin);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn login_attempts_are_rate_limited() {
+    let app = TestApp::new();
+    app.login("alice").await;
+
+    let mut statuses = vec![];
+    for _ in 0..10 {
+        let form =
+            serde_urlencoded::to_string([("username", "alice"), ("password", "guess")]).unwrap();
+        let request = Request::post("/login")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(form))
+            .unwrap();
+        statuses.push(app.send(request).await.0);
+    }
+
+    assert_eq!(statuses[0], StatusCode::OK);
+    assert_eq!(statuses[9], StatusCode::TOO_MANY_REQUESTS);
+}
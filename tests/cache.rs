@@ -0,0 +1,151 @@
+mod common;
+
+use std::time::Duration;
+
+use tokio_stream::StreamExt as _;
+use uuid::Uuid;
+
+use common::{parse_sse, TestApp};
+use crabot::cache::{CacheBackend, CacheConfig, CacheKey, ResponseCache};
+use crabot::config::Config;
+use crabot::models::{ChatMessage, ChatModel};
+
+fn enable_cache(config: &mut Config) {
+    config.cache.backend = Some(CacheBackend::Memory);
+    config.models.lorem.delay = Duration::ZERO;
+}
+
+async fn ask(app: &TestApp, cookie: &str, prompt: &str) -> (String, String) {
+    let conversation = Uuid::new_v4().to_string();
+    let (_, body) = app
+        .post_form(
+            cookie,
+            "/",
+            &[("prompt", prompt), ("conversation", &conversation)],
+        )
+        .await;
+    let events = parse_sse(&body);
+    let message = events.iter().find(|e| e.event == "message").unwrap();
+    let stored = app.state.store.get(conversation.parse().unwrap()).unwrap();
+    (message.data.clone(), stored.messages[0].response.clone())
+}
+
+#[tokio::test]
+async fn identical_prompts_are_answered_from_the_cache() {
+    let app = TestApp::with(enable_cache);
+    let cookie = app.login("alice").await;
+
+    let (html, first) = ask(&app, &cookie, "Tell me a story").await;
+    assert!(!html.contains("Cached answer"));
+
+    let (html, second) = ask(&app, &cookie, "Tell me a story").await;
+    assert!(html.contains("Cached answer"));
+    assert_eq!(first, second);
+
+    let (html, other) = ask(&app, &cookie, "Tell me another story").await;
+    assert!(!html.contains("Cached answer"));
+    assert_ne!(first, other);
+
+    // Answers are not shared between users.
+    let cookie = app.login("bob").await;
+    let (html, _) = ask(&app, &cookie, "Tell me a story").await;
+    assert!(!html.contains("Cached answer"));
+}
+
+fn key(prompt: &str) -> CacheKey {
+    CacheKey::new(
+        Uuid::nil(),
+        ChatModel::Lorem,
+        None,
+        &[ChatMessage::user(prompt.into())],
+    )
+}
+
+/// Records an answer, completed or not.
+async fn answer(cache: &ResponseCache, prompt: &str, completed: bool) {
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    let (complete_tx, complete) = tokio::sync::oneshot::channel();
+    let answer = format!("{} answered", prompt);
+    tokio::spawn(async move {
+        let _ = tx.send(answer).await;
+        if completed {
+            let _ = complete_tx.send(());
+        }
+    });
+    let chunks: Vec<_> = cache
+        .record(key(prompt), ChatModel::Lorem, rx.into(), complete)
+        .collect()
+        .await;
+    assert_eq!(chunks.len(), 1);
+}
+
+async fn fill(cache: &ResponseCache, prompt: &str) {
+    answer(cache, prompt, true).await;
+}
+
+#[tokio::test]
+async fn oldest_answers_are_evicted() {
+    let dir = std::env::temp_dir().join(format!("crabot-cache-{}", Uuid::new_v4()));
+    let config = CacheConfig {
+        backend: Some(CacheBackend::Disk),
+        max_entries: 2,
+        ..Default::default()
+    };
+    let cache = ResponseCache::open(&dir, config.clone()).unwrap();
+
+    for prompt in ["one", "two", "three"] {
+        fill(&cache, prompt).await;
+    }
+    assert!(cache.get(&key("one")).is_none());
+    assert!(cache.get(&key("two")).is_some());
+
+    // The disk backend survives restarts.
+    let reopened = ResponseCache::open(&dir, config).unwrap();
+    let answer = reopened.get(&key("three")).unwrap();
+    assert_eq!(answer.chunks, ["three answered"]);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn expired_answers_are_not_reused() {
+    let cache = ResponseCache::open(
+        std::env::temp_dir(),
+        CacheConfig {
+            backend: Some(CacheBackend::Memory),
+            ttl: Duration::ZERO,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    fill(&cache, "one").await;
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    assert!(cache.get(&key("one")).is_none());
+}
+
+#[tokio::test]
+async fn disabled_by_default() {
+    let cache = ResponseCache::open(std::env::temp_dir(), CacheConfig::default()).unwrap();
+    assert!(!cache.is_enabled());
+
+    fill(&cache, "one").await;
+    assert!(cache.get(&key("one")).is_none());
+}
+
+#[tokio::test]
+async fn incomplete_answers_are_not_cached() {
+    let cache = ResponseCache::open(
+        std::env::temp_dir(),
+        CacheConfig {
+            backend: Some(CacheBackend::Memory),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    answer(&cache, "one", false).await;
+    assert!(cache.get(&key("one")).is_none());
+    answer(&cache, "one", true).await;
+    assert!(cache.get(&key("one")).is_some());
+}
@@ -0,0 +1,216 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{
+        header::{CONTENT_TYPE, COOKIE},
+        Request, StatusCode,
+    },
+};
+use uuid::Uuid;
+
+use common::{event_sequence, parse_sse, TestApp};
+use crabot::store::{Message, Store};
+
+#[tokio::test]
+async fn post_message_streams_the_answer() {
+    let app = TestApp::new();
+    let cookie = app.login("alice").await;
+    let conversation = Uuid::new_v4().to_string();
+
+    let (status, body) = app
+        .post_form(
+            &cookie,
+            "/",
+            &[
+                ("prompt", "Tell me about crabs"),
+                ("model", "lorem"),
+                ("conversation", &conversation),
+            ],
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let events = parse_sse(&body);
+    assert_eq!(
+        event_sequence(&events),
+        ["generation", "message", "chunk", "end"]
+    );
+    assert_eq!(events.iter().filter(|e| e.event == "chunk").count(), 20);
+
+    let message = &events[1];
+    assert!(message.data.contains("Tell me about crabs"));
+    assert!(message.data.contains("hx-swap-oob=\"beforeend:#messages\""));
+
+    let stored = app
+        .state
+        .store
+        .get(conversation.parse().unwrap())
+        .expect("Conversation was not stored");
+    let stored = &stored.messages[0];
+    assert_eq!(stored.prompt, "Tell me about crabs");
+    assert!(message
+        .data
+        .contains(&format!("id=\"chunk-{}\"", stored.id)));
+
+    // Chunks are appended to the answer and add up to the stored response.
+    let response: String = events
+        .iter()
+        .filter(|e| e.event == "chunk")
+        .map(|e| {
+            let prefix = format!("<span hx-swap-oob='beforeend:#chunk-{}'>", stored.id);
+            let text = e.data.strip_prefix(&prefix).expect("Unexpected chunk");
+            text.strip_suffix("</span>").unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(response, stored.response);
+
+    // Event ids let the client resume where it left off.
+    assert_eq!(events[0].data, format!("/generations/{}/stream", stored.id));
+    assert!(events[1..].iter().all(|e| e.id.is_some()));
+}
+
+#[tokio::test]
+async fn conversation_page_renders_stored_messages() {
+    let app = TestApp::new();
+    let cookie = app.login("alice").await;
+    let conversation = Uuid::new_v4().to_string();
+
+    app.post_form(
+        &cookie,
+        "/",
+        &[("prompt", "Remember me"), ("conversation", &conversation)],
+    )
+    .await;
+
+    let (status, html) = app.get(&cookie, &format!("/c/{}", conversation)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("Remember me"));
+    let stored = app.state.store.get(conversation.parse().unwrap()).unwrap();
+    assert!(html.contains(stored.messages[0].response.trim()));
+}
+
+#[tokio::test]
+async fn conversations_are_private() {
+    let app = TestApp::new();
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let conversation = Uuid::new_v4().to_string();
+
+    app.post_form(
+        &alice,
+        "/",
+        &[("prompt", "A secret"), ("conversation", &conversation)],
+    )
+    .await;
+
+    let (status, _) = app.get(&bob, &format!("/c/{}", conversation)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = app
+        .post_form(
+            &bob,
+            "/",
+            &[("prompt", "Let me in"), ("conversation", &conversation)],
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn requires_a_session() {
+    let app = TestApp::new();
+
+    let (status, _) = app.post_form("", "/", &[("prompt", "Hello")]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn invalid_schemas_are_refused() {
+    let app = TestApp::new();
+    let cookie = app.login("alice").await;
+    let conversation = Uuid::new_v4();
+
+    let (status, body) = app
+        .post_form(
+            &cookie,
+            "/",
+            &[
+                ("prompt", "Hello"),
+                ("conversation", &conversation.to_string()),
+                ("schema", "{not json"),
+            ],
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let events = parse_sse(&body);
+    assert_eq!(event_sequence(&events), ["error", "end"]);
+    assert!(events[0].data.contains("Invalid schema"));
+    assert!(app.state.store.get(conversation).is_none());
+}
+
+#[test]
+fn concurrent_changes_are_all_saved() {
+    let dir = TestApp::temp_dir();
+    let store = Store::open(&dir).unwrap();
+    let (id, owner) = (Uuid::new_v4(), Uuid::new_v4());
+
+    std::thread::scope(|scope| {
+        for i in 0..16 {
+            let store = &store;
+            scope.spawn(move || {
+                let message = Message::new(format!("Prompt {}", i), Default::default());
+                store.push_message(id, owner, message).unwrap();
+            });
+        }
+    });
+
+    // The last file written holds every message, and no temporary file is left behind.
+    let reopened = Store::open(&dir).unwrap();
+    assert_eq!(reopened.get(id).unwrap().messages.len(), 16);
+    assert_eq!(
+        std::fs::read_dir(dir.join("conversations"))
+            .unwrap()
+            .count(),
+        1
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn attachments_are_served_to_those_who_can_read_them() {
+    let app = TestApp::new();
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let conversation = Uuid::new_v4();
+
+    let boundary = "crabot-boundary";
+    let body = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"prompt\"\r\n\r\nRead this\r\n\
+         --{b}\r\nContent-Disposition: form-data; name=\"conversation\"\r\n\r\n{}\r\n\
+         --{b}\r\nContent-Disposition: form-data; name=\"files\"; filename=\"notes.txt\"\r\n\
+         Content-Type: text/plain\r\n\r\nHermit crabs\r\n--{b}--\r\n",
+        conversation,
+        b = boundary
+    );
+    let request = Request::post("/")
+        .header(COOKIE, &alice)
+        .header(
+            CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .body(Body::from(body))
+        .unwrap();
+    app.send(request).await;
+
+    let stored = app.state.store.get(conversation).unwrap();
+    let url = stored.messages[0].attachments[0].url();
+    assert_eq!(
+        app.get(&alice, &url).await,
+        (StatusCode::OK, "Hermit crabs".into())
+    );
+    assert_eq!(app.get(&bob, &url).await.0, StatusCode::NOT_FOUND);
+    let renamed = url.replace("notes.txt", "other.txt");
+    assert_eq!(app.get(&alice, &renamed).await.0, StatusCode::NOT_FOUND);
+}
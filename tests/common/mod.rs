@@ -0,0 +1,172 @@
+//! An in-process app over a throwaway data directory, driven without binding a port.
+
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{
+        header::{CONTENT_TYPE, COOKIE, SET_COOKIE},
+        Request, StatusCode,
+    },
+    Router,
+};
+use tower::ServiceExt as _;
+use uuid::Uuid;
+
+use crabot::app::{create_app, open_state};
+use crabot::auth::UserRole;
+use crabot::config::Config;
+use crabot::shutdown::Shutdown;
+use crabot::state::AppState;
+
+pub const PASSWORD: &str = "correct horse battery staple";
+
+pub struct TestApp {
+    pub app: Router,
+    pub state: AppState,
+    data_dir: PathBuf,
+}
+
+impl TestApp {
+    pub fn new() -> Self {
+        Self::with(|_| ())
+    }
+
+    /// Opens the app with a configuration changed from the default, as the server would from
+    /// its environment.
+    pub fn with(configure: impl FnOnce(&mut Config)) -> Self {
+        let mut config = Config::default();
+        configure(&mut config);
+        Self::open_with(Self::temp_dir(), config)
+    }
+
+    /// A fresh data directory, for tests preparing it before the app opens it.
+    pub fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("crabot-test-{}", Uuid::new_v4()))
+    }
+
+    /// Opens the app over a data directory, removed once done.
+    pub fn open(data_dir: PathBuf) -> Self {
+        Self::open_with(data_dir, Config::default())
+    }
+
+    pub fn open_with(data_dir: PathBuf, config: Config) -> Self {
+        let state =
+            open_state(&data_dir, Shutdown::default(), config).expect("Failed to open state");
+        Self {
+            app: create_app(state.clone()),
+            state,
+            data_dir,
+        }
+    }
+
+    /// Creates a user and returns the session cookie it logs in with. The first user logs in
+    /// through the setup form and becomes an administrator.
+    pub async fn login(&self, username: &str) -> String {
+        if !self.state.users.is_empty() {
+            self.state
+                .users
+                .create(username, PASSWORD, UserRole::User)
+                .expect("Failed to create user");
+        }
+
+        let form =
+            serde_urlencoded::to_string([("username", username), ("password", PASSWORD)]).unwrap();
+        let response = self
+            .app
+            .clone()
+            .oneshot(
+                Request::post("/login")
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(Body::from(form))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        cookie.split(';').next().unwrap().to_string()
+    }
+
+    pub async fn get(&self, cookie: &str, uri: &str) -> (StatusCode, String) {
+        let request = Request::get(uri)
+            .header(COOKIE, cookie)
+            .body(Body::empty())
+            .unwrap();
+        self.send(request).await
+    }
+
+    /// Posts a url-encoded form, as htmx does.
+    pub async fn post_form(
+        &self,
+        cookie: &str,
+        uri: &str,
+        fields: &[(&str, &str)],
+    ) -> (StatusCode, String) {
+        let request = Request::post(uri)
+            .header(COOKIE, cookie)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("HX-Request", "true")
+            .body(Body::from(serde_urlencoded::to_string(fields).unwrap()))
+            .unwrap();
+        self.send(request).await
+    }
+
+    /// Sends a request and reads its whole body, event streams included.
+    pub async fn send(&self, request: Request<Body>) -> (StatusCode, String) {
+        let response = self.app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+/// One server-sent event.
+#[derive(Debug, Clone)]
+pub struct SseEvent {
+    pub event: String,
+    pub data: String,
+    pub id: Option<String>,
+}
+
+/// Parses an event stream, skipping comments such as keep-alives.
+pub fn parse_sse(body: &str) -> Vec<SseEvent> {
+    body.split("\n\n")
+        .filter_map(|block| {
+            let mut event = SseEvent {
+                event: "message".into(),
+                data: String::new(),
+                id: None,
+            };
+            let mut data = vec![];
+            for line in block.lines() {
+                let (field, value) = line.split_once(':').unwrap_or((line, ""));
+                let value = value.strip_prefix(' ').unwrap_or(value);
+                match field {
+                    "event" => event.event = value.to_string(),
+                    "data" => data.push(value),
+                    "id" => event.id = Some(value.to_string()),
+                    _ => (),
+                }
+            }
+            event.data = data.join("\n");
+            (!block.trim().is_empty() && !block.starts_with(':')).then_some(event)
+        })
+        .collect()
+}
+
+/// Names of the events, with consecutive repeats collapsed.
+pub fn event_sequence(events: &[SseEvent]) -> Vec<&str> {
+    let mut names: Vec<&str> = events.iter().map(|e| e.event.as_str()).collect();
+    names.dedup();
+    names
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use std::{collections::HashMap, time::Duration};
+
+use axum::{
+    body::Body,
+    http::{
+        header::{CONTENT_TYPE, COOKIE},
+        Request, StatusCode,
+    },
+};
+use uuid::Uuid;
+
+use common::{parse_sse, TestApp};
+use crabot::models::mock::MockProvider;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn models_are_compared_once_with_a_valid_schema() {
+    let provider = MockProvider::start(HashMap::new()).await.unwrap();
+    let app = TestApp::with(|config| {
+        config.models.lorem.delay = Duration::ZERO;
+        provider.install(&mut config.models);
+    });
+    let cookie = app.login("alice").await;
+    let conversation = Uuid::new_v4().to_string();
+
+    let form = [
+        ("prompt", "Hello"),
+        ("conversation", conversation.as_str()),
+        ("models", "lorem"),
+        ("models", "mistral"),
+        ("models", "lorem"),
+    ];
+    let (status, body) = app.post_form(&cookie, "/compare", &form).await;
+    assert_eq!(status, StatusCode::OK);
+    let events = parse_sse(&body);
+    assert_eq!(events.iter().filter(|e| e.event == "stats").count(), 2);
+
+    // The compare form is told over its event stream, other clients get a 400.
+    let mut form = form.to_vec();
+    form.push(("schema", "{not json"));
+    let (_, body) = app.post_form(&cookie, "/compare", &form).await;
+    let events = parse_sse(&body);
+    assert!(events
+        .iter()
+        .any(|e| e.event == "error" && e.data.contains("Invalid schema")));
+
+    let request = Request::post("/compare")
+        .header(COOKIE, &cookie)
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(serde_urlencoded::to_string(&form).unwrap()))
+        .unwrap();
+    let (status, body) = app.send(request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.starts_with("Invalid schema"));
+}
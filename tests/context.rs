@@ -0,0 +1,176 @@
+mod common;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use common::{parse_sse, TestApp};
+use crabot::config::Config;
+use crabot::context::{fit, ContextConfig, ContextStrategy, RollingSummary};
+use crabot::models::{mock::MockProvider, ChatMessage, ChatModel, Role};
+use crabot::utils::tokens::TokenCounter;
+
+/// A 300 token window, 100 of them for the answer.
+fn config(strategy: ContextStrategy) -> ContextConfig {
+    ContextConfig {
+        strategy,
+        answer_tokens: 100,
+        windows: HashMap::from([(ChatModel::Lorem, 300)]),
+    }
+}
+
+fn small_window(config: &mut Config) {
+    config.context = self::config(ContextStrategy::default());
+    config.models.lorem.delay = Duration::ZERO;
+}
+
+/// A system message, ten exchanges of about 30 tokens each, and a prompt.
+fn history() -> Vec<ChatMessage> {
+    let mut messages = vec![ChatMessage::system("Be brief.".into())];
+    for i in 0..10 {
+        messages.push(ChatMessage::user(format!(
+            "Question {} {}",
+            i,
+            "x".repeat(80)
+        )));
+        messages.push(ChatMessage::assistant(format!("Answer {}", i)));
+    }
+    messages.push(ChatMessage::user("Last question".into()));
+    messages
+}
+
+fn text(message: &ChatMessage) -> String {
+    message.content.text()
+}
+
+#[test]
+fn oldest_exchanges_are_dropped_first() {
+    let counter = TokenCounter::default();
+    let fitted = fit(
+        history(),
+        ChatModel::Lorem,
+        &counter,
+        &config(ContextStrategy::DropOldest),
+        None,
+    );
+
+    assert!(fitted.trimmed > 0 && fitted.trimmed < 10);
+    assert!(!fitted.summarized);
+    assert!(counter.count_messages(&fitted.messages) <= 200);
+    assert_eq!(fitted.messages.len(), 1 + 2 * (10 - fitted.trimmed) + 1);
+    assert_eq!(text(&fitted.messages[0]), "Be brief.");
+    assert!(text(&fitted.messages[1]).starts_with(&format!("Question {}", fitted.trimmed)));
+    assert_eq!(text(fitted.messages.last().unwrap()), "Last question");
+}
+
+#[test]
+fn keep_last_sends_the_latest_exchanges() {
+    let fitted = fit(
+        history(),
+        ChatModel::Lorem,
+        &TokenCounter::default(),
+        &config(ContextStrategy::KeepLast(2)),
+        None,
+    );
+
+    assert_eq!(fitted.trimmed, 8);
+    let texts: Vec<String> = fitted.messages.iter().map(text).collect();
+    assert_eq!(texts[0], "Be brief.");
+    assert!(texts[1].starts_with("Question 8"));
+    assert_eq!(texts[4], "Answer 9");
+    assert_eq!(texts[5], "Last question");
+}
+
+#[test]
+fn summarize_replaces_exchanges_with_the_rolling_summary() {
+    let summary = RollingSummary {
+        text: "Ten questions were asked.".into(),
+        exchanges: 9,
+    };
+    let fitted = fit(
+        history(),
+        ChatModel::Lorem,
+        &TokenCounter::default(),
+        &config(ContextStrategy::Summarize),
+        Some(&summary),
+    );
+
+    // The summary covers more than what had to be left out.
+    assert_eq!(fitted.trimmed, 9);
+    assert!(fitted.summarized);
+    let texts: Vec<String> = fitted.messages.iter().map(text).collect();
+    assert_eq!(fitted.messages[1].role, Role::System);
+    assert_eq!(
+        texts[1],
+        "Summary of earlier messages: Ten questions were asked."
+    );
+    assert!(texts[2].starts_with("Question 9"));
+    assert_eq!(texts.last().unwrap(), "Last question");
+
+    // Without a summary, exchanges are dropped.
+    let fitted = fit(
+        history(),
+        ChatModel::Lorem,
+        &TokenCounter::default(),
+        &config(ContextStrategy::Summarize),
+        None,
+    );
+    assert!(fitted.trimmed > 0 && !fitted.summarized);
+    assert!(text(&fitted.messages[1]).starts_with(&format!("Question {}", fitted.trimmed)));
+}
+
+#[tokio::test]
+async fn trimmed_answers_say_so() {
+    let app = TestApp::with(small_window);
+    let cookie = app.login("alice").await;
+    let conversation = Uuid::new_v4().to_string();
+
+    // About 150 tokens each, two don't fit together.
+    let prompt = "crab ".repeat(120);
+    for _ in 0..2 {
+        app.post_form(
+            &cookie,
+            "/",
+            &[("prompt", &prompt), ("conversation", &conversation)],
+        )
+        .await;
+    }
+
+    let stored = app.state.store.get(conversation.parse().unwrap()).unwrap();
+    assert_eq!(stored.messages[0].context_trimmed, 0);
+    assert_eq!(stored.messages[1].context_trimmed, 1);
+
+    let (_, html) = app.get(&cookie, &format!("/c/{}", conversation)).await;
+    assert!(html.contains("The first message was left out"));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn trimmed_comparisons_say_so() {
+    let provider = MockProvider::start(Default::default()).await.unwrap();
+    let app = TestApp::with(|config| {
+        small_window(config);
+        provider.install(&mut config.models);
+    });
+    let cookie = app.login("alice").await;
+    let conversation = Uuid::new_v4().to_string();
+
+    let prompt = "crab ".repeat(120);
+    app.post_form(
+        &cookie,
+        "/",
+        &[("prompt", &prompt), ("conversation", &conversation)],
+    )
+    .await;
+
+    let form = [
+        ("prompt", prompt.as_str()),
+        ("conversation", &conversation),
+        ("models", "lorem"),
+        ("models", "mistral"),
+    ];
+    let (_, body) = app.post_form(&cookie, "/compare", &form).await;
+    let events = parse_sse(&body);
+    let columns = events.iter().find(|e| e.event == "comparison").unwrap();
+    assert!(columns.data.contains("The first message was left out"));
+}
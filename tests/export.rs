@@ -0,0 +1,115 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    http::{
+        header::{CONTENT_TYPE, COOKIE},
+        Request, StatusCode,
+    },
+};
+use uuid::Uuid;
+
+use common::TestApp;
+use crabot::export;
+
+async fn upload(app: &TestApp, cookie: &str, data: &str) {
+    let boundary = "crabot-boundary";
+    let body = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"export.json\"\r\n\
+         Content-Type: application/json\r\n\r\n{data}\r\n--{b}--\r\n",
+        b = boundary,
+        data = data
+    );
+    let request = Request::post("/import")
+        .header(COOKIE, cookie)
+        .header(
+            CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .body(Body::from(body))
+        .unwrap();
+    let (status, _) = app.send(request).await;
+    assert!(
+        status.is_success() || status == StatusCode::SEE_OTHER,
+        "{}",
+        status
+    );
+}
+
+#[tokio::test]
+async fn imports_are_private_to_the_uploader() {
+    let app = TestApp::with(|config| config.models.lorem.delay = Duration::ZERO);
+    let alice = app.login("alice").await;
+    app.login("bob").await;
+    let bob_id = app.state.users.find("bob").unwrap().id;
+
+    // An export of a conversation shared with bob.
+    let id = Uuid::new_v4();
+    app.post_form(
+        &alice,
+        "/",
+        &[("prompt", "Hello"), ("conversation", &id.to_string())],
+    )
+    .await;
+    app.state.store.add_member(id, bob_id).unwrap();
+    let original = app.state.store.get(id).unwrap();
+    upload(&app, &alice, &export::to_json(&original).unwrap()).await;
+
+    let imported: Vec<_> = app
+        .state
+        .store
+        .list()
+        .into_iter()
+        .filter(|c| c.id != id)
+        .collect();
+    assert_eq!(imported.len(), 1);
+    assert!(imported[0].members.is_empty());
+    assert!(!app
+        .state
+        .store
+        .list_joined(bob_id)
+        .iter()
+        .any(|c| c.id == imported[0].id));
+    // Imports are copies, with ids of their own.
+    assert_ne!(imported[0].messages[0].id, original.messages[0].id);
+}
+
+#[tokio::test]
+async fn imports_leave_the_originals_searchable() {
+    let app = TestApp::with(|config| config.models.lorem.delay = Duration::ZERO);
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let bob_id = app.state.users.find("bob").unwrap().id;
+
+    let id = Uuid::new_v4();
+    app.post_form(
+        &alice,
+        "/",
+        &[
+            ("prompt", "How do hermit crabs choose their shells?"),
+            ("conversation", &id.to_string()),
+        ],
+    )
+    .await;
+    app.state.store.add_member(id, bob_id).unwrap();
+    let original = app.state.store.get(id).unwrap();
+
+    // Bob imports the export alice shared with him.
+    let (_, data) = app.get(&bob, &format!("/c/{}/export", id)).await;
+    upload(&app, &bob, &data).await;
+    let copy = app
+        .state
+        .store
+        .list_for(bob_id)
+        .into_iter()
+        .find(|c| c.id != id)
+        .unwrap();
+    assert_ne!(copy.messages[0].id, original.messages[0].id);
+
+    let (_, html) = app.get(&alice, "/search?q=hermit").await;
+    assert!(html.contains(&format!("/c/{}#message-{}", id, original.messages[0].id)));
+    let (_, html) = app.get(&bob, "/search?q=hermit").await;
+    assert!(html.contains(&format!("/c/{}#message-{}", copy.id, copy.messages[0].id)));
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        Request, StatusCode,
+    },
+};
+use uuid::Uuid;
+
+use common::TestApp;
+use crabot::auth::keys::{ApiKey, ApiKeyStore, NewApiKey, QuotaError};
+use crabot::models::ChatModel;
+
+fn create_key(store: &ApiKeyStore, daily_tokens: u64) -> ApiKey {
+    let new_key = NewApiKey {
+        name: "ci".into(),
+        owner: Uuid::new_v4(),
+        models: vec![],
+        daily_tokens: Some(daily_tokens),
+        requests_per_minute: None,
+    };
+    store.create(new_key).unwrap().0
+}
+
+#[test]
+fn running_requests_reserve_their_tokens() {
+    let dir = TestApp::temp_dir();
+    let store = ApiKeyStore::open(&dir).unwrap();
+    let key = create_key(&store, 100);
+
+    // The second request doesn't fit while the first one runs.
+    let first = store.reserve(&key, &[ChatModel::Lorem], 60).unwrap();
+    assert!(matches!(
+        store.reserve(&key, &[ChatModel::Lorem], 60),
+        Err(QuotaError::BudgetExceeded)
+    ));
+
+    // Once recorded, only what was spent counts.
+    store.record_usage(first, 30).unwrap();
+    let second = store.reserve(&key, &[ChatModel::Lorem], 60).unwrap();
+    store.record_usage(second, 60).unwrap();
+    assert_eq!(store.list()[0].tokens_today(), 90);
+
+    // Comparisons spend the prompt once per model.
+    let key = create_key(&store, 100);
+    let models = [ChatModel::Lorem, ChatModel::Mistral];
+    assert!(matches!(
+        store.reserve(&key, &models, 60),
+        Err(QuotaError::BudgetExceeded)
+    ));
+    assert!(store.reserve(&key, &models[..1], 60).is_ok());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn keys_are_admitted_to_their_models_and_rate() {
+    let dir = TestApp::temp_dir();
+    let store = ApiKeyStore::open(&dir).unwrap();
+    let new_key = NewApiKey {
+        name: "ci".into(),
+        owner: Uuid::new_v4(),
+        models: vec![ChatModel::Lorem],
+        daily_tokens: None,
+        requests_per_minute: Some(1),
+    };
+    let key = store.create(new_key).unwrap().0;
+
+    assert!(matches!(
+        store.admit(&key, &[ChatModel::Lorem, ChatModel::Mistral]),
+        Err(QuotaError::ModelNotAllowed(ChatModel::Mistral))
+    ));
+    assert!(store.admit(&key, &[ChatModel::Lorem]).is_ok());
+    assert!(matches!(
+        store.admit(&key, &[ChatModel::Lorem]),
+        Err(QuotaError::RateLimited)
+    ));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn refused_keys_store_nothing() {
+    let app = TestApp::new();
+    app.login("alice").await;
+    let new_key = NewApiKey {
+        name: "ci".into(),
+        owner: app.state.users.find("alice").unwrap().id,
+        models: vec![ChatModel::Mistral],
+        daily_tokens: None,
+        requests_per_minute: None,
+    };
+    let (_, secret) = app.state.api_keys.create(new_key).unwrap();
+
+    let boundary = "crabot-boundary";
+    let field = |name: &str, value: &str| {
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+            boundary, name, value
+        )
+    };
+    let body = format!(
+        "{}{}{}--{b}\r\nContent-Disposition: form-data; name=\"files\"; filename=\"notes.txt\"\r\n\
+         Content-Type: text/plain\r\n\r\nHermit crabs\r\n--{b}--\r\n",
+        field("prompt", "Summarize my notes"),
+        field("model", "lorem"),
+        field("conversation", &Uuid::new_v4().to_string()),
+        b = boundary
+    );
+    let request = Request::post("/")
+        .header(AUTHORIZATION, format!("Bearer {}", secret))
+        .header(
+            CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .body(Body::from(body))
+        .unwrap();
+    let (status, _) = app.send(request).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        std::fs::read_dir(app.state.attachments.dir())
+            .unwrap()
+            .count(),
+        0
+    );
+}
@@ -0,0 +1,135 @@
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+use tokio_stream::StreamExt as _;
+
+use crabot::models::{
+    lorem::{Failure, LoremMode, LoremOptions, LoremPipeline},
+    ChatMessage, Pipeline,
+};
+
+fn lorem(options: LoremOptions) -> LoremPipeline {
+    LoremPipeline {
+        schema: None,
+        options: LoremOptions {
+            delay: Duration::ZERO,
+            ..options
+        },
+    }
+}
+
+async fn answer(pipeline: &LoremPipeline, prompt: &str) -> Vec<String> {
+    pipeline
+        .run(vec![ChatMessage::user(prompt.into())])
+        .collect()
+        .await
+}
+
+#[tokio::test]
+async fn seeded_answers_are_reproducible() {
+    let options = LoremOptions {
+        seed: Some(42),
+        words: 12,
+        ..Default::default()
+    };
+
+    let first = answer(&lorem(options.clone()), "Hello").await;
+    let second = answer(&lorem(options.clone()), "Hello").await;
+    assert_eq!(first, second);
+    assert_eq!(first.len(), 12);
+
+    let other = answer(
+        &lorem(LoremOptions {
+            seed: Some(43),
+            ..options
+        }),
+        "Hello",
+    )
+    .await;
+    assert_ne!(first, other);
+}
+
+#[tokio::test]
+async fn echo_streams_the_prompt_back() {
+    let pipeline = lorem(LoremOptions {
+        mode: LoremMode::Echo,
+        ..Default::default()
+    });
+
+    let tokens = answer(&pipeline, "Is anybody\nout there?").await;
+    assert_eq!(tokens, ["Is ", "anybody\n", "out ", "there?"]);
+}
+
+#[tokio::test]
+async fn markdown_has_a_code_block() {
+    let pipeline = lorem(LoremOptions {
+        seed: Some(7),
+        mode: LoremMode::Markdown,
+        ..Default::default()
+    });
+
+    let text = answer(&pipeline, "Show me some code").await.concat();
+    assert!(text.starts_with("## "));
+    assert!(text.contains("\n- "));
+    assert!(text.contains("```rust\nfn "));
+}
+
+#[tokio::test]
+async fn schema_answers_conform() {
+    let schema = serde_json::json!({
+        "type": "object",
+        "properties": {"name": {"type": "string"}, "age": {"type": "integer", "minimum": 1}},
+    });
+    let pipeline = LoremPipeline {
+        schema: Some(schema.clone()),
+        options: LoremOptions {
+            delay: Duration::ZERO,
+            ..Default::default()
+        },
+    };
+
+    let text = answer(&pipeline, "Who are you?").await.concat();
+    assert!(crabot::utils::schema::validate(&schema, &text).is_empty());
+}
+
+#[tokio::test]
+async fn injected_failures() {
+    let broken = lorem(LoremOptions {
+        failure: Some(Failure::Error { after: 5 }),
+        ..Default::default()
+    });
+    assert_eq!(answer(&broken, "Hello").await.len(), 5);
+
+    // Past the end of the answer, it still breaks off.
+    let late = lorem(LoremOptions {
+        failure: Some(Failure::Error { after: 50 }),
+        ..Default::default()
+    });
+    let (complete, completed) = oneshot::channel();
+    let tokens: Vec<String> = late
+        .stream(vec![ChatMessage::user("Hello".into())], complete)
+        .collect()
+        .await;
+    assert_eq!(tokens.len(), 20);
+    assert!(completed.await.is_err());
+
+    let stalled = lorem(LoremOptions {
+        failure: Some("stall:3:200".parse().unwrap()),
+        ..Default::default()
+    });
+    let started_at = Instant::now();
+    assert_eq!(answer(&stalled, "Hello").await.len(), 20);
+    assert!(started_at.elapsed() >= Duration::from_millis(200));
+
+    let slow = lorem(LoremOptions {
+        failure: Some("slow-start:200".parse().unwrap()),
+        ..Default::default()
+    });
+    let started_at = Instant::now();
+    let mut tokens = slow.run(vec![ChatMessage::user("Hello".into())]);
+    tokens.next().await.unwrap();
+    assert!(started_at.elapsed() >= Duration::from_millis(200));
+
+    assert!("explode:1".parse::<Failure>().is_err());
+    assert!("stall:soon".parse::<Failure>().is_err());
+}
@@ -0,0 +1,176 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::{
+    body::{to_bytes, Body, BodyDataStream},
+    http::{header::COOKIE, Request},
+};
+use futures::StreamExt as _;
+use tokio::task::JoinHandle;
+use tower::ServiceExt as _;
+use uuid::Uuid;
+
+use common::{parse_sse, TestApp};
+use crabot::config::Config;
+use crabot::models::lorem::LoremMode;
+
+fn echo(config: &mut Config) {
+    config.models.lorem.mode = LoremMode::Echo;
+    config.models.lorem.delay = Duration::ZERO;
+}
+
+#[tokio::test]
+async fn answer_chunks_are_escaped() {
+    let app = TestApp::with(echo);
+    let cookie = app.login("alice").await;
+    let conversation = Uuid::new_v4().to_string();
+
+    let (_, body) = app
+        .post_form(
+            &cookie,
+            "/",
+            &[
+                ("prompt", "<img src=x onerror=alert(1)>"),
+                ("conversation", &conversation),
+            ],
+        )
+        .await;
+
+    let chunks: String = parse_sse(&body)
+        .into_iter()
+        .filter(|e| e.event == "chunk")
+        .map(|e| e.data)
+        .collect();
+    assert!(chunks.contains("&lt;img "));
+    assert!(!chunks.contains("<img"));
+}
+
+/// Follows a conversation live until the stream ends, and returns what it got.
+fn follow(app: &TestApp, cookie: &str, conversation: &str) -> JoinHandle<String> {
+    let request = Request::get(format!("/c/{}/live", conversation))
+        .header(COOKIE, cookie)
+        .body(Body::empty())
+        .unwrap();
+    let app = app.app.clone();
+    tokio::spawn(async move {
+        let response = app.oneshot(request).await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    })
+}
+
+async fn ended(stream: JoinHandle<String>) -> String {
+    tokio::time::timeout(Duration::from_secs(5), stream)
+        .await
+        .expect("The live stream did not end")
+        .unwrap()
+}
+
+/// A conversation of alice's shared with bob.
+async fn shared(app: &TestApp) -> (String, String, String) {
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+    let conversation = Uuid::new_v4().to_string();
+    app.post_form(
+        &alice,
+        "/",
+        &[("prompt", "Hello"), ("conversation", &conversation)],
+    )
+    .await;
+    app.post_form(
+        &alice,
+        &format!("/c/{}/members", conversation),
+        &[("username", "bob")],
+    )
+    .await;
+    (alice, bob, conversation)
+}
+
+#[tokio::test]
+async fn live_streams_end_on_shutdown() {
+    let app = TestApp::with(echo);
+    let (_, bob, conversation) = shared(&app).await;
+
+    let stream = follow(&app, &bob, &conversation);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!stream.is_finished());
+
+    app.state.shutdown.begin();
+    ended(stream).await;
+}
+
+#[tokio::test]
+async fn removed_members_stop_following() {
+    let app = TestApp::with(echo);
+    let (alice, bob, conversation) = shared(&app).await;
+
+    let stream = follow(&app, &bob, &conversation);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!stream.is_finished());
+
+    let bob_id = app.state.users.find("bob").unwrap().id;
+    let request = Request::delete(format!("/c/{}/members/{}", conversation, bob_id))
+        .header(COOKIE, &alice)
+        .body(Body::empty())
+        .unwrap();
+    app.send(request).await;
+
+    let body = ended(stream).await;
+    assert!(parse_sse(&body)
+        .iter()
+        .all(|e| e.event == "viewer" || e.event == "presence"));
+}
+
+/// Reads a live stream until it sent an event.
+async fn read_until(body: &mut BodyDataStream, received: &mut String, event: &str) {
+    while !received.contains(event) {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), body.next())
+            .await
+            .expect("The live stream stalled")
+            .unwrap()
+            .unwrap();
+        received.push_str(std::str::from_utf8(&chunk).unwrap());
+    }
+}
+
+#[tokio::test]
+async fn viewer_ids_are_issued_by_the_server() {
+    let app = TestApp::with(echo);
+    let (alice, bob, conversation) = shared(&app).await;
+
+    let request = Request::get(format!("/c/{}/live", conversation))
+        .header(COOKIE, &bob)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.app.clone().oneshot(request).await.unwrap();
+    let mut body = response.into_body().into_data_stream();
+    let mut received = String::new();
+
+    // The page is told its id first.
+    read_until(&mut body, &mut received, "event: viewer").await;
+    let event = &parse_sse(&received)[0];
+    assert_eq!(event.event, "viewer");
+    let viewer = event
+        .data
+        .split("value=\"")
+        .nth(1)
+        .and_then(|v| v.split('"').next())
+        .unwrap()
+        .to_string();
+    assert!(Uuid::parse_str(&viewer).is_ok());
+
+    // Bob's id doesn't keep alice's answers from his page.
+    app.post_form(
+        &alice,
+        "/",
+        &[
+            ("prompt", "Hidden from bob?"),
+            ("conversation", &conversation),
+            ("viewer", &viewer),
+        ],
+    )
+    .await;
+    read_until(&mut body, &mut received, "event: end").await;
+    assert!(received.contains("Hidden from bob?"));
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use common::TestApp;
+use crabot::search::{snippet, SearchIndex};
+use crabot::store::{Conversation, Message};
+
+async fn post(app: &TestApp, cookie: &str, prompt: &str) -> Uuid {
+    let conversation = Uuid::new_v4();
+    app.post_form(
+        cookie,
+        "/",
+        &[
+            ("prompt", prompt),
+            ("conversation", &conversation.to_string()),
+        ],
+    )
+    .await;
+    conversation
+}
+
+#[tokio::test]
+async fn search_finds_messages_of_the_user() {
+    let app = TestApp::with(|config| config.models.lorem.delay = Duration::ZERO);
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+
+    let crabs = post(&app, &alice, "How do hermit crabs choose their shells?").await;
+    post(&app, &alice, "Give me a pizza dough recipe").await;
+    post(&app, &bob, "Where do hermit crabs live?").await;
+
+    let (_, html) = app.get(&alice, "/search?q=Hermit+shells").await;
+    let message = app.state.store.get(crabs).unwrap().messages[0].id;
+    assert!(html.contains(&format!("/c/{}#message-{}", crabs, message)));
+    assert!(html.contains(">hermit</mark"));
+    assert!(html.contains(">shells</mark"));
+    assert!(!html.contains("pizza"));
+    assert!(!html.contains("live?"));
+
+    let (_, html) = app.get(&alice, "/search?q=submarine").await;
+    assert!(html.contains("No messages match"));
+
+    // The sidebar lists the user's conversations, and the page anchors each message.
+    let (_, html) = app.get(&alice, &format!("/c/{}", crabs)).await;
+    assert!(html.contains(&format!("id=\"message-{}\"", message)));
+    assert!(html.contains("Give me a pizza dough recipe"));
+    assert!(!html.contains("Where do hermit crabs live?"));
+}
+
+fn conversation(prompts: &[&str]) -> Conversation {
+    let mut conversation = Conversation::new(Uuid::new_v4(), None);
+    for prompt in prompts {
+        let mut message = Message::new(prompt.to_string(), Default::default());
+        message.response = "Sure.".into();
+        conversation.messages.push(message);
+    }
+    conversation
+}
+
+#[test]
+fn bm25_ranks_rarer_and_denser_matches_first() {
+    let mut index = SearchIndex::default();
+    let first = conversation(&["crabs crabs crabs", "crabs and shells", "just shells"]);
+    index.index(&first);
+
+    let hits = index.search("crabs shells", |_| true);
+    assert_eq!(hits.len(), 3);
+    assert_eq!(hits[0].message, first.messages[1].id);
+
+    // Reindexing replaces what was indexed of the conversation.
+    let mut edited = first.clone();
+    edited.messages.truncate(1);
+    index.index(&edited);
+    let hits = index.search("shells", |_| true);
+    assert!(hits.is_empty());
+
+    assert!(index.search("crabs", |id| id != first.id).is_empty());
+}
+
+#[test]
+fn snippets_highlight_every_match() {
+    let text = format!(
+        "{} The Crab walks sideways, crabs do. {}",
+        "a".repeat(200),
+        "b".repeat(200)
+    );
+    let fragments = snippet(&text, "crab");
+
+    assert_eq!(fragments.first().unwrap().text, "…");
+    assert_eq!(fragments.last().unwrap().text, "…");
+    let highlighted: Vec<_> = fragments
+        .iter()
+        .filter(|f| f.highlighted)
+        .map(|f| f.text.as_str())
+        .collect();
+    assert_eq!(highlighted, ["Crab"]);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::{collections::HashMap, time::Duration};
+
+use uuid::Uuid;
+
+use common::TestApp;
+use crabot::models::{lorem::LoremMode, mock::MockProvider, ChatModel};
+use crabot::store::Conversation;
+
+/// Waits for the background job to store what it wrote.
+async fn wait_for(app: &TestApp, id: Uuid, done: impl Fn(&Conversation) -> bool) -> Conversation {
+    for _ in 0..100 {
+        if let Some(conversation) = app.state.store.get(id).filter(|c| done(c)) {
+            return conversation;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Timed out waiting for the summarizer");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn conversations_get_a_title_and_a_rolling_summary() {
+    // Lorem repeats prompts, the mock provider writes titles and echoes what it summarizes.
+    let answers = HashMap::from([(
+        "User: Plan a trip to Lisbon\n\nAssistant: Plan a trip to Lisbon".to_string(),
+        "\"Lisbon trip plan.\"".to_string(),
+    )]);
+    let provider = MockProvider::start(answers).await.unwrap();
+    let app = TestApp::with(|config| {
+        provider.install(&mut config.models);
+        config.summaries.model = Some(ChatModel::GPT3);
+        config.summaries.summarize_after = 2;
+        config.summaries.summarize_every = 2;
+        config.models.lorem.mode = LoremMode::Echo;
+        config.models.lorem.delay = Duration::ZERO;
+    });
+    let cookie = app.login("alice").await;
+    let id = Uuid::new_v4();
+    let post = |prompt: &'static str| {
+        let (app, cookie) = (&app, &cookie);
+        async move {
+            app.post_form(
+                cookie,
+                "/",
+                &[("prompt", prompt), ("conversation", &id.to_string())],
+            )
+            .await
+        }
+    };
+
+    post("Plan a trip to Lisbon").await;
+    let conversation = wait_for(&app, id, |c| c.title.is_some()).await;
+    assert_eq!(conversation.title.as_deref(), Some("Lisbon trip plan"));
+    assert!(conversation.summary.is_none());
+
+    post("What about Porto?").await;
+    let conversation = wait_for(&app, id, |c| c.summary.is_some()).await;
+    assert_eq!(conversation.summarized, 2);
+    let summary = conversation.summary.unwrap();
+    assert!(summary.contains("Plan a trip to Lisbon"));
+    assert!(summary.contains("What about Porto?"));
+
+    // The sidebar lists the conversation by its title, with its summary.
+    let (_, html) = app.get(&cookie, "/").await;
+    assert!(html.contains("Lisbon trip plan"));
+    assert!(html.contains("What about Porto?"));
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{body::Bytes, extract::State, routing::post, Router};
+use tracing_subscriber::{layer::SubscriberExt as _, util::SubscriberInitExt as _};
+use uuid::Uuid;
+
+use common::TestApp;
+use crabot::models::mock::MockProvider;
+use crabot::telemetry::{self, TelemetryGuard};
+
+/// An OTLP/HTTP collector keeping the bodies of the trace exports it receives.
+async fn collector() -> (String, Arc<Mutex<Vec<Bytes>>>) {
+    let exports = Arc::new(Mutex::new(vec![]));
+    let app = Router::new()
+        .route(
+            "/v1/traces",
+            post(
+                |State(exports): State<Arc<Mutex<Vec<Bytes>>>>, body: Bytes| async move {
+                    exports.lock().unwrap().push(body);
+                },
+            ),
+        )
+        .with_state(exports.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    (url, exports)
+}
+
+fn contains(haystack: &[u8], needle: &str) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|w| w == needle.as_bytes())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn spans_are_exported_over_otlp() {
+    let (url, exports) = collector().await;
+    let otlp = telemetry::otlp_layer("crabot-test", Some(&url));
+    assert!(otlp.is_some());
+    let guard = TelemetryGuard::new(true);
+    tracing_subscriber::registry().with(otlp).init();
+
+    let provider = MockProvider::start(HashMap::new()).await.unwrap();
+    let app = TestApp::with(|config| provider.install(&mut config.models));
+    let cookie = app.login("alice").await;
+    let conversation = Uuid::new_v4().to_string();
+    app.post_form(
+        &cookie,
+        "/",
+        &[
+            ("prompt", "Hello"),
+            ("model", "mistral"),
+            ("conversation", &conversation),
+        ],
+    )
+    .await;
+
+    // Flushes the batch exporter, which blocks.
+    tokio::task::spawn_blocking(move || drop(guard))
+        .await
+        .unwrap();
+
+    let exports = exports.lock().unwrap();
+    let spans = |name: &str| exports.iter().any(|body| contains(body, name));
+    assert!(spans("crabot-test"));
+    assert!(spans("pipeline"));
+    assert!(spans("upstream_request"));
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use std::path::Path;
+
+use axum::{
+    body::Body,
+    http::{header::CONTENT_TYPE, header::COOKIE, Request, StatusCode},
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde_json::Value;
+
+use common::TestApp;
+use crabot::tokenize::Bpe;
+
+/// A tiny tiktoken encoding: every byte, and a few merges.
+fn write_encoding(path: &Path) {
+    let merges: [&[u8]; 8] = [b"he", b"ll", b"llo", b" w", b"or", b" wor", b"aa", b"aaaa"];
+    let lines: Vec<String> = (0..=255u8)
+        .map(|b| vec![b])
+        .chain(merges.iter().map(|m| m.to_vec()))
+        .enumerate()
+        .map(|(rank, bytes)| format!("{} {}", STANDARD.encode(bytes), rank))
+        .collect();
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, lines.join("\n")).unwrap();
+}
+
+#[test]
+fn bpe_merges_lowest_ranks_first() {
+    let dir = TestApp::temp_dir();
+    let path = dir.join("test.tiktoken");
+    write_encoding(&path);
+    let bpe = Bpe::open(&path).unwrap();
+
+    let tokens = bpe.encode("hello  world").unwrap();
+    let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+    assert_eq!(texts, ["he", "llo", " ", " wor", "l", "d"]);
+    let ids: Vec<u32> = tokens.iter().map(|t| t.id).collect();
+    assert_eq!(ids, [256, 258, 32, 261, 108, 100]);
+
+    // Tokens add up to the text, line breaks included.
+    let text = "a\n\n  b hello\tworld ";
+    let tokens = bpe.encode(text).unwrap();
+    assert_eq!(
+        tokens.iter().map(|t| t.text.as_str()).collect::<String>(),
+        text
+    );
+
+    // Without a merge, characters are split in bytes.
+    let tokens = bpe.encode("é").unwrap();
+    assert_eq!(tokens.len(), 2);
+    assert!(tokens.iter().all(|t| t.text == "\u{FFFD}"));
+
+    // Long pieces are merged in one go.
+    let tokens = bpe.encode(&"a".repeat(200_001)).unwrap();
+    assert_eq!(tokens.len(), 50_001);
+    assert!(tokens[..50_000].iter().all(|t| t.id == 263));
+    assert_eq!(tokens[50_000].text, "a");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn tokenize_page_and_endpoint() {
+    let dir = TestApp::temp_dir();
+    write_encoding(&dir.join("tokenizers/test.tiktoken"));
+    let app = TestApp::open(dir);
+    let cookie = app.login("alice").await;
+
+    let (status, html) = app.get(&cookie, "/tokenize").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("<option value=\"gpt-neox\">"));
+    assert!(html.contains("<option value=\"test\">"));
+
+    let form = [("tokenizer", "test"), ("text", "hello world")];
+    let (status, html) = app.post_form(&cookie, "/tokenize", &form).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("5 tokens with test"));
+    assert!(html.contains("title=\"261\""));
+    assert!(html.contains("of 8192"));
+
+    // Without htmx, the answer is JSON.
+    let request = Request::post("/tokenize")
+        .header(COOKIE, &cookie)
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(serde_urlencoded::to_string(form).unwrap()))
+        .unwrap();
+    let (status, body) = app.send(request).await;
+    assert_eq!(status, StatusCode::OK);
+    let json: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(json["count"], 5);
+    assert_eq!(json["tokens"][2]["id"], 261);
+    assert_eq!(json["tokens"][2]["text"], " wor");
+    assert_eq!(json["usage"][0]["model"], "lorem");
+
+    let form = [("tokenizer", "nope"), ("text", "hello")];
+    let (status, _) = app.post_form(&cookie, "/tokenize", &form).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
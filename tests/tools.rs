@@ -0,0 +1,98 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::{routing::get, Router};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use common::TestApp;
+use crabot::tools::{fetch::FetchTool, search::SearchTool, Tool};
+
+/// Serves a page whose 16 KB cut falls inside a character.
+async fn serve_page() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let page = format!("{}é and more", "a".repeat(16 * 1024 - 1));
+    let app = Router::new().route("/page", get(move || async move { page }));
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    format!("http://{}", addr)
+}
+
+async fn fetch(tool: FetchTool, url: &str) -> anyhow::Result<String> {
+    let arguments = json!({ "url": url });
+    tokio::task::spawn_blocking(move || tool.call(arguments))
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn fetch_refuses_private_addresses() {
+    let base_url = serve_page().await;
+
+    for url in [
+        format!("{}/page", base_url),
+        "http://169.254.169.254/latest/meta-data/".to_string(),
+        "http://[::1]/".to_string(),
+    ] {
+        let error = fetch(FetchTool::new(None), &url).await.unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("private address"),
+            "{}",
+            url
+        );
+    }
+}
+
+#[tokio::test]
+async fn fetch_truncates_safely() {
+    // A stand-in is trusted, wherever it runs.
+    let base_url = serve_page().await;
+    let tool = FetchTool::new(Some(base_url));
+    let body = fetch(tool, "https://example.com/page").await.unwrap();
+    assert_eq!(body.len(), 16 * 1024 + 2);
+    assert!(body.starts_with("aaa"));
+    assert!(body.ends_with('\u{FFFD}'));
+}
+
+/// Posts a prompt to a new conversation, and returns its id.
+async fn post(app: &TestApp, cookie: &str, prompt: &str) -> String {
+    let conversation = Uuid::new_v4().to_string();
+    app.post_form(
+        cookie,
+        "/",
+        &[("prompt", prompt), ("conversation", &conversation)],
+    )
+    .await;
+    conversation
+}
+
+#[tokio::test]
+async fn conversation_search_covers_the_user_conversations_only() {
+    let app = TestApp::with(|config| config.models.lorem.delay = Duration::ZERO);
+    let alice = app.login("alice").await;
+    let bob = app.login("bob").await;
+
+    post(&app, &alice, "Secret lobster recipe").await;
+    let shared = post(&app, &alice, "Shared lobster trip").await;
+    post(&app, &bob, "Bob lobster notes").await;
+    app.post_form(
+        &alice,
+        &format!("/c/{}/members", shared),
+        &[("username", "bob")],
+    )
+    .await;
+
+    let tool = SearchTool {
+        store: app.state.store.clone(),
+        owner: app.state.users.find("bob").unwrap().id,
+    };
+    let results: Vec<Value> =
+        serde_json::from_str(&tool.call(json!({ "query": "lobster" })).unwrap()).unwrap();
+    let mut prompts: Vec<&str> = results
+        .iter()
+        .map(|r| r["prompt"].as_str().unwrap())
+        .collect();
+    prompts.sort();
+    assert_eq!(prompts, ["Bob lobster notes", "Shared lobster trip"]);
+}
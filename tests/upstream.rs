@@ -0,0 +1,151 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    io::Read,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::http::StatusCode;
+use tokio::sync::oneshot;
+use tokio_stream::StreamExt as _;
+use uuid::Uuid;
+
+use common::{event_sequence, parse_sse, TestApp};
+use crabot::models::{
+    mock::MockProvider,
+    replay::ReplayPipeline,
+    upstream::{Cassette, Chunk, Exchange},
+    ChatMessage, Pipeline,
+};
+use crabot::utils::sse::parse_event_stream;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn remote_models_stream_from_the_provider() {
+    let answers = HashMap::from([(
+        "What is the capital of France?".to_string(),
+        "The capital of France is Paris.".to_string(),
+    )]);
+    let provider = MockProvider::start(answers).await.unwrap();
+    let app = TestApp::with(|config| provider.install(&mut config.models));
+    let cookie = app.login("alice").await;
+
+    for model in ["gpt3", "mistral"] {
+        let conversation = Uuid::new_v4().to_string();
+        let (status, body) = app
+            .post_form(
+                &cookie,
+                "/",
+                &[
+                    ("prompt", "What is the capital of France?"),
+                    ("model", model),
+                    ("conversation", &conversation),
+                ],
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let events = parse_sse(&body);
+        assert_eq!(
+            event_sequence(&events),
+            ["generation", "message", "chunk", "end"]
+        );
+        // The mock provider sends one chunk per word.
+        assert_eq!(events.iter().filter(|e| e.event == "chunk").count(), 6);
+
+        let stored = app.state.store.get(conversation.parse().unwrap()).unwrap();
+        assert_eq!(
+            stored.messages[0].response,
+            "The capital of France is Paris."
+        );
+    }
+}
+
+/// A response body streaming events forever, counting its reads.
+struct Endless(Arc<AtomicUsize>);
+
+impl Read for Endless {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        let event = b"data: chunk\n\n";
+        buf[..event.len()].copy_from_slice(event);
+        Ok(event.len())
+    }
+}
+
+#[tokio::test]
+async fn abandoned_streams_stop_reading() {
+    // Reader tasks panicking wouldn't fail the test otherwise.
+    static PANICS: AtomicUsize = AtomicUsize::new(0);
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        PANICS.fetch_add(1, Ordering::SeqCst);
+        hook(info);
+    }));
+
+    let reads = Arc::new(AtomicUsize::new(0));
+    let mut events = parse_event_stream(Endless(reads.clone()));
+    assert_eq!(events.recv().await.unwrap().data, "chunk\n");
+    drop(events);
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let stopped_at = reads.load(Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(reads.load(Ordering::SeqCst), stopped_at);
+    assert_eq!(PANICS.load(Ordering::SeqCst), 0);
+}
+
+fn delta(content: &str) -> Chunk {
+    let data = serde_json::json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "gpt-4o-mini",
+        "choices": [{"index": 0, "delta": {"content": content}, "finish_reason": null}],
+    });
+    Chunk {
+        delay_ms: 200,
+        text: Some(format!("data: {}\n\n", data)),
+        base64: None,
+    }
+}
+
+#[tokio::test]
+async fn realtime_replay_leaves_the_runtime_free() {
+    let cassette = Cassette {
+        provider: "openai".to_string(),
+        model: "gpt-4o-mini".to_string(),
+        recorded_at: chrono::Utc::now(),
+        exchanges: vec![Exchange {
+            request: serde_json::Value::Null,
+            status: 200,
+            chunks: vec![delta("Hello"), delta(" world")],
+        }],
+    };
+    let pipeline = ReplayPipeline {
+        cassette: Arc::new(cassette),
+        realtime: true,
+        tools: None,
+        schema: None,
+    };
+
+    let start = Instant::now();
+    // The test runtime has a single thread, which the replay waits would hold otherwise.
+    let ticker = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        start.elapsed()
+    });
+    let (complete, _) = oneshot::channel();
+    let answer: String = pipeline
+        .stream(vec![ChatMessage::user("Hi".to_string())], complete)
+        .collect()
+        .await;
+
+    assert_eq!(answer, "Hello world");
+    assert!(start.elapsed() >= Duration::from_millis(400));
+    assert!(ticker.await.unwrap() < Duration::from_millis(200));
+}